@@ -683,7 +683,7 @@ where
         use core::sync::atomic::AtomicBool;
         for i in 0..nlogs {
             let stuck = stuck.clone();
-            let func = move |rid: &[AtomicBool; MAX_REPLICAS_PER_LOG], idx: usize| {
+            let func = move |rid: &[AtomicBool], idx: usize| {
                 for replia in 0..MAX_REPLICAS_PER_LOG {
                     if rid[replia].compare_exchange_weak(
                         true,
@@ -17,12 +17,11 @@ pub use crate::log::DEFAULT_LOG_BYTES;
 pub use crate::log::MAX_REPLICAS_PER_LOG;
 
 pub use crate::log::LogToken;
-pub use crate::log::GC_FROM_HEAD;
 pub use crate::log::WARN_THRESHOLD;
 
 /// Callback function which indicates which replicas need to be advanced for GC
-/// to make progress.
-type CallbackFn = dyn FnMut(&[AtomicBool; MAX_REPLICAS_PER_LOG], usize);
+/// to make progress. The slice has one flag per replica the log supports.
+type CallbackFn = dyn FnMut(&[AtomicBool], usize);
 
 /// The meta-data we need to store in the log entries to make scan (multi-log)
 /// operations works.
@@ -67,24 +66,33 @@ pub struct LogMetaData {
 
     /// Use this array in GC callback function to notify other replicas to make progress.
     /// Assumes that the callback handler clears the replica-ids which need to do GC.
-    dormant_replicas: [AtomicBool; MAX_REPLICAS_PER_LOG],
+    dormant_replicas: Box<[AtomicBool]>,
 }
 
 impl LogMetaData {
+    /// Creates the meta-data for log `idx` that supports up to
+    /// [`MAX_REPLICAS_PER_LOG`] replicas.
     pub fn new(idx: usize) -> Self {
-        #[allow(clippy::declare_interior_mutable_const)]
-        const DORMANT_DEFAULT: AtomicBool = AtomicBool::new(false);
+        Self::with_replicas(idx, MAX_REPLICAS_PER_LOG)
+    }
 
+    /// Creates the meta-data for log `idx` that supports up to `max_replicas`
+    /// replicas, for logs created with the same `max_replicas` (see
+    /// [`crate::log::Log::new_with_replicas`]).
+    pub fn with_replicas(idx: usize, max_replicas: usize) -> Self {
         Self {
             idx,
             scanlock: CachePadded::new(AtomicUsize::new(0)),
-            gc: UnsafeCell::new(Box::new(
-                |_rid: &[AtomicBool; MAX_REPLICAS_PER_LOG], _lid: usize| {},
-            )),
+            gc: UnsafeCell::new(Box::new(|_rid: &[AtomicBool], _lid: usize| {})),
             notify_replicas: CachePadded::new(AtomicBool::new(true)),
-            dormant_replicas: [DORMANT_DEFAULT; MAX_REPLICAS_PER_LOG],
+            dormant_replicas: (0..max_replicas).map(|_| AtomicBool::new(false)).collect(),
         }
     }
+
+    /// Returns the number of replicas the meta-data supports.
+    pub(crate) fn max_replicas(&self) -> usize {
+        self.dormant_replicas.len()
+    }
 }
 
 impl Default for LogMetaData {
@@ -172,7 +180,7 @@ where
             // try again. The replica that reserved entry (h + self.size - GC_FROM_HEAD)
             // is currently trying to advance the head of the log. Keep refreshing the
             // replica against the log to make sure that it isn't deadlocking GC.
-            if tail > head + self.slog.len() - GC_FROM_HEAD {
+            if tail > head + self.slog.len() - self.gc_from_head {
                 if waitgc % WARN_THRESHOLD == 0 {
                     warn!(
                         "append(ops.len()={}, {}) takes too many iterations ({}) waiting for gc...",
@@ -182,6 +190,7 @@ where
                     );
                 }
                 waitgc += 1;
+                let _gc_wait = self.stats.gc_wait.start();
                 self.exec(idx, &mut s);
                 continue;
             }
@@ -189,7 +198,7 @@ where
             // If on adding in the above entries there would be fewer than `GC_FROM_HEAD`
             // entries left on the log, then we need to advance the head of the log.
             let mut advance = false;
-            if tail + nops > head + self.slog.len() - GC_FROM_HEAD {
+            if tail + nops > head + self.slog.len() - self.gc_from_head {
                 advance = true
             };
 
@@ -239,7 +248,7 @@ where
         // try again. The replica that reserved entry (h + self.size - GC_FROM_HEAD)
         // is currently trying to advance the head of the log. Keep refreshing the
         // replica against the log to make sure that it isn't deadlocking GC.
-        if tail > head + self.slog.len() - GC_FROM_HEAD {
+        if tail > head + self.slog.len() - self.gc_from_head {
             self.exec(idx, &mut s);
             return Err(0);
         }
@@ -247,7 +256,7 @@ where
         // If on adding in the above entries there would be fewer than `GC_FROM_HEAD`
         // entries left on the log, then we need to advance the head of the log.
         let mut advance = false;
-        if tail + nops > head + self.slog.len() - GC_FROM_HEAD {
+        if tail + nops > head + self.slog.len() - self.gc_from_head {
             advance = true;
         }
 
@@ -464,6 +473,7 @@ where
                     warn!("Spending a long time in `advance_head`, are we starving?");
                 }
                 iteration += 1;
+                let _gc_wait = self.stats.gc_wait.start();
                 self.exec(rid, &mut s);
                 continue;
             }
@@ -477,7 +487,7 @@ where
             // Make sure that we freed up enough space so that threads waiting for
             // GC in append can make progress. Otherwise, try to make progress again.
             // If we're making progress again, then try consuming entries on the log.
-            if f < min_local_tail + self.slog.len() - GC_FROM_HEAD {
+            if f < min_local_tail + self.slog.len() - self.gc_from_head {
                 return;
             } else {
                 self.exec(rid, &mut s);
@@ -508,15 +518,12 @@ where
     /// let mut l = Log::<Operation>::new_with_bytes(1 * 1024 * 1024, LogMetaData::new(1));
     ///
     /// // Update the callback function for the log.
-    /// let callback_func = |rid: &[AtomicBool; 16], idx: usize| {
+    /// let callback_func = |rid: &[AtomicBool], idx: usize| {
     ///     // Take action on log `idx` and replicas in `rid`.
     /// };
     /// l.update_closure(callback_func)
     /// ```
-    pub fn update_closure(
-        &mut self,
-        gc: impl FnMut(&[AtomicBool; MAX_REPLICAS_PER_LOG], usize) + 'static,
-    ) {
+    pub fn update_closure(&mut self, gc: impl FnMut(&[AtomicBool], usize) + 'static) {
         unsafe { *self.metadata.gc.get() = Box::new(gc) };
     }
 }
@@ -527,7 +534,7 @@ mod tests {
     extern crate std;
 
     use super::*;
-    use crate::log::{Entry, LogToken};
+    use crate::log::{Entry, LogToken, GC_FROM_HEAD};
     use std::sync::Arc;
 
     // Define operations along with their arguments that go onto the log.
@@ -581,6 +588,23 @@ mod tests {
         }
     }
 
+    // Tests that the meta-data can be sized for more than
+    // `MAX_REPLICAS_PER_LOG` replicas.
+    #[test]
+    fn test_log_metadata_replicas() {
+        let n = 2 * MAX_REPLICAS_PER_LOG;
+        let mut l =
+            Log::<Operation>::new_with_replicas(1024 * 1024, n, LogMetaData::with_replicas(1, n));
+        assert_eq!(l.metadata.max_replicas(), n);
+        assert_eq!(LogMetaData::new(1).max_replicas(), MAX_REPLICAS_PER_LOG);
+        for _ in 0..n {
+            assert!(l.register().is_some());
+        }
+
+        l.update_closure(move |rid: &[AtomicBool], _idx: usize| assert_eq!(rid.len(), n));
+        unsafe { (*l.metadata.gc.get())(&l.metadata.dormant_replicas, 1) };
+    }
+
     // Test that we can correctly append an entry into the log.
     #[test]
     fn test_log_append() {
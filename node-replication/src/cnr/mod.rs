@@ -102,11 +102,26 @@ mod replica;
 pub use crate::log::MAX_REPLICAS_PER_LOG;
 pub use crate::replica::ReplicaToken;
 pub use log::{EntryMetaData, Log, LogMetaData};
-pub use replica::{Replica, MAX_THREADS_PER_REPLICA};
+pub use replica::{ConfigError, Replica, ReplicaConfig, MAX_THREADS_PER_REPLICA};
 
 use alloc::vec::Vec;
 use core::fmt::Debug;
 
+#[cfg(feature = "stats")]
+use crate::stats::{CombinerStats, LogStats};
+
+/// A snapshot of the runtime statistics of a [`Replica`], taken with
+/// [`Replica::stats`].
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Statistics of the replica.
+    pub replica: CombinerStats,
+    /// Statistics of the logs the replica is registered with, in the order
+    /// the logs were passed to [`Replica::new`].
+    pub logs: Vec<LogStats>,
+}
+
 /// Every data structure must implement [`LogMapper`] trait for
 /// [`Dispatch::ReadOperation`] and [`Dispatch::WriteOperation`].
 ///
@@ -155,4 +170,14 @@ pub trait Dispatch {
     /// Method on the data structure that allows a write operation to be
     /// executed against it.
     fn dispatch_mut(&self, op: Self::WriteOperation) -> Self::Response;
+
+    /// Applies a write operation whose response is not needed, e.g., when a
+    /// replica replays an operation that another replica appended to a log.
+    /// Override this if building the response is expensive.
+    ///
+    /// The default implementation calls `dispatch_mut()` and drops the
+    /// response.
+    fn apply_mut(&self, op: Self::WriteOperation) {
+        self.dispatch_mut(op);
+    }
 }
@@ -8,6 +8,7 @@ use core::hint::spin_loop;
 use core::intrinsics::unlikely;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -18,9 +19,14 @@ use super::log::Log;
 use super::Dispatch;
 use super::LogMapper;
 
+#[cfg(feature = "stats")]
+use super::Stats;
 use crate::log::LogToken;
 use crate::replica::ReplicaToken;
 pub use crate::replica::MAX_THREADS_PER_REPLICA;
+pub use crate::replica::{ConfigError, ReplicaConfig};
+use crate::stats::CombinerCounters;
+use crate::wait;
 
 /// Type that has meta-data about either scan or write op while it's in the log.
 type OperationState<D> = (<D as Dispatch>::WriteOperation, usize, bool);
@@ -46,7 +52,7 @@ where
     combiner: CachePadded<AtomicUsize>,
 
     /// Number of pending operations for each thread per log.
-    pending: [CachePadded<AtomicBool>; MAX_THREADS_PER_REPLICA],
+    pending: Box<[CachePadded<AtomicBool>]>,
 
     /// A buffer of operations for flat combining. The combiner stages operations in
     /// here and then batch appends them into the shared log. This helps amortize
@@ -63,31 +69,21 @@ impl<D> LogState<D>
 where
     D: Sized + Dispatch + Sync,
 {
-    fn new(log: Arc<Log<<D as Dispatch>::WriteOperation>>) -> LogState<D> {
-        #[allow(clippy::declare_interior_mutable_const)]
-        const PENDING_DEFAULT: CachePadded<AtomicBool> = CachePadded::new(AtomicBool::new(false));
-
+    fn new(log: Arc<Log<<D as Dispatch>::WriteOperation>>, config: ReplicaConfig) -> LogState<D> {
+        assert!(
+            log.metadata.max_replicas() >= log.max_replicas(),
+            "LogMetaData supports fewer replicas than the log (see LogMetaData::with_replicas)"
+        );
         let idx = log.register().unwrap();
         LogState {
             slog: log,
             idx,
             combiner: CachePadded::new(AtomicUsize::new(0)),
-            pending: [PENDING_DEFAULT; MAX_THREADS_PER_REPLICA],
-            buffer:
-                CachePadded::new(
-                    RefCell::new(
-                        Vec::with_capacity(
-                            MAX_THREADS_PER_REPLICA
-                                * Context::<
-                                    <D as Dispatch>::WriteOperation,
-                                    <D as Dispatch>::Response,
-                                >::batch_size(),
-                        ),
-                    ),
-                ),
-            scan_buffer: CachePadded::new(RefCell::new(Vec::with_capacity(
-                MAX_THREADS_PER_REPLICA,
-            ))),
+            pending: (0..config.max_threads)
+                .map(|_| CachePadded::new(AtomicBool::new(false)))
+                .collect(),
+            buffer: CachePadded::new(RefCell::new(Vec::with_capacity(config.max_batch()))),
+            scan_buffer: CachePadded::new(RefCell::new(Vec::with_capacity(config.max_threads))),
         }
     }
 }
@@ -110,6 +106,10 @@ where
     /// Idx that will be handed out to the next thread that registers with the replica.
     next: CachePadded<AtomicUsize>,
 
+    /// Marks idx (below `next`) of threads that called [`Replica::unregister`].
+    /// Those are free and will be handed out again by [`Replica::register`].
+    vacant: Vec<AtomicBool>,
+
     /// The underlying replicated data structure. Shared between threads registered
     /// with this replica. Each replica maintains its own copy of the data structure.
     data: CachePadded<D>,
@@ -117,7 +117,8 @@ where
     /// List of per-thread contexts. Threads buffer write operations in here when they
     /// cannot perform flat combining (because another thread might be doing so).
     ///
-    /// The vector is initialized with `MAX_THREADS_PER_REPLICA` elements.
+    /// The vector is initialized with [`ReplicaConfig::max_threads`] elements
+    /// (`MAX_THREADS_PER_REPLICA` by default).
     contexts: Vec<CachePadded<Context<<D as Dispatch>::WriteOperation, <D as Dispatch>::Response>>>,
 
     /// It is used to store the log offsets in various logs for scan operations.
@@ -128,6 +129,9 @@ where
 
     /// An instance of per log state maintained by each replica.
     logstate: Vec<CachePadded<LogState<D>>>,
+
+    /// Runtime statistics of the replica (see [`crate::stats`]).
+    stats: CombinerCounters,
 }
 
 /// The Replica is Sync. Member variables are protected by a CAS on `combiner`.
@@ -243,6 +247,31 @@ where
     pub fn with_data(
         logs: Vec<Arc<Log<<D as Dispatch>::WriteOperation>>>,
         d: D,
+    ) -> Arc<Replica<D>> {
+        Replica::allocate(logs, ReplicaConfig::default(), d)
+    }
+
+    /// Similar to [`Replica<D>::with_data`], but the number of threads and
+    /// the depth of their contexts is given by `config` (instead of
+    /// [`MAX_THREADS_PER_REPLICA`] and [`crate::context::MAX_PENDING_OPS`]).
+    ///
+    /// All `logs` must have been created with the same configuration (see
+    /// [`crate::log::Log::new_with_config`]). Fails if `config` is unusable
+    /// (see [`ReplicaConfig::validate`]).
+    pub fn with_config(
+        logs: Vec<Arc<Log<<D as Dispatch>::WriteOperation>>>,
+        config: ReplicaConfig,
+        d: D,
+    ) -> Result<Arc<Replica<D>>, ConfigError> {
+        config.validate()?;
+        Ok(Replica::allocate(logs, config, d))
+    }
+
+    /// Creates a replica for a valid `config`.
+    fn allocate(
+        logs: Vec<Arc<Log<<D as Dispatch>::WriteOperation>>>,
+        config: ReplicaConfig,
+        d: D,
     ) -> Arc<Replica<D>> {
         use core::mem::MaybeUninit;
 
@@ -255,20 +284,23 @@ where
 
             uninit_ptr.write(Replica {
                 next: CachePadded::new(AtomicUsize::new(1)),
+                vacant: Vec::with_capacity(config.max_threads),
                 data: CachePadded::new(d),
                 logstate: Vec::with_capacity(logs.len()),
-                contexts: Vec::with_capacity(MAX_THREADS_PER_REPLICA),
-                offsets: Vec::with_capacity(MAX_THREADS_PER_REPLICA),
-                hash: Vec::with_capacity(MAX_THREADS_PER_REPLICA),
+                contexts: Vec::with_capacity(config.max_threads),
+                offsets: Vec::with_capacity(config.max_threads),
+                hash: Vec::with_capacity(config.max_threads),
+                stats: Default::default(),
             });
 
             let mut replica = uninit_replica.assume_init();
-            // Add `MAX_THREADS_PER_REPLICA` contexts
-            for idx in 0..MAX_THREADS_PER_REPLICA {
+            // Add `max_threads` contexts
+            for idx in 0..config.max_threads {
                 let replica_mut = Arc::get_mut(&mut replica).unwrap();
                 replica_mut
                     .contexts
-                    .push(CachePadded::new(Context::new(idx + 1)));
+                    .push(CachePadded::new(Context::new(idx + 1, config.pending_ops)));
+                replica_mut.vacant.push(AtomicBool::new(false));
                 replica_mut
                     .offsets
                     .push(RefCell::new(Vec::with_capacity(logs.len())));
@@ -284,7 +316,7 @@ where
                 Arc::get_mut(&mut replica)
                     .unwrap()
                     .logstate
-                    .push(CachePadded::new(LogState::new(log.clone())));
+                    .push(CachePadded::new(LogState::new(log.clone(), config)));
             }
 
             replica
@@ -362,11 +394,22 @@ where
     /// let idx = replica.register().expect("Failed to register with replica.");
     /// ```
     pub fn register(&self) -> Option<ReplicaToken> {
+        // Prefer the idx of a thread that unregistered earlier.
+        let next = core::cmp::min(self.next.load(Ordering::SeqCst), self.contexts.len() + 1);
+        for idx in 1..next {
+            if self.vacant[idx - 1]
+                .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return Some(ReplicaToken(idx, 0));
+            }
+        }
+
         // Loop until we either run out of identifiers or we manage to increment `next`.
         loop {
             let idx = self.next.load(Ordering::SeqCst);
 
-            if idx > MAX_THREADS_PER_REPLICA {
+            if idx > self.contexts.len() {
                 return None;
             };
 
@@ -378,8 +421,100 @@ where
                 continue;
             };
 
-            return Some(ReplicaToken(idx));
+            return Some(ReplicaToken(idx, 0));
+        }
+    }
+
+    /// Unregisters a thread from this replica. The idx of the thread will be
+    /// handed out again by subsequent [`Replica::register`] calls.
+    ///
+    /// Before the idx is released, we wait until all operations the thread
+    /// still has in its context are executed and their responses are dropped.
+    /// It is a bug to use `idx` (or a copy of it) after calling this method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use node_replication::cnr::Dispatch;
+    /// use node_replication::cnr::Log;
+    /// use node_replication::cnr::LogMapper;
+    /// use node_replication::cnr::Replica;
+    ///
+    /// use core::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// #[derive(Default)]
+    /// struct Data {
+    ///     junk: AtomicUsize,
+    /// }
+    ///
+    /// #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+    /// pub struct OpWr(pub usize);
+    ///
+    /// impl LogMapper for OpWr {
+    ///     // Only one log used for the example, hence returning 0.
+    ///     fn hash(&self, _nlogs:usize, logs: &mut Vec<usize>)
+    ///     {
+    ///         logs.push(0);
+    ///     }
+    /// }
+    ///
+    /// #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+    /// pub struct OpRd(());
+    ///
+    /// impl LogMapper for OpRd {
+    ///     // Only one log used for the example, hence returning 0.
+    ///     fn hash(&self, _nlogs:usize, logs: &mut Vec<usize>)
+    ///     {
+    ///         logs.push(0);
+    ///     }
+    /// }
+    /// #
+    /// # impl Dispatch for Data {
+    /// #     type ReadOperation<'rop> = OpRd;
+    /// #     type WriteOperation = OpWr;
+    /// #     type Response = Option<usize>;
+    /// #
+    /// #     fn dispatch<'rop>(
+    /// #         &self,
+    /// #         _op: Self::ReadOperation<'rop>,
+    /// #     ) -> Self::Response {
+    /// #         Some(self.junk.load(Ordering::Relaxed))
+    /// #     }
+    /// #
+    /// #     fn dispatch_mut(
+    /// #         &self,
+    /// #         op: Self::WriteOperation,
+    /// #     ) -> Self::Response {
+    /// #         self.junk.store(op.0, Ordering::Relaxed);
+    /// #         None
+    /// #     }
+    /// # }
+    ///
+    /// let log = Arc::new(Log::<<Data as Dispatch>::WriteOperation>::default());
+    /// let replica = Replica::<Data>::new(vec![log]);
+    /// let idx = replica.register().expect("Failed to register with replica.");
+    /// replica.unregister(idx);
+    ///
+    /// // The idx of the unregistered thread is re-used.
+    /// assert_eq!(replica.register(), Some(idx));
+    /// ```
+    pub fn unregister(&self, idx: ReplicaToken) {
+        let ctxt = &self.contexts[idx.0 - 1];
+        let mut iteration = 0;
+        while ctxt.has_outstanding() {
+            if ctxt.res().is_none() {
+                // We don't know which logs the outstanding operations map to.
+                for hashidx in 0..self.logstate.len() {
+                    self.try_combine(idx.0, hashidx);
+                }
+                wait::wait(iteration);
+                iteration += 1;
+            }
         }
+
+        self.vacant[idx.0 - 1].store(true, Ordering::SeqCst);
     }
 
     /// Executes an mutable operation against this replica and returns a response.
@@ -596,9 +731,11 @@ where
                         let depends_on = depends_on.as_ref().unwrap();
                         self.handle_scan_op(o, thread_id, *logidx, rid, tid, is_read_op, depends_on)
                     } else {
-                        let resp = self.data.dispatch_mut(o);
                         if rid == self.logstate[*logidx].idx.0 {
+                            let resp = self.data.dispatch_mut(o);
                             self.contexts[tid - 1].enqueue_resp(resp);
+                        } else {
+                            self.data.apply_mut(o);
                         }
                         true
                     }
@@ -873,7 +1010,7 @@ where
         // Use an idx greater than the maximum that can be allocated.
         while self.logstate[0].combiner.compare_exchange_weak(
             0,
-            MAX_THREADS_PER_REPLICA + 2,
+            self.contexts.len() + 2,
             Ordering::Acquire,
             Ordering::Acquire,
         ) != Ok(0)
@@ -888,7 +1025,7 @@ where
                      _is_read_op,
                      _depends_on|
          -> bool {
-            self.data.dispatch_mut(o);
+            self.data.apply_mut(o);
             true
         };
 
@@ -932,6 +1069,16 @@ where
         }
     }
 
+    /// Returns a snapshot of the runtime statistics of the replica and the
+    /// logs it is registered with.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        Stats {
+            replica: self.stats.snapshot(),
+            logs: self.logstate.iter().map(|ls| ls.slog.stats()).collect(),
+        }
+    }
+
     /// Enqueues an operation inside a thread local context. Returns a boolean
     /// indicating whether the operation was enqueued (true) or not (false).
     #[inline(always)]
@@ -1005,6 +1152,11 @@ where
 
         // Collect operations from each thread registered with this replica.
         for tid in 1..next {
+            // Skip idx of threads that unregistered.
+            if self.vacant[tid - 1].load(Ordering::Relaxed) {
+                continue;
+            }
+
             if pending[tid - 1].compare_exchange_weak(
                 true,
                 false,
@@ -1027,6 +1179,8 @@ where
             }
         }
 
+        self.stats.combined(buffer.len() + scan_buffer.len());
+
         // Append all collected operations into the shared log. We pass a closure
         // in here because operations on the log might need to be consumed for GC.
         {
@@ -1039,9 +1193,11 @@ where
              -> bool {
                 match is_scan {
                     false => {
-                        let resp = self.data.dispatch_mut(o);
                         if rid == self.logstate[hashidx].idx.0 {
+                            let resp = self.data.dispatch_mut(o);
                             self.contexts[tid - 1].enqueue_resp(resp);
+                        } else {
+                            self.data.apply_mut(o);
                         }
                         true
                     }
@@ -1073,10 +1229,12 @@ where
                     let depends_on = depends_on.as_ref().unwrap();
                     self.handle_scan_op(o, thread_id, hashidx, rid, tid, is_read_op, depends_on)
                 } else {
-                    let resp = self.data.dispatch_mut(o);
                     if rid == self.logstate[hashidx].idx.0 {
+                        let resp = self.data.dispatch_mut(o);
                         self.contexts[tid - 1].enqueue_resp(resp);
-                    };
+                    } else {
+                        self.data.apply_mut(o);
+                    }
                     true
                 }
             };
@@ -1124,10 +1282,12 @@ where
             }
 
             if self.is_replica_sync_for_logs(1, self.logstate.len(), depends_on) {
-                let resp = self.data.dispatch_mut(op);
                 if issuer_rid == self.logstate[hashidx].idx.0 {
+                    let resp = self.data.dispatch_mut(op);
                     self.contexts[issuer_tid - 1].enqueue_resp(resp);
-                };
+                } else {
+                    self.data.apply_mut(op);
+                }
                 true
             } else {
                 false
@@ -1234,7 +1394,7 @@ mod test {
         assert_eq!(repl.contexts.len(), MAX_THREADS_PER_REPLICA);
         assert_eq!(
             repl.logstate[0].buffer.borrow().capacity(),
-            MAX_THREADS_PER_REPLICA * Context::<u64, Result<u64, ()>>::batch_size()
+            MAX_THREADS_PER_REPLICA * repl.contexts[0].batch_size()
         );
         assert_eq!(repl.data.junk.load(Ordering::Relaxed), 0);
     }
@@ -1247,10 +1407,10 @@ mod test {
             LogMetaData::new(1),
         ));
         let repl = Replica::<Data>::new(vec![slog]);
-        assert_eq!(repl.register(), Some(ReplicaToken(1)));
+        assert_eq!(repl.register(), Some(ReplicaToken(1, 0)));
         assert_eq!(repl.next.load(Ordering::SeqCst), 2);
         repl.next.store(17, Ordering::SeqCst);
-        assert_eq!(repl.register(), Some(ReplicaToken(17)));
+        assert_eq!(repl.register(), Some(ReplicaToken(17, 0)));
         assert_eq!(repl.next.load(Ordering::SeqCst), 18);
     }
 
@@ -1267,6 +1427,29 @@ mod test {
         assert!(repl.register().is_none());
     }
 
+    // Tests that idx of unregistered threads are handed out again, even if
+    // all thread identifiers were used up.
+    #[test]
+    fn test_replica_unregister() {
+        let slog = Arc::new(Log::<<Data as Dispatch>::WriteOperation>::default());
+        let repl = Replica::<Data>::new(vec![slog]);
+
+        let mut tkns = vec![];
+        while let Some(tkn) = repl.register() {
+            tkns.push(tkn);
+        }
+        assert_eq!(tkns.len(), MAX_THREADS_PER_REPLICA);
+
+        repl.make_pending(OpWr(121), tkns[7].0, 0, false, false);
+        repl.unregister(tkns[7]);
+        assert_eq!(repl.data.junk.load(Ordering::Relaxed), 1);
+        assert!(repl.vacant[7].load(Ordering::SeqCst));
+
+        assert_eq!(repl.register(), Some(tkns[7]));
+        assert!(!repl.vacant[7].load(Ordering::SeqCst));
+        assert!(repl.register().is_none());
+    }
+
     // Tests that we can successfully allow operations to go pending on this replica.
     #[test]
     fn test_replica_make_pending() {
@@ -5,15 +5,25 @@
 //!
 //! This allows the combiner to find and flat-combine operations.
 
+use alloc::boxed::Box;
+#[cfg(feature = "async")]
+use alloc::vec::Vec;
 use core::cell::{Cell, UnsafeCell};
 use core::default::Default;
+#[cfg(feature = "async")]
+use core::hint::spin_loop;
 use core::iter::{ExactSizeIterator, Iterator};
+#[cfg(feature = "async")]
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "async")]
+use core::task::Waker;
 
 use crossbeam_utils::CachePadded;
 use static_assertions::const_assert;
 
-/// The maximum number of operations that can be batched inside this context.
+/// The default maximum number of operations that can be batched inside a
+/// context (see [`crate::replica::ReplicaConfig`]).
 #[cfg(not(loom))]
 pub const MAX_PENDING_OPS: usize = 32;
 #[cfg(loom)]
@@ -30,6 +40,10 @@ pub struct PendingOperation<T, R, M> {
     pub(crate) op: UnsafeCell<Option<T>>,
     pub(crate) resp: Cell<Option<R>>,
     pub(crate) meta: UnsafeCell<M>,
+    /// Set if the issuer gave up on the operation before its response arrived
+    /// (see [`Context::abandon_at`]).
+    #[cfg(feature = "async")]
+    pub(crate) abandoned: Cell<bool>,
 }
 
 impl<T, R, M> Default for PendingOperation<T, R, M>
@@ -41,6 +55,8 @@ where
             op: UnsafeCell::new(None),
             resp: Cell::new(None),
             meta: Default::default(),
+            #[cfg(feature = "async")]
+            abandoned: Cell::new(false),
         }
     }
 }
@@ -70,7 +86,9 @@ where
 {
     /// Array that will hold all pending operations to be appended to the shared
     /// log as well as the results obtained on executing them against a replica.
-    pub(crate) batch: [CachePadded<PendingOperation<T, R, M>>; MAX_PENDING_OPS],
+    ///
+    /// The length is a power of two for `index()` to work.
+    pub(crate) batch: Box<[CachePadded<PendingOperation<T, R, M>>]>,
 
     /// Logical array index at which new operations will be enqueued into the
     /// batch. This variable is updated by the thread that owns this context,
@@ -90,6 +108,22 @@ where
     /// Identifies the context number within a replica. It also maps to the
     /// thread-id because the partitioned nature of the contexts in the replica.
     pub _idx: usize,
+
+    /// Number of operations (ahead of all others) whose issuer gave up waiting
+    /// for the response (see [`Context::abandon`]). Their responses are
+    /// dropped once they arrive. Only accessed by the thread that owns this
+    /// context.
+    abandoned: AtomicUsize,
+
+    /// Wakers of the tasks that wait for a response (or the combiner lock) on
+    /// this context, one for every task.
+    #[cfg(feature = "async")]
+    wakers: UnsafeCell<Vec<Waker>>,
+
+    /// Protects `wakers`, the tasks and the combiner might access it
+    /// concurrently.
+    #[cfg(feature = "async")]
+    waker_lock: AtomicBool,
 }
 
 impl<T, R, M> Default for Context<T, R, M>
@@ -100,19 +134,7 @@ where
 {
     /// Default constructor for the context.
     fn default() -> Self {
-        let mut batch: [CachePadded<PendingOperation<T, R, M>>; MAX_PENDING_OPS] =
-            unsafe { ::core::mem::MaybeUninit::zeroed().assume_init() };
-        for elem in &mut batch[..] {
-            *elem = CachePadded::new(Default::default());
-        }
-
-        Context {
-            batch,
-            tail: CachePadded::new(AtomicUsize::new(0)),
-            head: CachePadded::new(AtomicUsize::new(0)),
-            comb: CachePadded::new(AtomicUsize::new(0)),
-            _idx: 0,
-        }
+        Context::new(0, MAX_PENDING_OPS)
     }
 }
 
@@ -122,10 +144,25 @@ where
     R: Sized + Clone,
     M: Default,
 {
-    pub fn new(_idx: usize) -> Self {
-        Self {
+    /// Creates a context that holds up to `batch_size` pending operations
+    /// (must be a power of two).
+    pub fn new(_idx: usize, batch_size: usize) -> Self {
+        debug_assert!(batch_size.is_power_of_two());
+        let batch = (0..batch_size)
+            .map(|_| CachePadded::new(Default::default()))
+            .collect();
+
+        Context {
+            batch,
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            head: CachePadded::new(AtomicUsize::new(0)),
+            comb: CachePadded::new(AtomicUsize::new(0)),
             _idx,
-            ..Default::default()
+            abandoned: AtomicUsize::new(0),
+            #[cfg(feature = "async")]
+            wakers: UnsafeCell::new(Vec::new()),
+            #[cfg(feature = "async")]
+            waker_lock: AtomicBool::new(false),
         }
     }
 }
@@ -141,13 +178,23 @@ where
     /// otherwise.
     #[inline(always)]
     pub(crate) fn enqueue(&self, op: T, meta: M) -> bool {
+        self.try_enqueue(op, meta).is_some()
+    }
+
+    /// Enqueues an operation onto this context's batch of pending operations.
+    ///
+    /// Returns the logical index of the operation in the batch if it was
+    /// successfully enqueued (see also [`Context::res_at`]). None otherwise.
+    #[inline(always)]
+    pub(crate) fn try_enqueue(&self, op: T, meta: M) -> Option<usize> {
+        self.discard_abandoned();
         let t = self.tail.load(Ordering::Acquire);
         let h = self.head.load(Ordering::Relaxed);
 
         // Check if we have space in the batch to hold this operation. If we
-        // don't, then return false to the caller thread.
-        if t - h == MAX_PENDING_OPS {
-            return false;
+        // don't, then return None to the caller thread.
+        if t - h == self.batch.len() {
+            return None;
         }
 
         // Add in the operation to the batch. Once added, update the tail so
@@ -160,7 +207,7 @@ where
         unsafe { *me = meta };
 
         self.tail.store(t + 1, Ordering::Release);
-        true
+        Some(t)
     }
 
     /// Enqueues a batch of responses onto this context. This is invoked by the combiner
@@ -196,6 +243,7 @@ where
     /// Returns a single response if available. Otherwise, returns None.
     #[inline(always)]
     pub(crate) fn res(&self) -> Option<R> {
+        self.discard_abandoned();
         let s = self.head.load(Ordering::Relaxed);
         let f = self.comb.load(Ordering::Relaxed);
 
@@ -212,6 +260,130 @@ where
         self.batch[self.index(s)].resp.take()
     }
 
+    /// Gives up on the oldest operation that has no response yet. The
+    /// operation stays enqueued, but its response is dropped once it arrives.
+    pub(crate) fn abandon(&self) {
+        self.abandoned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drops the available responses of abandoned operations and releases the
+    /// slots at the head whose responses were already taken.
+    #[inline(always)]
+    fn discard_abandoned(&self) {
+        let f = self.comb.load(Ordering::Relaxed);
+        let mut h = self.head.load(Ordering::Relaxed);
+        let mut abandoned = self.abandoned.load(Ordering::Relaxed);
+        while h < f {
+            let e = &self.batch[self.index(h)];
+            #[cfg(feature = "async")]
+            if e.abandoned.replace(false) || unsafe { (*e.resp.as_ptr()).is_none() } {
+                // Abandoned by a future or taken out of order with `res_at`.
+                e.resp.take();
+                h += 1;
+                continue;
+            }
+            if abandoned == 0 {
+                break;
+            }
+
+            e.resp.take();
+            abandoned -= 1;
+            h += 1;
+        }
+        self.head.store(h, Ordering::Relaxed);
+        self.abandoned.store(abandoned, Ordering::Relaxed);
+    }
+
+    /// Returns the response of the operation at logical index `pos` (as
+    /// returned by [`Context::try_enqueue`]) if it is available. Otherwise,
+    /// returns None.
+    ///
+    /// Unlike [`Context::res`], responses can be retrieved out of order. This
+    /// allows multiple in-flight operations (e.g., futures) on one context.
+    #[cfg(feature = "async")]
+    #[inline(always)]
+    pub(crate) fn res_at(&self, pos: usize) -> Option<R> {
+        let f = self.comb.load(Ordering::Relaxed);
+        if pos >= f {
+            return None;
+        }
+
+        let resp = self.batch[self.index(pos)].resp.take();
+        self.discard_abandoned();
+        resp
+    }
+
+    /// Gives up on the operation at logical index `pos` (as returned by
+    /// [`Context::try_enqueue`]). The operation stays enqueued, but its
+    /// response is dropped.
+    #[cfg(feature = "async")]
+    pub(crate) fn abandon_at(&self, pos: usize) {
+        if pos < self.comb.load(Ordering::Relaxed) {
+            self.res_at(pos);
+        } else {
+            // The combiner might be about to respond, the response is dropped
+            // once the slot reaches the head.
+            self.batch[self.index(pos)].abandoned.set(true);
+        }
+    }
+
+    /// Registers `waker` to be woken by [`Context::take_wakers`]. Returns true
+    /// if there was no waker registered before.
+    #[cfg(feature = "async")]
+    pub(crate) fn register_waker(&self, waker: &Waker) -> bool {
+        self.with_wakers(|wakers| {
+            if !wakers.iter().any(|w| w.will_wake(waker)) {
+                wakers.push(waker.clone());
+                wakers.len() == 1
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Removes and returns all registered wakers.
+    #[cfg(feature = "async")]
+    pub(crate) fn take_wakers(&self) -> Vec<Waker> {
+        self.with_wakers(core::mem::take)
+    }
+
+    #[cfg(feature = "async")]
+    fn with_wakers<U>(&self, f: impl FnOnce(&mut Vec<Waker>) -> U) -> U {
+        while self
+            .waker_lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            spin_loop();
+        }
+
+        let r = f(unsafe { &mut *self.wakers.get() });
+        self.waker_lock.store(false, Ordering::Release);
+        r
+    }
+
+    /// Returns true if this context still has operations that were not executed
+    /// yet or responses that were not retrieved (with [`Context::res`]) yet.
+    #[inline(always)]
+    pub(crate) fn has_outstanding(&self) -> bool {
+        self.head.load(Ordering::Relaxed) != self.tail.load(Ordering::Acquire)
+    }
+
+    /// Discards all operations and responses that are still in the context.
+    ///
+    /// Must not be called while a combiner might be using the context.
+    pub(crate) fn clear(&self) {
+        let t = self.tail.load(Ordering::Acquire);
+        for i in self.head.load(Ordering::Relaxed)..t {
+            self.batch[self.index(i)].resp.take();
+            #[cfg(feature = "async")]
+            self.batch[self.index(i)].abandoned.set(false);
+        }
+        self.comb.store(t, Ordering::Relaxed);
+        self.head.store(t, Ordering::Relaxed);
+        self.abandoned.store(0, Ordering::Relaxed);
+    }
+
     /// Adds any pending operations on this context to a passed in buffer.
     /// Returns the the number of such operations that were added in.
     #[inline(always)]
@@ -235,14 +407,14 @@ where
 
     /// Returns the maximum number of operations that will go pending on this context.
     #[inline(always)]
-    pub(crate) fn batch_size() -> usize {
-        MAX_PENDING_OPS
+    pub(crate) fn batch_size(&self) -> usize {
+        self.batch.len()
     }
 
     /// Given a logical address, returns an index into the batch at which it falls.
     #[inline(always)]
     pub(crate) fn index(&self, logical: usize) -> usize {
-        logical & (MAX_PENDING_OPS - 1)
+        logical & (self.batch.len() - 1)
     }
 }
 
@@ -427,6 +599,81 @@ mod test {
         assert_eq!(c.res(), None);
     }
 
+    // Tests that a context reports outstanding operations until all responses
+    // are retrieved.
+    #[test]
+    fn test_context_has_outstanding() {
+        let c = Context::<u64, Result<u64, ()>, ()>::default();
+        assert!(!c.has_outstanding());
+
+        assert!(c.enqueue(121, ()));
+        assert!(c.has_outstanding());
+
+        c.enqueue_resp(Ok(11));
+        assert!(c.has_outstanding());
+
+        assert_eq!(c.res(), Some(Ok(11)));
+        assert!(!c.has_outstanding());
+    }
+
+    // Tests that responses can be retrieved out of order and that the head
+    // only moves past slots whose responses were taken.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_context_res_at() {
+        let c = Context::<u64, Result<u64, ()>, ()>::default();
+        assert_eq!(c.try_enqueue(121, ()), Some(0));
+        assert_eq!(c.try_enqueue(122, ()), Some(1));
+        assert_eq!(c.res_at(0), None);
+
+        c.enqueue_resps(&[Ok(11), Ok(12)]);
+        assert_eq!(c.res_at(1), Some(Ok(12)));
+        assert_eq!(c.head.load(Ordering::Relaxed), 0);
+        assert_eq!(c.res_at(0), Some(Ok(11)));
+        assert_eq!(c.head.load(Ordering::Relaxed), 2);
+        assert!(!c.has_outstanding());
+    }
+
+    // Tests that the response of an operation abandoned out of order is
+    // dropped and that its slot doesn't hold up the head.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_context_abandon_at() {
+        let c = Context::<u64, Result<u64, ()>, ()>::default();
+        assert_eq!(c.try_enqueue(121, ()), Some(0));
+        assert_eq!(c.try_enqueue(122, ()), Some(1));
+        assert_eq!(c.try_enqueue(123, ()), Some(2));
+        c.abandon_at(1);
+
+        c.enqueue_resps(&[Ok(11), Ok(12)]);
+        c.abandon_at(0);
+        assert_eq!(c.head.load(Ordering::Relaxed), 2);
+        assert_eq!(c.res(), None);
+
+        c.enqueue_resp(Ok(13));
+        assert_eq!(c.res(), Some(Ok(13)));
+        assert!(!c.has_outstanding());
+    }
+
+    // Tests that the responses of abandoned operations are dropped and not
+    // returned for the operations that follow them.
+    #[test]
+    fn test_context_abandon() {
+        let c = Context::<u64, Result<u64, ()>, ()>::default();
+        assert!(c.enqueue(121, ()));
+        c.abandon();
+        assert!(c.enqueue(122, ()));
+        assert_eq!(c.res(), None);
+
+        c.enqueue_resp(Ok(11));
+        assert_eq!(c.res(), None);
+        assert_eq!(c.head.load(Ordering::Relaxed), 1);
+
+        c.enqueue_resp(Ok(12));
+        assert_eq!(c.res(), Some(Ok(12)));
+        assert!(!c.has_outstanding());
+    }
+
     // Tests that res panics if the head moves beyond the combiner offset.
     #[test]
     #[should_panic]
@@ -443,7 +690,15 @@ mod test {
     // Tests that batch_size() works correctly.
     #[test]
     fn test_context_batch_size() {
-        assert_eq!(Context::<usize, usize, ()>::batch_size(), MAX_PENDING_OPS);
+        let c = Context::<usize, usize, ()>::default();
+        assert_eq!(c.batch_size(), MAX_PENDING_OPS);
+
+        let c = Context::<usize, usize, ()>::new(1, 4);
+        assert_eq!(c.batch_size(), 4);
+        for i in 0..4 {
+            assert!(c.enqueue(i, ()));
+        }
+        assert!(!c.enqueue(4, ()));
     }
 
     // Tests that index() works correctly.
@@ -19,7 +19,7 @@
     doc_auto_cfg,
     core_intrinsics
 )]
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 extern crate std;
 
 extern crate alloc;
@@ -33,6 +33,11 @@ extern crate logging;
 pub(crate) mod context;
 pub mod log;
 pub mod replica;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(not(feature = "stats"))]
+pub(crate) mod stats;
+pub mod wait;
 
 pub mod cnr;
 pub mod nr;
@@ -20,7 +20,11 @@ pub use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use static_assertions::const_assert;
 
 use crate::context::MAX_PENDING_OPS;
-use crate::replica::MAX_THREADS_PER_REPLICA;
+use crate::replica::{ConfigError, ReplicaConfig, MAX_THREADS_PER_REPLICA};
+use crate::stats::LogCounters;
+#[cfg(feature = "stats")]
+use crate::stats::LogStats;
+use crate::wait::Waiter;
 
 /// A token that identifies a replica for a log.
 ///
@@ -40,12 +44,11 @@ pub struct LogToken(pub usize);
 pub const DEFAULT_LOG_BYTES: usize = 2 * 1024 * 1024;
 const_assert!(DEFAULT_LOG_BYTES.is_power_of_two());
 
-/// The maximum number of replicas that can be registered with the log (plus one
-/// since replica registration starts at 0).
+/// The default maximum number of replicas that can be registered with the log.
 ///
-/// Should be equal or greater than the max. amount of NUMA nodes we expect on
-/// our system Can't make it arbitrarily high as it will lead to more memory
-/// overheads / bigger structs.
+/// Logs that need to support more replicas (e.g., one per NUMA node on
+/// machines with more nodes) can be created with [`Log::new_with_replicas`].
+/// Every replica slot adds a few cache-lines to the log.
 #[cfg(not(loom))]
 pub const MAX_REPLICAS_PER_LOG: usize = 16;
 #[cfg(loom)] // Otherwise uses too much stack space wich crashes in loom...
@@ -59,6 +62,10 @@ pub const MAX_REPLICAS_PER_LOG: usize = 3;
 /// possible append after deciding to perform GC. This largest possible append is when
 /// every thread within a replica has a full batch of writes to be appended to the shared
 /// log.
+///
+/// This is the value for the default [`ReplicaConfig`], logs created with
+/// [`Log::new_with_config`] derive it from their configuration (see
+/// [`Log::gc_from_head`]).
 pub const GC_FROM_HEAD: usize = MAX_PENDING_OPS * MAX_THREADS_PER_REPLICA;
 const_assert!(GC_FROM_HEAD.is_power_of_two());
 
@@ -75,6 +82,9 @@ const_assert!(WARN_THRESHOLD.is_power_of_two());
 ///
 /// `T` is the type on the operation - typically an enum class containing opcodes as well
 /// as arguments. It is required that this type be sized and cloneable.
+///
+/// Operations are stored inline, so the size of `T` determines the size of
+/// every entry (see [`crate::nr::Dispatch::WriteOperation`]).
 #[repr(align(64))]
 pub(crate) struct Entry<T, M>
 where
@@ -92,6 +102,11 @@ where
 
     /// Indicates whether this entry represents a valid operation when on the log.
     pub(crate) alivef: AtomicBool,
+
+    /// Set if applying the operation panicked on one of the replicas. The
+    /// replicas that reach the entry afterwards skip the operation.
+    pub(crate) skipped: AtomicBool,
+
     /// Meta-data associated with this entry.
     ///
     /// The meta-data is generic and depends on which variant (NR/CNR) is used.
@@ -108,6 +123,7 @@ where
             operation: None,
             replica: 0,
             alivef: AtomicBool::new(false),
+            skipped: AtomicBool::new(false),
             metadata: Default::default(),
         }
     }
@@ -153,7 +169,10 @@ where
     /// Required for garbage collection; since replicas make progress over the log
     /// independently, we want to make sure that we don't garbage collect operations
     /// that haven't been executed by all replicas.
-    pub(crate) ltails: [CachePadded<AtomicUsize>; MAX_REPLICAS_PER_LOG],
+    ///
+    /// Has one entry for every replica the log supports (see
+    /// [`Log::max_replicas`]).
+    pub(crate) ltails: Box<[CachePadded<AtomicUsize>]>,
 
     /// Identifier that will be allocated to the next replica that registers with
     /// this Log. Also required to correctly index into ltails above.
@@ -162,7 +181,33 @@ where
     /// Array consisting of local alive masks for each registered replica. Required
     /// because replicas make independent progress over the log, so we need to
     /// track log wrap-arounds for each of them separately.
-    pub(crate) lmasks: [CachePadded<Cell<bool>>; MAX_REPLICAS_PER_LOG],
+    pub(crate) lmasks: Box<[CachePadded<Cell<bool>>]>,
+
+    /// Array counting for each registered replica how many of the operations
+    /// it appended were skipped because they panicked on another replica (see
+    /// [`Entry::skipped`]). Only updated by the replica itself while it
+    /// applies entries.
+    pub(crate) lskipped: Box<[CachePadded<Cell<usize>>]>,
+
+    /// Array marking the slots (in `ltails` and `lmasks`) of replicas that left
+    /// the log with [`Log::unregister()`]. Garbage collection does not wait for
+    /// retired replicas and their slots are handed out again by
+    /// [`Log::register_from()`].
+    pub(crate) retired: Box<[CachePadded<AtomicBool>]>,
+
+    /// Serializes changes to the set of replicas registered with the log.
+    membership: CachePadded<AtomicBool>,
+
+    /// Garbage collection is performed once the tail and the head are these
+    /// many entries apart (see [`GC_FROM_HEAD`]).
+    pub(crate) gc_from_head: usize,
+
+    /// Runtime statistics of the log (see [`crate::stats`]).
+    pub(crate) stats: LogCounters,
+
+    /// How threads of the NR instance the log belongs to wait (see
+    /// [`crate::wait`]).
+    pub(crate) waiter: Waiter,
 
     /// Meta-data used by log implementations.
     pub(crate) metadata: LM,
@@ -230,8 +275,35 @@ where
     /// This method allocates memory for the log upfront. No further allocations
     /// will be performed once this method returns.
     pub fn new_with_entries(num: usize, metadata: LM) -> Self {
+        Log::allocate(
+            num,
+            MAX_REPLICAS_PER_LOG,
+            ReplicaConfig::default(),
+            metadata,
+        )
+    }
+
+    /// Constructs and returns a log of (approximately) `num` entries that
+    /// supports up to `max_replicas` replicas configured with `config`.
+    ///
+    /// Like [`Log::new_with_config`], but sized in entries. Fails if `config`
+    /// is unusable (see [`ReplicaConfig::validate`]).
+    pub fn new_with_entries_and_config(
+        num: usize,
+        max_replicas: usize,
+        config: ReplicaConfig,
+        metadata: LM,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Log::allocate(num, max_replicas, config, metadata))
+    }
+
+    /// Allocates a log of (approximately) `num` entries for a valid `config`.
+    fn allocate(num: usize, max_replicas: usize, config: ReplicaConfig, metadata: LM) -> Self {
+        let gc_from_head = config.max_batch();
+
         // Allocate the log
-        let mut v = Vec::with_capacity(Log::<T, LM, M>::entries_to_log_entries(num));
+        let mut v = Vec::with_capacity(Log::<T, LM, M>::entries_to_log_entries(num, gc_from_head));
         for _ in 0..v.capacity() {
             v.push(Default::default());
         }
@@ -239,41 +311,29 @@ where
         // Convert it to a boxed slice, so we don't accidentially change the size
         let raw = v.into_boxed_slice();
 
-        #[allow(clippy::declare_interior_mutable_const)]
-        const LMASK_DEFAULT: CachePadded<Cell<bool>> = CachePadded::new(Cell::new(true));
-
-        #[cfg(not(loom))]
-        {
-            #[allow(clippy::declare_interior_mutable_const)]
-            const LTAIL_DEFAULT: CachePadded<AtomicUsize> = CachePadded::new(AtomicUsize::new(0));
-
-            Log {
-                slog: raw,
-                head: CachePadded::new(AtomicUsize::new(0usize)),
-                tail: CachePadded::new(AtomicUsize::new(0usize)),
-                ctail: CachePadded::new(AtomicUsize::new(0usize)),
-                ltails: [LTAIL_DEFAULT; MAX_REPLICAS_PER_LOG],
-                next: CachePadded::new(AtomicUsize::new(1usize)),
-                lmasks: [LMASK_DEFAULT; MAX_REPLICAS_PER_LOG],
-                metadata,
-            }
-        }
-        // `AtomicUsize::new` is not const in loom. This code block (including arr
-        // dependency) becomes redundant once
-        // https://github.com/tokio-rs/loom/issues/170 is fixed:
-        #[cfg(loom)]
-        {
-            use arr_macro::arr;
-            Log {
-                slog: raw,
-                head: CachePadded::new(AtomicUsize::new(0usize)),
-                tail: CachePadded::new(AtomicUsize::new(0usize)),
-                ctail: CachePadded::new(AtomicUsize::new(0usize)),
-                ltails: arr![CachePadded::new(AtomicUsize::new(0)); 3], // MAX_REPLICAS_PER_LOG
-                next: CachePadded::new(AtomicUsize::new(1usize)),
-                lmasks: [LMASK_DEFAULT; MAX_REPLICAS_PER_LOG],
-                metadata,
-            }
+        Log {
+            slog: raw,
+            head: CachePadded::new(AtomicUsize::new(0usize)),
+            tail: CachePadded::new(AtomicUsize::new(0usize)),
+            ctail: CachePadded::new(AtomicUsize::new(0usize)),
+            ltails: (0..max_replicas)
+                .map(|_| CachePadded::new(AtomicUsize::new(0)))
+                .collect(),
+            next: CachePadded::new(AtomicUsize::new(1usize)),
+            lmasks: (0..max_replicas)
+                .map(|_| CachePadded::new(Cell::new(true)))
+                .collect(),
+            lskipped: (0..max_replicas)
+                .map(|_| CachePadded::new(Cell::new(0)))
+                .collect(),
+            retired: (0..max_replicas)
+                .map(|_| CachePadded::new(AtomicBool::new(false)))
+                .collect(),
+            membership: CachePadded::new(AtomicBool::new(false)),
+            gc_from_head,
+            stats: Default::default(),
+            waiter: Default::default(),
+            metadata,
         }
     }
 
@@ -305,7 +365,76 @@ where
     /// let l = Log::<Operation, (), ()>::new_with_bytes(1 * 1024 * 1024, ());
     /// ```
     pub fn new_with_bytes(bytes: usize, metadata: LM) -> Self {
-        Log::new_with_entries(Self::bytes_to_log_entries(bytes), metadata)
+        Log::new_with_entries(Self::bytes_to_log_entries(bytes, GC_FROM_HEAD), metadata)
+    }
+
+    /// Constructs and returns a log of (approximately) `bytes` bytes that
+    /// supports up to `max_replicas` replicas (instead of
+    /// [`MAX_REPLICAS_PER_LOG`]).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use node_replication::log::Log;
+    ///
+    /// // Operation type that will be stored on the log.
+    /// #[derive(Clone)]
+    /// enum Operation {
+    ///     Read,
+    ///     Write(u64),
+    /// }
+    ///
+    /// // Creates a ~1 MiB sized log for a machine with 32 NUMA nodes.
+    /// let l = Log::<Operation, (), ()>::new_with_replicas(1 * 1024 * 1024, 32, ());
+    /// assert_eq!(l.max_replicas(), 32);
+    /// ```
+    pub fn new_with_replicas(bytes: usize, max_replicas: usize, metadata: LM) -> Self {
+        Log::allocate(
+            Self::bytes_to_log_entries(bytes, GC_FROM_HEAD),
+            max_replicas,
+            ReplicaConfig::default(),
+            metadata,
+        )
+    }
+
+    /// Constructs and returns a log of (approximately) `bytes` bytes that
+    /// supports up to `max_replicas` replicas which are created with `config`.
+    ///
+    /// The log keeps enough space for the largest batch such a replica can
+    /// append (see [`Log::gc_from_head`]), so it has at least
+    /// `2 * config.max_batch()` entries. Fails if `config` is unusable (see
+    /// [`ReplicaConfig::validate`]).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use node_replication::log::Log;
+    /// use node_replication::nr::ReplicaConfig;
+    ///
+    /// // Operation type that will be stored on the log.
+    /// #[derive(Clone)]
+    /// enum Operation {
+    ///     Read,
+    ///     Write(u64),
+    /// }
+    ///
+    /// // A log for replicas with up to 512 threads.
+    /// let config = ReplicaConfig { max_threads: 512, pending_ops: 32 };
+    /// let l = Log::<Operation, (), ()>::new_with_config(1 * 1024 * 1024, 4, config, ()).unwrap();
+    /// assert_eq!(l.gc_from_head(), 512 * 32);
+    /// ```
+    pub fn new_with_config(
+        bytes: usize,
+        max_replicas: usize,
+        config: ReplicaConfig,
+        metadata: LM,
+    ) -> Result<Self, ConfigError> {
+        Log::new_with_entries_and_config(
+            Self::bytes_to_log_entries(bytes, config.max_batch()),
+            max_replicas,
+            config,
+            metadata,
+        )
     }
 
     /// Constructs and returns a log of (approximately) [`DEFAULT_LOG_BYTES`]
@@ -314,16 +443,19 @@ where
     /// # See also
     /// - [`Log::new_with_bytes`]
     pub fn new_with_metadata(metadata: LM) -> Self {
-        Log::new_with_entries(Self::bytes_to_log_entries(DEFAULT_LOG_BYTES), metadata)
+        Log::new_with_entries(
+            Self::bytes_to_log_entries(DEFAULT_LOG_BYTES, GC_FROM_HEAD),
+            metadata,
+        )
     }
 
     /// Determines the number of entries in the log. This is likely just `entries` rounded
     /// to the next power of two -- as long as it's above the minimal threshold required
-    /// for the log to work (2*GC_FROM_HEAD).
-    fn entries_to_log_entries(entries: usize) -> usize {
-        core::cmp::max(2 * GC_FROM_HEAD, entries)
+    /// for the log to work (2*gc_from_head).
+    fn entries_to_log_entries(entries: usize, gc_from_head: usize) -> usize {
+        core::cmp::max(2 * gc_from_head, entries)
             .checked_next_power_of_two()
-            .unwrap_or(2 * GC_FROM_HEAD)
+            .unwrap_or(2 * gc_from_head)
     }
 
     /// Converts a size (in bytes), to the number of log entries required to fill this
@@ -331,22 +463,35 @@ where
     ///
     /// The resulting amount of entries likely will occupy more space as the #log-entries
     /// needs to be rounded to a power-of-two.
-    fn bytes_to_log_entries(bytes: usize) -> usize {
+    fn bytes_to_log_entries(bytes: usize, gc_from_head: usize) -> usize {
         // Calculate the number of entries that will go into the log, and retrieve a
         // slice to it from the allocated region of memory.
         // Make sure the log is large enough to allow for periodic garbage collection.
-        let mut num = core::cmp::max(2 * GC_FROM_HEAD, bytes / Log::<T, LM, M>::entry_size());
+        let mut num = core::cmp::max(2 * gc_from_head, bytes / Log::<T, LM, M>::entry_size());
 
         // Round off to the next power of two if required. If we overflow, then set
         // the number of entries to the minimum required for GC. This is unlikely since
         // we'd need a log size > 2^63 entries for this to happen.
         if !num.is_power_of_two() {
-            num = num.checked_next_power_of_two().unwrap_or(2 * GC_FROM_HEAD)
+            num = num.checked_next_power_of_two().unwrap_or(2 * gc_from_head)
         }
 
         num
     }
 
+    /// Returns the maximum number of replicas that can be registered with the
+    /// log.
+    pub fn max_replicas(&self) -> usize {
+        self.ltails.len()
+    }
+
+    /// Returns the distance between the tail and the head of the log at which
+    /// garbage collection is performed. This is the largest batch a replica
+    /// of the log can append at once (see [`crate::replica::ReplicaConfig`]).
+    pub fn gc_from_head(&self) -> usize {
+        self.gc_from_head
+    }
+
     /// Returns the size of a log entry in bytes.
     pub const fn entry_size() -> usize {
         size_of::<Cell<Entry<T, M>>>()
@@ -379,25 +524,81 @@ where
     /// let idx = l.register().expect("Failed to register with the Log.");
     /// ```
     pub fn register(&self) -> Option<LogToken> {
-        // Loop until we either run out of identifiers or we manage to increment `next`.
-        loop {
+        self.with_membership(|| {
             let n = self.next.load(Ordering::Relaxed);
 
             // Check if we've exceeded the maximum number of replicas the log can support.
-            if n > MAX_REPLICAS_PER_LOG {
+            if n > self.max_replicas() {
                 return None;
             };
 
-            if self
-                .next
-                .compare_exchange_weak(n, n + 1, Ordering::SeqCst, Ordering::SeqCst)
-                != Ok(n)
-            {
-                continue;
-            };
+            self.next.store(n + 1, Ordering::Release);
+            Some(LogToken(n))
+        })
+    }
+
+    /// Registers a new replica with the log that starts consuming the log at
+    /// the position that the replica identified by `from` has reached. Slots
+    /// of retired replicas for which `reuse` returns true are handed out
+    /// before allocating a new one.
+    ///
+    /// The caller has to make sure the replica identified by `from` does not
+    /// execute operations from the log until this method returns (e.g., by
+    /// holding its combiner lock). This ensures its local tail stays put and
+    /// GC can't advance the head beyond it before the new replica is visible.
+    pub(crate) fn register_from(
+        &self,
+        from: &LogToken,
+        reuse: impl Fn(&LogToken) -> bool,
+    ) -> Option<LogToken> {
+        self.with_membership(|| {
+            let n = self.next.load(Ordering::Relaxed);
+            let idx = (1..n)
+                .find(|idx| self.retired[idx - 1].load(Ordering::Relaxed) && reuse(&LogToken(*idx)))
+                .or_else(|| (n <= self.max_replicas()).then_some(n))?;
+
+            self.ltails[idx - 1].store(
+                self.ltails[from.0 - 1].load(Ordering::Relaxed),
+                Ordering::Relaxed,
+            );
+            self.lmasks[idx - 1].set(self.lmasks[from.0 - 1].get());
+            self.retired[idx - 1].store(false, Ordering::Release);
+            if idx == n {
+                self.next.store(n + 1, Ordering::Release);
+            }
+
+            Some(LogToken(idx))
+        })
+    }
+
+    /// Retires the replica identified by `idx` from the log. From now on, GC
+    /// no longer waits for the replica to consume entries and its slot can be
+    /// handed out again by [`Log::register_from()`].
+    ///
+    /// It is a bug to use `idx` to execute or append operations afterwards.
+    pub(crate) fn unregister(&self, idx: &LogToken) {
+        self.with_membership(|| self.retired[idx.0 - 1].store(true, Ordering::Release));
+    }
 
-            return Some(LogToken(n));
+    /// Runs `f` while holding the lock that serializes changes to the set of
+    /// replicas registered with the log.
+    fn with_membership<R>(&self, f: impl FnOnce() -> R) -> R {
+        let mut iteration = 1;
+        while self
+            .membership
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            #[cfg(loom)]
+            loom::thread::yield_now();
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
+
+        let r = f();
+        self.membership.store(false, Ordering::Release);
+        self.waiter.notify();
+        r
     }
 
     /// Returns a physical index given a logical index into the shared log.
@@ -407,20 +608,27 @@ where
     }
 
     /// Loops over all `ltails` and finds the replica with the lowest tail.
+    /// Retired replicas are skipped.
     ///
     /// # Returns
     /// The ID (in `LogToken`) of the replica with the lowest tail and the
     /// corresponding/lowest tail `idx` in the `Log`.
     pub(crate) fn find_min_tail(&self) -> (usize, usize) {
-        let r = self.next.load(Ordering::Relaxed);
+        let r = self.next.load(Ordering::Acquire);
         let (mut min_replica_idx, mut min_local_tail) = (0, self.ltails[0].load(Ordering::Relaxed));
+        let mut found = false;
 
         // Find the smallest local tail across all replicas.
         for idx in 1..r {
+            if self.retired[idx - 1].load(Ordering::Acquire) {
+                continue;
+            }
+
             let cur_local_tail = self.ltails[idx - 1].load(Ordering::Relaxed);
             //info!("Replica {} cur_local_tail {}.", idx - 1, cur_local_tail);
 
-            if cur_local_tail < min_local_tail {
+            if !found || cur_local_tail < min_local_tail {
+                found = true;
                 min_local_tail = cur_local_tail;
                 min_replica_idx = idx - 1;
             }
@@ -449,9 +657,11 @@ where
         self.next.store(1, Ordering::SeqCst);
 
         // Next, reset replica-local metadata.
-        for r in 0..MAX_REPLICAS_PER_LOG {
+        for r in 0..self.max_replicas() {
             self.ltails[r].store(0, Ordering::Relaxed);
             self.lmasks[r].set(true);
+            self.lskipped[r].set(0);
+            self.retired[r].store(false, Ordering::Relaxed);
         }
 
         // Next, free up all log entries. Use pointers to avoid memcpy and speed up the
@@ -459,6 +669,7 @@ where
         for i in 0..self.slog.len() {
             let e = self.slog[self.index(i)].as_ptr();
             (*e).alivef.store(false, Ordering::Release);
+            (*e).skipped.store(false, Ordering::Relaxed);
         }
     }
 
@@ -531,6 +742,35 @@ where
     pub(crate) fn get_ctail(&self) -> usize {
         self.ctail.load(Ordering::Relaxed)
     }
+
+    /// Returns the position in the log up to which the replica identified by
+    /// `idx` applied the operations (its local tail).
+    #[inline(always)]
+    pub(crate) fn get_ltail(&self, idx: &LogToken) -> usize {
+        self.ltails[idx.0 - 1].load(Ordering::Relaxed)
+    }
+
+    /// Returns a snapshot of the runtime statistics of the log.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LogStats {
+        let ctail = self.get_ctail();
+        let registered = core::cmp::min(self.next.load(Ordering::Acquire) - 1, self.max_replicas());
+        let lag = (0..registered)
+            .map(|i| {
+                if self.retired[i].load(Ordering::Relaxed) {
+                    None
+                } else {
+                    Some(ctail.saturating_sub(self.ltails[i].load(Ordering::Relaxed)))
+                }
+            })
+            .collect();
+
+        LogStats {
+            ctail,
+            gc_wait: self.stats.gc_wait.get(),
+            lag,
+        }
+    }
 }
 
 impl<T, LM, M> Default for Log<T, LM, M>
@@ -680,4 +920,51 @@ mod tests {
         }
         assert!(l.register().is_none());
     }
+
+    // Tests that a log can support more than `MAX_REPLICAS_PER_LOG` replicas.
+    #[test]
+    fn test_log_register_max_replicas() {
+        let l = Log::<Operation, (), ()>::new_with_replicas(1024, 2 * MAX_REPLICAS_PER_LOG, ());
+        assert_eq!(l.max_replicas(), 2 * MAX_REPLICAS_PER_LOG);
+        for _i in 0..2 * MAX_REPLICAS_PER_LOG {
+            assert!(l.register().is_some());
+        }
+        assert!(l.register().is_none());
+        assert_eq!(l.find_min_tail(), (0, 0));
+    }
+
+    // Tests that `register_from` starts at the position of the given replica
+    // and re-uses the slots of retired replicas.
+    #[test]
+    fn test_log_register_from() {
+        let l = Log::<Operation, (), ()>::default();
+        let lt1 = l.register().unwrap();
+        let lt2 = l.register().unwrap();
+        l.ltails[0].store(1023, Ordering::Relaxed);
+        l.lmasks[0].set(false);
+
+        let lt3 = l.register_from(&lt1, |_| true).unwrap();
+        assert_eq!(lt3, LogToken(3));
+        assert_eq!(l.ltails[2].load(Ordering::Relaxed), 1023);
+        assert!(!l.lmasks[2].get());
+
+        l.unregister(&lt2);
+        assert_eq!(l.register_from(&lt1, |_| true), Some(LogToken(2)));
+        assert_eq!(l.ltails[1].load(Ordering::Relaxed), 1023);
+        assert_eq!(l.next.load(Ordering::Relaxed), 4);
+    }
+
+    // Tests that retired replicas are ignored when looking for the smallest tail.
+    #[test]
+    fn test_log_find_min_tail_retired() {
+        let l = Log::<Operation, (), ()>::default();
+        let lt1 = l.register().unwrap();
+        let _lt2 = l.register().unwrap();
+        l.ltails[0].store(12, Ordering::Relaxed);
+        l.ltails[1].store(99, Ordering::Relaxed);
+        assert_eq!(l.find_min_tail(), (0, 12));
+
+        l.unregister(&lt1);
+        assert_eq!(l.find_min_tail(), (1, 99));
+    }
 }
@@ -0,0 +1,486 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! A builder to configure and create [`NodeReplicated`] instances.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::num::NonZeroUsize;
+
+use super::log::{self, Log};
+use super::replica::{Replica, ReplicaConfig, ReplicaId};
+use super::transport::LogTransport;
+use super::wal::{LogSerialize, Wal};
+use super::{
+    AffinityChange, AffinityChangeFn, AffinityManager, Dispatch, Eviction, Locality, NodeLookupFn,
+    NodeMappingFn, NodeReplicated, NodeReplicatedError, MAX_REPLICAS_PER_LOG,
+};
+use crate::wait::{WaitStrategy, Waiter};
+
+/// User provided function that creates the initial data-structure of a
+/// replica.
+type DataFactoryFn<D> = dyn FnMut(ReplicaId) -> D;
+
+/// Size of the [`Log`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum LogSize {
+    /// Approximate size in bytes (see [`Log::new_with_config`]).
+    Bytes(usize),
+    /// Approximate number of entries (see [`Log::new_with_entries_and_config`]).
+    Entries(usize),
+}
+
+/// Configures and creates a [`NodeReplicated`] instance.
+///
+/// Every option has a default, except for the initial data-structure of the
+/// replicas if `D` doesn't implement [`Default`] (use
+/// [`NodeReplicatedBuilder::data`] or [`NodeReplicatedBuilder::data_factory`]).
+/// The configuration is validated by [`NodeReplicatedBuilder::build`], which
+/// returns [`NodeReplicatedError::InvalidConfig`] instead of panicking.
+///
+/// There is no option for statistics: They are collected if the crate is
+/// built with the `stats` feature and read with `NodeReplicated::stats`, the
+/// clock that times waits is set with `stats::set_clock`.
+///
+/// # Example
+///
+/// ```
+/// # #![feature(generic_associated_types)]
+/// use node_replication::nr::{Dispatch, NodeReplicated, ReplicaConfig};
+///
+/// #[derive(Default)]
+/// struct Counter(usize);
+/// # impl Dispatch for Counter {
+/// #     type ReadOperation<'rop> = ();
+/// #     type WriteOperation = ();
+/// #     type Response = usize;
+/// #
+/// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+/// #         self.0
+/// #     }
+/// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+/// #         self.0 += 1;
+/// #         self.0
+/// #     }
+/// # }
+///
+/// let nrht = NodeReplicated::<Counter>::builder()
+///     .replicas(2)
+///     .log_entries(1 << 12)
+///     .replica_config(ReplicaConfig { max_threads: 8, pending_ops: 4 })
+///     .affinity(|_ac| 0)
+///     .build()
+///     .unwrap();
+///
+/// let ttkn = nrht.register(1).unwrap();
+/// assert_eq!(nrht.execute_mut((), ttkn), 1);
+/// ```
+pub struct NodeReplicatedBuilder<D: Dispatch + Sync> {
+    replicas: usize,
+    max_replicas: Option<usize>,
+    log_size: LogSize,
+    config: ReplicaConfig,
+    affinity: Option<Box<AffinityChangeFn>>,
+    node_lookup: Option<Box<NodeLookupFn>>,
+    node_mapping: Option<Box<NodeMappingFn>>,
+    data: Option<Box<DataFactoryFn<D>>>,
+    wait_strategy: Option<&'static dyn WaitStrategy>,
+    eviction: Option<Eviction<D>>,
+    /// Streams the operations to a follower (see
+    /// [`NodeReplicatedBuilder::transport`]).
+    wal: Option<Wal<D::WriteOperation>>,
+    /// Set if boxing one of the user provided functions failed, reported by
+    /// [`NodeReplicatedBuilder::build`].
+    out_of_memory: bool,
+}
+
+impl<D> Default for NodeReplicatedBuilder<D>
+where
+    D: Default + Dispatch + Sized + Sync,
+{
+    /// A builder that creates the replicas with the [`Default`] constructor
+    /// of `D`.
+    fn default() -> Self {
+        NodeReplicatedBuilder::new().data_factory(|_rid| D::default())
+    }
+}
+
+impl<D> NodeReplicatedBuilder<D>
+where
+    D: Dispatch + Sized + Sync,
+{
+    /// Creates a builder for a single replica with a log of
+    /// [`log::DEFAULT_LOG_BYTES`] and the default [`ReplicaConfig`].
+    ///
+    /// The initial data-structure has to be provided with
+    /// [`NodeReplicatedBuilder::data`] or
+    /// [`NodeReplicatedBuilder::data_factory`].
+    pub fn new() -> Self {
+        NodeReplicatedBuilder {
+            replicas: 1,
+            max_replicas: None,
+            log_size: LogSize::Bytes(log::DEFAULT_LOG_BYTES),
+            config: ReplicaConfig::default(),
+            affinity: None,
+            node_lookup: None,
+            node_mapping: None,
+            data: None,
+            wait_strategy: None,
+            eviction: None,
+            wal: None,
+            out_of_memory: false,
+        }
+    }
+
+    /// Boxes `f`, remembers the failure for [`NodeReplicatedBuilder::build`]
+    /// if there is not enough memory.
+    fn try_box<F>(&mut self, f: F) -> Option<Box<F>> {
+        let f = Box::try_new(f).ok();
+        self.out_of_memory |= f.is_none();
+        f
+    }
+
+    /// Sets the number of replicas that are created, typically the number of
+    /// NUMA nodes in the system (default: 1).
+    pub fn replicas(mut self, num_replicas: usize) -> Self {
+        self.replicas = num_replicas;
+        self
+    }
+
+    /// Sets the number of replicas that can be active at the same time (see
+    /// [`NodeReplicated::add_replica`]).
+    ///
+    /// Defaults to [`MAX_REPLICAS_PER_LOG`] or the number of replicas if
+    /// that is more.
+    pub fn max_replicas(mut self, max_replicas: usize) -> Self {
+        self.max_replicas = Some(max_replicas);
+        self
+    }
+
+    /// Sets the (approximate) size of the [`Log`] in bytes.
+    pub fn log_bytes(mut self, bytes: usize) -> Self {
+        self.log_size = LogSize::Bytes(bytes);
+        self
+    }
+
+    /// Sets the (approximate) size of the [`Log`] in entries.
+    pub fn log_entries(mut self, entries: usize) -> Self {
+        self.log_size = LogSize::Entries(entries);
+        self
+    }
+
+    /// Sets the number of threads and the depth of their contexts for all
+    /// replicas.
+    pub fn replica_config(mut self, config: ReplicaConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sets the function that changes the memory affinity of a thread (see
+    /// [`NodeReplicated::new`] and [`AffinityChange`]). By default the
+    /// affinity isn't changed.
+    pub fn affinity(
+        mut self,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.affinity = self
+            .try_box(chg_mem_affinity)
+            .map(|f| f as Box<AffinityChangeFn>);
+        self
+    }
+
+    /// Sets the function that returns the NUMA node of the calling thread
+    /// (see [`NodeReplicated::set_node_lookup`]).
+    pub fn node_lookup(mut self, node_lookup: impl Fn() -> usize + Send + Sync + 'static) -> Self {
+        self.node_lookup = self.try_box(node_lookup).map(|f| f as Box<NodeLookupFn>);
+        self
+    }
+
+    /// Sets the function that maps a NUMA node to the replica serving it
+    /// (see [`NodeReplicated::set_node_mapping`]).
+    pub fn node_mapping(
+        mut self,
+        node_mapping: impl Fn(usize) -> ReplicaId + Send + Sync + 'static,
+    ) -> Self {
+        self.node_mapping = self.try_box(node_mapping).map(|f| f as Box<NodeMappingFn>);
+        self
+    }
+
+    /// Every replica starts with a clone of `ds` (see
+    /// [`NodeReplicated::with_data`]).
+    pub fn data(self, ds: D) -> Self
+    where
+        D: Clone + 'static,
+    {
+        self.data_factory(move |_rid| ds.clone())
+    }
+
+    /// Every replica starts with the data-structure returned by `factory`
+    /// for its [`ReplicaId`].
+    ///
+    /// `factory` is called while the memory affinity is set to the replica
+    /// (see [`AffinityChange::Replica`]). It has to return the same state for
+    /// every replica, otherwise operations give different results on
+    /// different replicas.
+    pub fn data_factory(mut self, factory: impl FnMut(ReplicaId) -> D + 'static) -> Self {
+        self.data = self.try_box(factory).map(|f| f as Box<DataFactoryFn<D>>);
+        self
+    }
+
+    /// Makes threads that wait on the instance use `strategy` (see
+    /// [`crate::wait`]).
+    ///
+    /// The strategy only applies to this instance, instances built without
+    /// one use the strategy installed with [`crate::wait::set_strategy`].
+    pub fn wait_strategy(mut self, strategy: &'static dyn WaitStrategy) -> Self {
+        self.wait_strategy = Some(strategy);
+        self
+    }
+
+    /// Evicts a replica that holds up garbage collection of the [`Log`]
+    /// because it lags more than `max_lag` entries behind (e.g., its threads
+    /// stopped issuing operations). By default, threads that wait for garbage
+    /// collection keep syncing a lagging replica instead (see
+    /// [`NodeReplicated::execute_mut_timeout`]).
+    ///
+    /// An evicted replica is dormant: garbage collection no longer waits for
+    /// it. The next operation on the replica rebuilds it with a clone of the
+    /// data-structure of a healthy replica, it doesn't replay the operations
+    /// it missed. A replica is only evicted while none of its threads are in
+    /// the middle of an operation.
+    ///
+    /// `max_lag` has to be smaller than the log minus the largest batch of a
+    /// replica (see [`ReplicaConfig::max_batch`]), otherwise the replica is
+    /// stuck before it lags enough to be evicted.
+    pub fn evict_after(mut self, max_lag: usize) -> Self
+    where
+        D: Clone,
+    {
+        self.eviction = Some(Eviction {
+            max_lag,
+            clone: D::clone,
+        });
+        self
+    }
+
+    /// Validates the configuration and creates the [`NodeReplicated`]
+    /// instance.
+    pub fn build(self) -> Result<NodeReplicated<D>, NodeReplicatedError> {
+        if self.out_of_memory {
+            return Err(NodeReplicatedError::OutOfMemory);
+        }
+        let num_replicas = NonZeroUsize::new(self.replicas).ok_or(
+            NodeReplicatedError::InvalidConfig("At least one replica is required"),
+        )?;
+        let max_replicas = self
+            .max_replicas
+            .unwrap_or_else(|| core::cmp::max(num_replicas.get(), MAX_REPLICAS_PER_LOG));
+        if max_replicas < num_replicas.get() {
+            return Err(NodeReplicatedError::InvalidConfig(
+                "max_replicas is smaller than the number of replicas",
+            ));
+        }
+        self.config.validate()?;
+        let mut factory = self.data.ok_or(NodeReplicatedError::InvalidConfig(
+            "No initial data-structure for the replicas",
+        ))?;
+
+        let affinity = match self.affinity {
+            Some(affinity) => affinity,
+            None => Box::try_new(|_ac| 0)?,
+        };
+        let locality = Locality {
+            node_lookup: self.node_lookup,
+            node_mapping: self.node_mapping,
+            num_replicas: num_replicas.get(),
+        };
+        let mut log = match self.log_size {
+            LogSize::Bytes(bytes) => Log::new_with_config(bytes, max_replicas, self.config, ())?,
+            LogSize::Entries(entries) => {
+                Log::new_with_entries_and_config(entries, max_replicas, self.config, ())?
+            }
+        };
+
+        if let Some(eviction) = &self.eviction {
+            if eviction.max_lag >= log.slog.len() - log.gc_from_head() {
+                return Err(NodeReplicatedError::InvalidConfig(
+                    "evict_after has to be smaller than the log",
+                ));
+            }
+        }
+
+        if let Some(strategy) = self.wait_strategy {
+            log.waiter = Waiter::new(strategy);
+        }
+        let waiter = log.waiter;
+        let wal = self.wal.map(|mut wal| {
+            wal.waiter = waiter;
+            Arc::try_new(wal)
+        });
+
+        let config = self.config;
+        let mut nr = NodeReplicated::from_parts(
+            num_replicas,
+            AffinityManager::new(affinity),
+            locality,
+            log,
+            config,
+            wal.transpose()?,
+            |log_token| {
+                let d = factory(log_token.0 - 1);
+                Ok(Replica::with_config(log_token, config, d)?)
+            },
+        )?;
+        nr.eviction = self.eviction;
+        Ok(nr)
+    }
+}
+
+impl<D> NodeReplicatedBuilder<D>
+where
+    D: Dispatch + Sized + Sync,
+    D::WriteOperation: LogSerialize,
+{
+    /// Streams all mutable operations to a follower through `transport` (see
+    /// [`NodeReplicated::follow`]).
+    ///
+    /// The combiners queue their batches in log order and send them once they
+    /// appended them to the log, outside of the locks of the instance, so the
+    /// follower receives the operations in log order. A follower that falls
+    /// [`super::wal::TRANSPORT_QUEUE`] batches behind slows down the
+    /// combiners.
+    pub fn transport(mut self, transport: impl LogTransport + 'static) -> Self {
+        self.wal = self
+            .try_box(transport)
+            .map(|t| Wal::streaming(t as Box<dyn LogTransport>));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::super::replica::test::Data;
+    use super::*;
+
+    // Tests that every replica gets the data-structure the factory creates
+    // for it.
+    #[test]
+    fn test_builder_data_factory() {
+        let nr = NodeReplicatedBuilder::<Data>::new()
+            .replicas(3)
+            .data_factory(|rid| Data {
+                junk: 7 * rid as u64,
+            })
+            .build()
+            .expect("Can't create Ds");
+
+        for rid in 0..3 {
+            let ttkn = nr.register(rid).expect("Unable to register with replica");
+            assert_eq!(nr.execute(0, ttkn), Ok(7 * rid as u64));
+        }
+    }
+
+    // Tests that the replica configuration applies to all replicas, including
+    // the ones that are added later.
+    #[test]
+    fn test_builder_replica_config() {
+        let config = ReplicaConfig {
+            max_threads: 2,
+            pending_ops: 4,
+        };
+        let nr = NodeReplicatedBuilder::<Data>::default()
+            .replicas(1)
+            .max_replicas(2)
+            .log_entries(64)
+            .replica_config(config)
+            .build()
+            .expect("Can't create Ds");
+        assert_eq!(nr.log.max_replicas(), 2);
+        assert_eq!(nr.log.gc_from_head(), 8);
+
+        let rid = nr.add_replica().expect("Can't add replica");
+        assert!(matches!(
+            nr.add_replica(),
+            Err(NodeReplicatedError::TooManyReplicas)
+        ));
+        let ttkn = nr.register(rid).expect("Unable to register with replica");
+        assert!(nr.register(rid).is_some());
+        assert!(nr.register(rid).is_none());
+
+        for _i in 0..100 {
+            assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        }
+        assert_eq!(nr.execute(0, ttkn), Ok(100));
+    }
+
+    // Tests that invalid configurations are rejected with an error.
+    #[test]
+    fn test_builder_invalid_config() {
+        let invalid = |b: NodeReplicatedBuilder<Data>| {
+            matches!(b.build(), Err(NodeReplicatedError::InvalidConfig(_)))
+        };
+
+        assert!(invalid(NodeReplicatedBuilder::new()));
+        assert!(invalid(NodeReplicatedBuilder::default().replicas(0)));
+        assert!(invalid(
+            NodeReplicatedBuilder::default().replicas(3).max_replicas(2)
+        ));
+        assert!(invalid(NodeReplicatedBuilder::default().replica_config(
+            ReplicaConfig {
+                max_threads: 0,
+                pending_ops: 4,
+            }
+        )));
+        assert!(invalid(NodeReplicatedBuilder::default().replica_config(
+            ReplicaConfig {
+                max_threads: 4,
+                pending_ops: 3,
+            }
+        )));
+        assert!(invalid(
+            NodeReplicatedBuilder::default().evict_after(usize::MAX)
+        ));
+    }
+    // Tests that a wait strategy only applies to the instance it is given to.
+    #[test]
+    fn test_builder_wait_strategy() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Counting(AtomicUsize);
+
+        impl WaitStrategy for Counting {
+            fn wait(&self, _iteration: usize) {
+                core::hint::spin_loop();
+            }
+
+            fn notify(&self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        static FIRST: Counting = Counting(AtomicUsize::new(0));
+        static SECOND: Counting = Counting(AtomicUsize::new(0));
+
+        let first = NodeReplicatedBuilder::<Data>::default()
+            .wait_strategy(&FIRST)
+            .build()
+            .expect("Can't create Ds");
+        let second = NodeReplicatedBuilder::<Data>::default()
+            .wait_strategy(&SECOND)
+            .build()
+            .expect("Can't create Ds");
+        let first_before = FIRST.0.load(Ordering::Relaxed);
+        let second_before = SECOND.0.load(Ordering::Relaxed);
+
+        let ttkn = first.register(0).expect("Unable to register with replica");
+        assert_eq!(first.execute_mut(0, ttkn), Ok(107));
+        assert!(FIRST.0.load(Ordering::Relaxed) > first_before);
+        assert_eq!(SECOND.0.load(Ordering::Relaxed), second_before);
+
+        let ttkn = second.register(0).expect("Unable to register with replica");
+        assert_eq!(second.execute_mut(0, ttkn), Ok(107));
+        assert!(SECOND.0.load(Ordering::Relaxed) > second_before);
+    }
+}
@@ -14,8 +14,29 @@ pub use crate::log::GC_FROM_HEAD;
 
 pub use crate::log::WARN_THRESHOLD;
 
+use crate::log::Entry;
+
 pub type Log<T> = crate::log::Log<T, (), ()>;
 
+/// Marks a log entry as skipped if it is dropped while unwinding from a panic
+/// that happened while applying the entry. It is forgotten otherwise.
+///
+/// The local tail of the replica is moved to the entry (see
+/// [`Log::poisoned_at`]).
+struct PoisonOnUnwind<'e, T: Sized + Clone> {
+    entry: &'e Entry<T, ()>,
+    log: &'e Log<T>,
+    idx: &'e LogToken,
+    pos: usize,
+}
+
+impl<T: Sized + Clone> Drop for PoisonOnUnwind<'_, T> {
+    fn drop(&mut self) {
+        self.entry.skipped.store(true, Ordering::Release);
+        self.log.ltails[self.idx.0 - 1].store(self.pos, Ordering::Relaxed);
+    }
+}
+
 impl<T> Log<T>
 where
     T: Sized + Clone,
@@ -78,12 +99,29 @@ where
     #[inline(always)]
     #[doc(hidden)]
     pub fn append<F: FnMut(T, bool)>(
+        &self,
+        ops: &[T],
+        idx: &LogToken,
+        s: F,
+    ) -> Result<Option<usize>, usize> {
+        self.append_then(ops, idx, s, |_pos| {})
+    }
+
+    /// Inserts a slice of operations into the log, like [`Log::append`].
+    ///
+    /// Once entries are reserved for the operations, `reserved` is called
+    /// with the position of the first one. The operations are added to the
+    /// log (and can be applied by the replicas) after it returns.
+    #[inline(always)]
+    pub(crate) fn append_then<F: FnMut(T, bool), W: FnOnce(usize)>(
         &self,
         ops: &[T],
         idx: &LogToken,
         mut s: F,
+        reserved: W,
     ) -> Result<Option<usize>, usize> {
         let nops = ops.len();
+        debug_assert!(nops <= self.gc_from_head, "Batch exceeds gc_from_head");
         let mut iteration = 1;
         let mut waitgc = 1;
 
@@ -110,7 +148,7 @@ where
             // try again. The replica that reserved entry (h + self.slog.len() - GC_FROM_HEAD)
             // is currently trying to advance the head of the log. Keep refreshing the
             // replica against the log to make sure that it isn't deadlocking GC.
-            if tail > head + self.slog.len() - GC_FROM_HEAD {
+            if tail > head + self.slog.len() - self.gc_from_head {
                 if waitgc % WARN_THRESHOLD == 0 {
                     warn!(
                         "append(ops.len()={}, {}) takes too many iterations ({}) waiting for gc...",
@@ -122,8 +160,14 @@ where
                     return Err(min_replica_idx);
                 }
                 waitgc += 1;
-                self.exec(idx, &mut s);
+                {
+                    let _gc_wait = self.stats.gc_wait.start();
+                    self.exec(idx, &mut s);
+                }
+                // Measures its own waits.
                 self.advance_head(idx, &mut s)?;
+                let _gc_wait = self.stats.gc_wait.start();
+                self.waiter.wait(waitgc);
 
                 #[cfg(loom)]
                 loom::thread::yield_now();
@@ -133,7 +177,7 @@ where
             // If on adding in the above entries there would be fewer than `GC_FROM_HEAD`
             // entries left on the log, then we need to advance the head of the log.
             let mut advance = false;
-            if tail + nops > head + self.slog.len() - GC_FROM_HEAD {
+            if tail + nops > head + self.slog.len() - self.gc_from_head {
                 advance = true
             };
 
@@ -150,6 +194,7 @@ where
             };
 
             // Successfully reserved entries on the shared log. Add the operations in.
+            reserved(tail);
             for (i, op) in ops.iter().enumerate().take(nops) {
                 let e = self.slog[self.index(tail + i)].as_ptr();
                 let mut m = self.lmasks[idx.0 - 1].get();
@@ -165,8 +210,10 @@ where
 
                 unsafe { (*e).operation = Some(op.clone()) };
                 unsafe { (*e).replica = idx.0 };
+                unsafe { (*e).skipped.store(false, Ordering::Relaxed) };
                 unsafe { (*e).alivef.store(m, Ordering::Release) };
             }
+            self.waiter.notify();
 
             // If needed, advance the head of the log forward to make room on the log.
             return if advance {
@@ -269,18 +316,34 @@ where
                         self.lmasks[idx.0 - 1].get()
                     );
                 }
+                self.waiter.wait(iteration);
                 iteration += 1;
 
                 #[cfg(loom)]
                 loom::thread::yield_now();
             }
 
-            unsafe {
-                d(
-                    (*e).operation.as_ref().unwrap().clone(),
-                    (*e).replica == idx.0,
-                )
-            };
+            // An operation that panicked on a replica is skipped by the
+            // replicas that reach it afterwards. If it is ours, the replica
+            // hands out an error instead of a response for it (see
+            // `Replica::combine`). Operations are expected to panic on every
+            // replica or none, so a replica that applies it concurrently
+            // panics as well.
+            let entry = unsafe { &*e };
+            let mine = entry.replica == idx.0;
+            if !entry.skipped.load(Ordering::Acquire) {
+                let guard = PoisonOnUnwind {
+                    entry,
+                    log: self,
+                    idx,
+                    pos: i,
+                };
+                d(entry.operation.as_ref().unwrap().clone(), mine);
+                core::mem::forget(guard);
+            } else if mine {
+                let skipped = &self.lskipped[idx.0 - 1];
+                skipped.set(skipped.get() + 1);
+            }
 
             // Looks like we're going to wrap around now; flip this replica's local mask.
             if self.index(i) == self.slog.len() - 1 {
@@ -295,6 +358,30 @@ where
         self.ltails[idx.0 - 1].store(gtail, Ordering::Relaxed);
     }
 
+    /// Returns the position of the entry that panicked while the replica
+    /// identified by `idx` applied it in [`Log::exec`], if the replica
+    /// stopped there.
+    pub(crate) fn poisoned_at(&self, idx: &LogToken) -> Option<usize> {
+        let ltail = self.ltails[idx.0 - 1].load(Ordering::Relaxed);
+        if ltail >= self.tail.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let e = self.slog[self.index(ltail)].as_ptr();
+        let poisoned = unsafe {
+            (*e).alivef.load(Ordering::Acquire) == self.lmasks[idx.0 - 1].get()
+                && (*e).skipped.load(Ordering::Acquire)
+        };
+        poisoned.then_some(ltail)
+    }
+
+    /// Returns how many operations appended by the replica identified by
+    /// `idx` were skipped in [`Log::exec`] because they panicked on another
+    /// replica.
+    pub(crate) fn skipped(&self, idx: &LogToken) -> usize {
+        self.lskipped[idx.0 - 1].get()
+    }
+
     /// Advances the head of the log forward. If a replica has stopped making
     /// progress, then this method will never return. Accepts a closure that is
     /// passed into exec() to ensure that this replica does not deadlock GC.
@@ -318,7 +405,9 @@ where
                     return Err(min_replica_idx);
                 }
                 iteration += 1;
+                let _gc_wait = self.stats.gc_wait.start();
                 self.exec(rid, &mut s);
+                self.waiter.wait(iteration);
 
                 #[cfg(loom)]
                 loom::thread::yield_now();
@@ -327,11 +416,12 @@ where
 
             // There are entries that can be freed up; update the head offset.
             self.head.store(min_local_tail, Ordering::Relaxed);
+            self.waiter.notify();
 
             // Make sure that we freed up enough space so that threads waiting for
             // GC in append can make progress. Otherwise, try to make progress again.
             // If we're making progress again, then try consuming entries on the log.
-            if f < min_local_tail + self.slog.len() - GC_FROM_HEAD {
+            if f < min_local_tail + self.slog.len() - self.gc_from_head {
                 return Ok(());
             } else {
                 self.exec(rid, &mut s);
@@ -604,6 +694,42 @@ mod tests {
         l.exec(&lt, &mut f);
     }
 
+    // Tests that an operation that panicked on one replica is skipped by the
+    // others, including the replica that appended it.
+    #[test]
+    fn test_log_exec_poisoned() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let l = Log::<Operation>::default();
+        let one = l.register().unwrap();
+        let two = l.register().unwrap();
+        let three = l.register().unwrap();
+        let ops = [Operation::Read, Operation::Write(1)];
+        assert!(l.append(&ops, &one, |_o, _mine| {}).is_ok());
+
+        let r = catch_unwind(AssertUnwindSafe(|| {
+            l.exec(&two, &mut |o, _mine| {
+                assert_eq!(o, Operation::Read, "Invalid operation");
+            })
+        }));
+        r.expect_err("Operation didn't panic");
+        assert_eq!(l.poisoned_at(&two), Some(1));
+        assert_eq!(l.poisoned_at(&one), None);
+
+        let mut applied = 0;
+        l.exec(&one, &mut |_o, _mine| applied += 1);
+        assert_eq!(applied, 1);
+        assert!(l.is_replica_synced_for_reads(&one, l.get_ctail()));
+        assert_eq!(l.poisoned_at(&one), None);
+        assert_eq!(l.skipped(&one), 1);
+        assert_eq!(l.skipped(&two), 0);
+
+        let mut applied = 0;
+        l.exec(&three, &mut |_o, _mine| applied += 1);
+        assert_eq!(applied, 1);
+        assert_eq!(l.skipped(&three), 0);
+    }
+
     // Tests that operations are cloned when added to the log, and that
     // they are correctly dropped once overwritten.
     #[test]
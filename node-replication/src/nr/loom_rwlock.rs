@@ -34,6 +34,10 @@ where
         }
     }
 
+    pub fn with_readers(t: T, _readers: usize) -> Self {
+        Self::new(t)
+    }
+
     pub fn write(&self, _n: usize) -> loom::sync::RwLockWriteGuard<'_, T> {
         self.inner.write().unwrap()
     }
@@ -71,20 +71,43 @@
 //! ```
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::Debug;
-use core::marker::Sync;
+#[cfg(feature = "async")]
+use core::future::Future;
+use core::marker::{PhantomData, Sync};
 use core::num::NonZeroUsize;
 #[cfg(feature = "async")]
+use core::pin::Pin;
+use core::ptr;
+#[cfg(not(loom))]
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+#[cfg(feature = "async")]
+use core::task::{Context, Poll};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+#[cfg(feature = "async")]
 use reusable_box::ReusableBoxFuture;
 
 use arrayvec::ArrayVec;
 
+#[cfg(feature = "stats")]
+use crate::stats::{LogStats, ReplicaStats};
+
+mod builder;
 mod context;
 pub mod log;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod numa;
 pub mod replica;
 #[cfg(feature = "async")]
 pub mod reusable_box;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub mod shm;
+pub mod transport;
+pub mod wal;
 
 #[cfg(not(loom))]
 #[path = "rwlock.rs"]
@@ -93,8 +116,15 @@ pub mod rwlock;
 #[path = "loom_rwlock.rs"]
 pub mod rwlock;
 
+pub use builder::NodeReplicatedBuilder;
 pub use log::{Log, MAX_REPLICAS_PER_LOG};
-pub use replica::{CombinerLock, Replica, ReplicaError, ReplicaId, ReplicaToken};
+pub use replica::{
+    CombinerLock, ConfigError, Replica, ReplicaConfig, ReplicaError, ReplicaId, ReplicaToken,
+};
+
+use replica::Active;
+use transport::LogTransport;
+use wal::{LogSerialize, Segment, SyncPolicy, Wal, WalError};
 
 /// Trait that a (single-threaded) data structure must implement to be usable
 /// with NR.
@@ -121,6 +151,11 @@ pub trait Dispatch {
     /// A write operation. When executed against the data structure, an
     /// operation of this type is allowed to mutate state. The library ensures
     /// that this is done so in a thread-safe manner.
+    ///
+    /// Every entry of the [`Log`] stores an operation inline and is as large
+    /// as the largest variant. Operations that carry a large payload (e.g.,
+    /// the data of a write) should keep it behind an `Arc`, so appending them
+    /// to the log only bumps a reference count.
     type WriteOperation: Sized + Clone + PartialEq + Send;
 
     /// The type on the value returned by the data structure when a
@@ -134,10 +169,102 @@ pub trait Dispatch {
     /// Method on the data structure that allows a write operation to be
     /// executed against it.
     fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response;
+
+    /// Applies a write operation whose response is not needed, e.g., when a
+    /// replica replays an operation that another replica appended to the
+    /// [`Log`]. Override this if building the response is expensive.
+    ///
+    /// The default implementation calls [`Dispatch::dispatch_mut`] and drops
+    /// the response.
+    fn apply_mut(&mut self, op: Self::WriteOperation) {
+        self.dispatch_mut(op);
+    }
+
+    /// Coalesces a batch of write operations before the combiner appends it
+    /// to the [`Log`], e.g., merges repeated updates of the same key.
+    ///
+    /// Returns true if `coalesced` (empty when called) holds the operations
+    /// to append instead of `ops`. For every operation in `coalesced`,
+    /// `replaced` holds the number of consecutive operations of `ops` it
+    /// replaces (in order). Applying a coalesced operation must leave the data
+    /// structure in the same state as applying the operations it replaces.
+    ///
+    /// Every replica, including the one of the combiner, only applies
+    /// `coalesced`; the responses of the replaced operations are derived from
+    /// the response of the coalesced one with [`Dispatch::fan_out`]. A batch
+    /// that is empty or doesn't replace exactly `ops` is ignored.
+    ///
+    /// The default implementation doesn't coalesce.
+    fn coalesce(
+        _ops: &[Self::WriteOperation],
+        _coalesced: &mut Vec<Self::WriteOperation>,
+        _replaced: &mut Vec<usize>,
+    ) -> bool {
+        false
+    }
+
+    /// Derives the responses of `ops`, the operations a coalesced operation
+    /// replaced (see [`Dispatch::coalesce`]), from the response `resp` of the
+    /// coalesced operation. Pushes one response per operation to `resps` (in
+    /// order).
+    ///
+    /// The default implementation hands `resp` to every operation.
+    fn fan_out(
+        ops: &[Self::WriteOperation],
+        resp: Self::Response,
+        resps: &mut Vec<Self::Response>,
+    ) {
+        resps.extend(ops.iter().map(|_op| resp.clone()));
+    }
+}
+
+/// Trait that a data structure (which implements [`Dispatch`]) can implement
+/// to support checkpoints of its state (see [`NodeReplicated::checkpoint`]).
+pub trait Snapshot: Sized {
+    /// Appends an image of the data structure to `buf`.
+    fn snapshot(&self, buf: &mut Vec<u8>);
+
+    /// Reconstructs the data structure from an image written by
+    /// [`Snapshot::snapshot`]. Returns None if `buf` is not a valid image.
+    fn restore(buf: &[u8]) -> Option<Self>;
+}
+
+/// A consistent image of a replicated data structure, taken with
+/// [`NodeReplicated::checkpoint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Number of mutable operations that were applied to the data structure
+    /// when the image was taken. If the operations are persisted in a
+    /// write-ahead log (see [`wal`]), it is the index of the first record
+    /// that is not part of the image.
+    pub position: usize,
+    /// The image written by [`Snapshot::snapshot`].
+    pub data: Vec<u8>,
+    /// Number of entries of the log of the instance the image was taken
+    /// from.
+    pub log_entries: usize,
+    /// The configuration of the replicas of the instance the image was taken
+    /// from.
+    pub config: ReplicaConfig,
+}
+
+/// A snapshot of the runtime statistics of a [`NodeReplicated`] instance,
+/// taken with [`NodeReplicated::stats`].
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Statistics of every active replica.
+    pub replicas: Vec<(ReplicaId, ReplicaStats)>,
+    /// Statistics of the shared log.
+    pub log: LogStats,
 }
 
 /// A token handed out to threads registered with replicas.
 ///
+/// Tokens are stamped with the id of the [`NodeReplicated`] instance that
+/// handed them out. Using a token with another instance, or after it was
+/// unregistered, fails with [`NodeReplicatedError::InvalidToken`].
+///
 /// # Implementation detail for potential future API
 /// For maximum type-safety this would be an affine type, then we'd have to
 /// return it again in `execute` and `execute_mut`. However it feels like this
@@ -152,18 +279,25 @@ pub struct ThreadToken {
     /// The registration token for this thread that we got from the replica
     /// (through [`Replica::register`]) identified by `rid`.
     rtkn: ReplicaToken,
+    /// Id of the [`NodeReplicated`] instance the token belongs to (0 for
+    /// tokens that were not handed out by an instance).
+    nr: usize,
 }
 
 impl ThreadToken {
     /// Creates a new ThreadToken
     ///
+    /// The token doesn't belong to any [`NodeReplicated`] instance, so they
+    /// reject it ([`NodeReplicatedError::InvalidToken`]). Only tokens from
+    /// [`NodeReplicated::register`] can be used with an instance.
+    ///
     /// # Safety
     /// This method should only ever be used for the benchmark harness to create
     /// additional, fake replica implementations. If we had something like `pub(test)` we
     /// should declare it like that instead of just `pub`.
     #[doc(hidden)]
     pub fn new(rid: ReplicaId, rtkn: ReplicaToken) -> Self {
-        Self { rid, rtkn }
+        Self { rid, rtkn, nr: 0 }
     }
 }
 
@@ -276,11 +410,124 @@ impl<'f> Drop for AffinityToken<'f> {
     }
 }
 
+/// User provided function that returns the NUMA node of the calling thread.
+///
+/// See also [`NodeReplicated::set_node_lookup`].
+type NodeLookupFn = dyn Fn() -> usize + Send + Sync;
+
+/// User provided function that maps a NUMA node to the replica that serves
+/// threads on it.
+///
+/// See also [`NodeReplicated::set_node_mapping`].
+type NodeMappingFn = dyn Fn(usize) -> ReplicaId + Send + Sync;
+
+/// Finds the replica that is local to the calling thread (see
+/// [`NodeReplicated::register_local`]).
+struct Locality {
+    /// Returns the node of the calling thread, if `None` we use
+    /// [`numa::current_node`] on Linux and node 0 elsewhere.
+    node_lookup: Option<Box<NodeLookupFn>>,
+    /// Maps nodes to replicas, if `None` node `n` maps to replica
+    /// `n % num_replicas`.
+    node_mapping: Option<Box<NodeMappingFn>>,
+    /// Number of replicas the instance was created with.
+    num_replicas: usize,
+}
+
+impl Locality {
+    fn new(num_replicas: NonZeroUsize) -> Self {
+        Self {
+            node_lookup: None,
+            node_mapping: None,
+            num_replicas: num_replicas.get(),
+        }
+    }
+
+    /// Returns the replica that is local to the calling thread.
+    fn local_replica(&self) -> ReplicaId {
+        let node = match &self.node_lookup {
+            Some(node_lookup) => node_lookup(),
+            #[cfg(all(feature = "std", target_os = "linux"))]
+            None => numa::current_node(),
+            #[cfg(not(all(feature = "std", target_os = "linux")))]
+            None => 0,
+        };
+
+        match &self.node_mapping {
+            Some(node_mapping) => node_mapping(node),
+            None => node % self.num_replicas,
+        }
+    }
+}
+
 /// Errors that can be encountered by interacting with [`NodeReplicated`].
 #[derive(Debug)]
 pub enum NodeReplicatedError {
     /// Not enough memory to create a [`NodeReplicated`] instance.
     OutOfMemory,
+    /// The [`Log`] can't support any more replicas.
+    TooManyReplicas,
+    /// There is no (active) replica with the given [`ReplicaId`].
+    InvalidReplica,
+    /// The replica still has registered threads or is the last active replica.
+    ReplicaInUse,
+    /// The replica is poisoned because [`Dispatch::dispatch_mut`] panicked on
+    /// it (see [`ReplicaError::Poisoned`]).
+    Poisoned(ReplicaId),
+    /// The operation panicked on another replica and was skipped by all
+    /// replicas (see [`ReplicaError::Skipped`]). The replica of the thread
+    /// is not affected.
+    Skipped,
+    /// The write-ahead log could not be opened or recovered, or a batch of
+    /// mutable operations could not be written to it. In the latter case, the
+    /// operation is applied but may not be durable.
+    Wal(WalError),
+    /// The image of a [`Checkpoint`] is invalid or the checkpoint is ahead of
+    /// the write-ahead log.
+    InvalidCheckpoint,
+    /// The operation did not complete within its spin budget because the
+    /// replica with the given [`ReplicaId`] did not make progress (see
+    /// [`NodeReplicated::execute_mut_timeout`]). A mutable operation that
+    /// timed out may still be applied later.
+    Stalled(ReplicaId),
+    /// The [`ThreadToken`] was not handed out by this instance or is no
+    /// longer registered (see [`NodeReplicated::register`]).
+    InvalidToken,
+    /// The configuration passed to [`NodeReplicatedBuilder`] is unusable, the
+    /// string says why.
+    InvalidConfig(&'static str),
+    /// The instance follows a leader (see [`NodeReplicated::follow`]) and
+    /// doesn't accept mutable operations.
+    ReadOnly,
+}
+
+/// The error of [`NodeReplicated::try_execute_mut_batch`].
+///
+/// Operations of a batch are applied in rounds, so some of them may already
+/// be applied when an error occurs. A caller that retries the batch must
+/// skip those.
+#[derive(Debug)]
+pub struct BatchError<R> {
+    /// Why the batch failed.
+    pub error: NodeReplicatedError,
+    /// The responses of the operations that completed, in order. The
+    /// operation at index `responses.len()` is the first one that failed.
+    pub responses: Vec<R>,
+    /// The number of operations that were taken from the batch. Operations
+    /// from `responses.len()` up to here failed but may still have been
+    /// applied by the other replicas (except for the one that panicked). The
+    /// remaining operations were not applied.
+    pub submitted: usize,
+}
+
+impl<R> From<NodeReplicatedError> for BatchError<R> {
+    fn from(error: NodeReplicatedError) -> Self {
+        BatchError {
+            error,
+            responses: Vec::new(),
+            submitted: 0,
+        }
+    }
 }
 
 impl From<core::alloc::AllocError> for NodeReplicatedError {
@@ -295,6 +542,18 @@ impl From<alloc::collections::TryReserveError> for NodeReplicatedError {
     }
 }
 
+impl From<WalError> for NodeReplicatedError {
+    fn from(e: WalError) -> Self {
+        NodeReplicatedError::Wal(e)
+    }
+}
+
+impl From<ConfigError> for NodeReplicatedError {
+    fn from(e: ConfigError) -> Self {
+        NodeReplicatedError::InvalidConfig(e.0)
+    }
+}
+
 /// The "main" type of NR which users interact with.
 ///
 /// It is used to wrap a single threaded data-structure that implements
@@ -306,8 +565,69 @@ impl From<alloc::collections::TryReserveError> for NodeReplicatedError {
 /// which are behind automatically.
 pub struct NodeReplicated<D: Dispatch + Sync> {
     log: Log<D::WriteOperation>,
-    replicas: Vec<Box<Replica<D>>>,
+    /// Replicas indexed by their [`ReplicaId`] (one entry for every replica
+    /// the log supports, null if there never was a replica with the given
+    /// id).
+    ///
+    /// Replicas are only deallocated once the [`NodeReplicated`] is dropped.
+    /// Removed replicas are retired and later revived in place by
+    /// [`NodeReplicated::add_replica`], so references to them stay valid. Their
+    /// data-structure and thread contexts are freed in the meantime.
+    replicas: Vec<AtomicPtr<Replica<D>>>,
+    /// Serializes [`NodeReplicated::add_replica`] and
+    /// [`NodeReplicated::remove_replica`].
+    membership: AtomicBool,
     affinity_mngr: AffinityManager,
+    locality: Locality,
+    /// Write-ahead log shared by all replicas (if operations are persisted,
+    /// see [`NodeReplicated::recover_from`]).
+    wal: Option<Arc<Wal<D::WriteOperation>>>,
+    /// Number of operations that were applied to the data-structure before
+    /// the first entry of `log`, i.e., the operations that were recovered or
+    /// restored from a [`Checkpoint`].
+    base: usize,
+    /// Set while the instance follows a leader (see [`NodeReplicated::follow`]).
+    following: AtomicBool,
+    /// Unique id of this instance, stamped into the [`ThreadToken`]s it hands
+    /// out.
+    id: usize,
+    /// Configuration of all replicas (including the ones added with
+    /// [`NodeReplicated::add_replica`]).
+    config: ReplicaConfig,
+    /// Evicts replicas that lag behind (if enabled with
+    /// [`NodeReplicatedBuilder::evict_after`]).
+    eviction: Option<Eviction<D>>,
+    _replicas: PhantomData<Box<Replica<D>>>,
+}
+
+/// Policy for replicas that lag behind (see
+/// [`NodeReplicatedBuilder::evict_after`]).
+struct Eviction<D> {
+    /// A replica is evicted once it lags more than this many entries behind
+    /// the log.
+    max_lag: usize,
+    /// Copies the data-structure of a healthy replica to rebuild an evicted
+    /// one.
+    clone: fn(&D) -> D,
+}
+
+/// Id of the next [`NodeReplicated`] instance (ids start at 1, see
+/// [`ThreadToken`]).
+static NEXT_INSTANCE_ID: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(1);
+
+impl<D> Drop for NodeReplicated<D>
+where
+    D: Dispatch + Sync,
+{
+    fn drop(&mut self) {
+        for replica in self.replicas.iter() {
+            let ptr = replica.load(Ordering::Acquire);
+            if !ptr.is_null() {
+                // Safety: Allocated with `Box::into_raw` and never freed before.
+                drop(unsafe { Box::from_raw(ptr) });
+            }
+        }
+    }
 }
 
 impl<D> NodeReplicated<D>
@@ -320,7 +640,9 @@ where
     ///
     /// # Arguments
     /// - `num_replicas`: How many replicas you want to create. Typically the
-    ///   number of NUMA nodes in your system.
+    ///   number of NUMA nodes in your system. Up to [`MAX_REPLICAS_PER_LOG`]
+    ///   replicas (or `num_replicas` if that is more) can be active at the
+    ///   same time, see [`NodeReplicated::add_replica`].
     /// - `chg_mem_affinity`: A user-provided function that is called whenever
     ///   the code operates on a certain [`Replica`] that is not local to the
     ///   thread that we're running on (can happen if a replica falls behind and
@@ -387,237 +709,1236 @@ where
         chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
         log_size: usize,
     ) -> Result<Self, NodeReplicatedError> {
-        assert!(num_replicas.get() < MAX_REPLICAS_PER_LOG);
-        let affinity_mngr = AffinityManager::new(Box::try_new(chg_mem_affinity)?);
-        let log = Log::new_with_bytes(log_size, ());
-
-        let mut replicas = Vec::new();
-        replicas.try_reserve(num_replicas.get())?;
-
-        for replica_id in 0..num_replicas.get() {
-            let log_token = log
-                .register()
-                .expect("Succeeds (num_replicas < MAX_REPLICAS_PER_LOG)");
-
-            let r = {
-                // Allocate the replica on the proper NUMA node
-                let _aff_tkn = affinity_mngr.switch(replica_id);
-                Box::try_new(Replica::new(log_token))?
-                // aff_tkn is dropped here
-            };
-
-            // This succeeds, we did `try_reserve` earlier so no `try_push` is
-            // necessary.
-            replicas.push(r);
-        }
-
-        Ok(NodeReplicated {
-            replicas,
-            log,
-            affinity_mngr,
-        })
+        Self::builder()
+            .replicas(num_replicas.get())
+            .affinity(chg_mem_affinity)
+            .log_bytes(log_size)
+            .build()
     }
-}
 
-impl<D> NodeReplicated<D>
-where
-    D: Clone + Dispatch + Sized + Sync,
-{
-    /// Same as [`NodeReplicated::new`], but provide the initial data-structure
-    /// `ds` (which may not have a [`Default`] constructor).
-    ///
-    /// `ds` will be cloned for each replica.
-    pub fn with_data(
-        _num_replicas: NonZeroUsize,
-        _chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
-        _ds: D,
-    ) -> Result<Self, NodeReplicatedError> {
-        unimplemented!("complete me")
+    /// Returns a [`NodeReplicatedBuilder`] that creates the replicas with the
+    /// [`Default`] constructor of `D`.
+    pub fn builder() -> NodeReplicatedBuilder<D> {
+        NodeReplicatedBuilder::default()
     }
 }
 
 impl<D> NodeReplicated<D>
 where
-    D: Dispatch + Sized + Sync,
+    D: Default + Dispatch + Sized + Sync,
+    D::WriteOperation: LogSerialize,
 {
-    /// Registers a thread with a given replica in the [`NodeReplicated`]
-    /// data-structure. Returns an Option containing a [`ThreadToken`] if the
-    /// registration was successful. None if the registration failed.
-    ///
-    /// The [`ThreadToken`] is used to identify the thread to issue the
-    /// operation for subsequent [`NodeReplicated::execute`] and
-    /// [`NodeReplicated::execute_mut`] calls.
-    ///
-    /// # Arguments
+    /// Same as [`NodeReplicated::new`], but all mutable operations are
+    /// persisted in a write-ahead log stored in `segment` (see [`wal`]).
     ///
-    /// - `replica_id`: Which replica the thread should be registered with. This
-    /// should be less than the `num_replicas` argument provided in the
-    /// constructor (see [`NodeReplicated::new`]). In most cases, `replica_id`
-    /// will correspond to the NUMA node that the thread is running on.
+    /// The operations that `segment` already contains (e.g., written by an
+    /// earlier instance before the process crashed) are replayed on top of
+    /// the [`Default`] data-structure of every replica, before new operations
+    /// are appended. Operations that panicked (see
+    /// [`NodeReplicatedError::Poisoned`]) are skipped. The combiner writes
+    /// operations to `segment` before their responses are returned, `policy`
+    /// decides how often the segment is synced.
     ///
     /// # Example
-    ///
     /// ```
-    /// #![feature(generic_associated_types)]
+    /// # #![feature(generic_associated_types)]
     /// use core::num::NonZeroUsize;
     /// use node_replication::nr::NodeReplicated;
-    /// use node_replication::nr::Dispatch;
+    /// # use node_replication::nr::Dispatch;
+    /// use node_replication::nr::wal::{Segment, SyncPolicy, WalError};
+    /// use std::sync::{Arc, Mutex};
     ///
     /// #[derive(Default)]
-    /// struct Void;
-    /// impl Dispatch for Void {
-    ///     type ReadOperation<'rop> = ();
-    ///     type WriteOperation = ();
-    ///     type Response = ();
+    /// struct Counter(u64);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = u64;
+    /// #     type Response = u64;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += op;
+    /// #         self.0
+    /// #     }
+    /// # }
     ///
-    ///     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {}
-    ///     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {}
+    /// // A segment that is kept in memory (use `FileSegment` to store it in a file).
+    /// #[derive(Clone, Default)]
+    /// struct MemSegment(Arc<Mutex<Vec<u8>>>);
+    /// impl Segment for MemSegment {
+    ///     fn append(&mut self, buf: &[u8]) -> Result<(), WalError> {
+    ///         Ok(self.0.lock().unwrap().extend_from_slice(buf))
+    ///     }
+    ///     fn sync(&mut self) -> Result<(), WalError> {
+    ///         Ok(())
+    ///     }
+    ///     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), WalError> {
+    ///         Ok(buf.extend_from_slice(&self.0.lock().unwrap()))
+    ///     }
+    ///     fn truncate(&mut self, len: usize) -> Result<(), WalError> {
+    ///         Ok(self.0.lock().unwrap().truncate(len))
+    ///     }
     /// }
     ///
     /// let replicas = NonZeroUsize::new(2).unwrap();
-    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let segment = MemSegment::default();
+    /// let nrht = NodeReplicated::<Counter>::recover_from(replicas, |_| { 0 }, segment.clone(), SyncPolicy::Always).unwrap();
     /// let ttkn = nrht.register(0).unwrap();
-    /// assert!(nrht.register(replicas.get()).is_none());
+    /// nrht.execute_mut(40, ttkn);
+    /// nrht.execute_mut(2, ttkn);
+    /// drop(nrht);
+    ///
+    /// let nrht = NodeReplicated::<Counter>::recover_from(replicas, |_| { 0 }, segment, SyncPolicy::Always).unwrap();
+    /// let ttkn = nrht.register(1).unwrap();
+    /// assert_eq!(nrht.execute((), ttkn), 42);
     /// ```
-    pub fn register(&self, replica_id: ReplicaId) -> Option<ThreadToken> {
-        if replica_id < self.replicas.len() {
-            let rtkn = self.replicas[replica_id].register()?;
-            Some(ThreadToken::new(replica_id, rtkn))
-        } else {
-            None
-        }
+    pub fn recover_from(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        segment: impl Segment + 'static,
+        policy: SyncPolicy,
+    ) -> Result<Self, NodeReplicatedError> {
+        let (wal, ops) = Wal::open(Box::try_new(segment)?, policy)?;
+        let mut nr = Self::with_replica_factory(
+            num_replicas,
+            chg_mem_affinity,
+            log::DEFAULT_LOG_BYTES,
+            Some(Arc::try_new(wal)?),
+            |log_token| {
+                let mut d = D::default();
+                for op in ops.iter().flatten() {
+                    d.apply_mut(op.clone());
+                }
+                Ok(Replica::with_data(log_token, d))
+            },
+        )?;
+        nr.base = ops.len();
+        Ok(nr)
     }
 
-    fn try_execute_mut<'a>(
-        &'a self,
-        op: <D as Dispatch>::WriteOperation,
-        tkn: ThreadToken,
-        cl: Option<CombinerLock<'a, D>>,
-    ) -> Result<<D as Dispatch>::Response, ReplicaError<D>> {
-        if let Some(combiner_lock) = cl {
-            // We expect to have already enqueued the op (it's a re-try since have the combiner lock),
-            // so technically its not needed to supply it again (but we currently do it anyways...)
-            self.replicas[tkn.rid].execute_mut_locked(&self.log, op, tkn.rtkn, combiner_lock)
-        } else {
-            self.replicas[tkn.rid].execute_mut(&self.log, op, tkn.rtkn)
-        }
+    /// Same as [`NodeReplicated::recover_from`] with the write-ahead log
+    /// stored in the file at `path` (see [`wal::FileSegment`]). The file is
+    /// created if it doesn't exist yet.
+    #[cfg(feature = "std")]
+    pub fn recover(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        path: impl AsRef<std::path::Path>,
+        policy: SyncPolicy,
+    ) -> Result<Self, NodeReplicatedError> {
+        let segment = wal::FileSegment::open(path)?;
+        Self::recover_from(num_replicas, chg_mem_affinity, segment, policy)
     }
+}
 
-    /// Executes a mutable operation against the data-structure.
-    ///
-    /// Thanks to the [`Log`] that [`NodeReplicated`] uses, all replicas will
-    /// execute all mutable operations in the same order.
+impl<D> NodeReplicated<D>
+where
+    D: Dispatch + Sized + Sync,
+    D::WriteOperation: LogSerialize,
+{
+    /// Applies the operations that a leader streams through `transport` (see
+    /// [`transport`]) until the leader closes it.
     ///
-    ///  This method is similar to the one found in [`Replica::execute_mut`],
-    /// but in addition, it handles liveness issues due to lagging replicas
-    /// (which a single replica can not).
+    /// The instance has to start with the same state as the leader. It is
+    /// read-only while it follows: mutable operations fail with
+    /// [`NodeReplicatedError::ReadOnly`] (or panic), reads see the operations
+    /// of the leader in log order. Once `follow` returns, the instance
+    /// accepts mutable operations again, e.g., to take over from a leader
+    /// that is gone.
     ///
-    /// # Arguments
-    /// - `op`: Which operation to execute.
-    /// - `tkn`: Which thread executes the operation (see also
-    ///   [`NodeReplicated::register`]).
+    /// Blocks the calling thread, which uses the thread slot of `tkn` to
+    /// apply the operations.
+    pub fn follow(
+        &self,
+        mut transport: impl LogTransport,
+        tkn: ThreadToken,
+    ) -> Result<(), NodeReplicatedError> {
+        self.check_token(tkn)?;
+        if self
+            .following
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(NodeReplicatedError::ReadOnly);
+        }
+
+        let mut buf = Vec::new();
+        let mut ops = Vec::new();
+        let r = loop {
+            let open = match transport.recv(&mut buf) {
+                Ok(open) => open,
+                Err(e) => break Err(NodeReplicatedError::Wal(e)),
+            };
+            match wal::decode_records(&buf, &mut ops) {
+                Ok(used) => drop(buf.drain(..used)),
+                Err(e) => break Err(NodeReplicatedError::Wal(e)),
+            }
+            if !ops.is_empty() {
+                if let Err(e) = self.append_batch(ops.drain(..), tkn) {
+                    break Err(e.error);
+                }
+            }
+
+            if !open {
+                // The leader closed the transport in the middle of a record.
+                break if buf.is_empty() {
+                    Ok(())
+                } else {
+                    Err(NodeReplicatedError::Wal(WalError::Corrupt))
+                };
+            }
+        };
+
+        self.following.store(false, Ordering::Release);
+        r
+    }
+}
+
+impl<D> NodeReplicated<D>
+where
+    D: Snapshot + Dispatch + Sized + Sync,
+{
+    /// Takes a [`Checkpoint`] of the data-structure.
     ///
-    /// # Flow
-    /// Eventually, this method calls [`Replica::execute_mut`] which will call
-    /// into [`Dispatch::dispatch_mut`].
+    /// The image is taken from one (healthy) replica after it caught up with
+    /// the [`Log`]. Threads can keep issuing operations in the meantime, only
+    /// the replica we take the image of is briefly blocked. Use
+    /// [`NodeReplicated::restore`] (or [`NodeReplicated::restore_from`] with a
+    /// write-ahead log) to create an instance from the checkpoint.
     ///
     /// # Example
     /// ```
-    /// #![feature(generic_associated_types)]
+    /// # #![feature(generic_associated_types)]
     /// use core::num::NonZeroUsize;
-    /// use node_replication::nr::NodeReplicated;
-    /// use node_replication::nr::Dispatch;
+    /// use node_replication::nr::{Dispatch, NodeReplicated, Snapshot};
+    /// use std::convert::TryInto;
     ///
     /// #[derive(Default)]
-    /// struct Void;
-    /// impl Dispatch for Void {
-    ///     type ReadOperation<'rop> = ();
-    ///     type WriteOperation = usize;
-    ///     type Response = usize;
+    /// struct Counter(u64);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = u64;
+    /// #     type Response = u64;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += op;
+    /// #         self.0
+    /// #     }
+    /// # }
     ///
-    ///     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
-    ///         unreachable!("no read-op is issued")
+    /// impl Snapshot for Counter {
+    ///     fn snapshot(&self, buf: &mut Vec<u8>) {
+    ///         buf.extend_from_slice(&self.0.to_le_bytes());
     ///     }
-    ///     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
-    ///         assert!(op == 99);
-    ///         // "eventually", because if we just do one `execute_mut` call
-    ///         // we won't immediately advance the 2nd replica
-    ///         println!("Having two replicas means I'm (eventually) called twice");
-    ///         0xbeef
+    ///     fn restore(buf: &[u8]) -> Option<Self> {
+    ///         Some(Counter(u64::from_le_bytes(buf.try_into().ok()?)))
     ///     }
     /// }
     ///
     /// let replicas = NonZeroUsize::new(2).unwrap();
-    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let nrht = NodeReplicated::<Counter>::new(replicas, |_| { 0 }).unwrap();
     /// let ttkn = nrht.register(0).unwrap();
+    /// nrht.execute_mut(40, ttkn);
+    /// nrht.execute_mut(2, ttkn);
     ///
-    /// assert_eq!(nrht.execute_mut(99, ttkn), 0xbeef);
+    /// let checkpoint = nrht.checkpoint().unwrap();
+    /// assert_eq!(checkpoint.position, 2);
+    ///
+    /// let restored = NodeReplicated::<Counter>::restore(replicas, |_| { 0 }, &checkpoint).unwrap();
+    /// let ttkn = restored.register(1).unwrap();
+    /// assert_eq!(restored.execute((), ttkn), 42);
     /// ```
-    pub fn execute_mut(
-        &self,
-        op: <D as Dispatch>::WriteOperation,
-        tkn: ThreadToken,
-    ) -> <D as Dispatch>::Response {
-        /// An enum to keep track of a stack of operations we should do on Replicas.
-        ///
-        /// e.g., either `Sync` an out-of-date, behind replica, or call `execute_locked` or
-        /// `execute_mut_locked` to resume the operation with a combiner lock.
-        enum ResolveOp<'a, D: core::marker::Sync + Dispatch + Sized> {
-            /// Resumes a replica that earlier returned with an Error (and the CombinerLock).
-            Exec(Option<CombinerLock<'a, D>>),
-            /// Indicates need to [`Replica::sync()`] a replica with the given ID.
-            Sync(ReplicaId),
-        }
+    pub fn checkpoint(&self) -> Result<Checkpoint, NodeReplicatedError> {
+        self.with_membership(|| {
+            let (replica, _active) = self.enter_healthy()?;
+            replica.sync(&self.log);
 
-        let mut q = ArrayVec::<ResolveOp<D>, { crate::log::MAX_REPLICAS_PER_LOG }>::new();
-        loop {
-            match q.pop().unwrap_or(ResolveOp::Exec(None)) {
-                ResolveOp::Exec(cl) => match self.try_execute_mut(op.clone(), tkn, cl) {
-                    Ok(resp) => {
-                        assert!(q.is_empty());
-                        return resp;
-                    }
-                    Err(ReplicaError::NoLogSpace(stuck_ridx, cl_acq)) => {
-                        assert_ne!(stuck_ridx, tkn.rid);
-                        q.push(ResolveOp::Exec(Some(cl_acq)));
-                        q.push(ResolveOp::Sync(stuck_ridx));
-                    }
-                    Err(ReplicaError::GcFailed(stuck_ridx)) => {
-                        {
-                            assert_ne!(stuck_ridx, tkn.rid);
-                            let _aftkn = self.affinity_mngr.switch(stuck_ridx);
-                            self.replicas[stuck_ridx].sync(&self.log);
-                            // Affinity is reverted here, _aftkn is dropped.
-                        }
-                        return self.replicas[tkn.rid]
-                            .get_response(&self.log, tkn.rtkn.tid())
-                            .expect("GcFailed has to produced a response");
-                    }
-                },
-                ResolveOp::Sync(ridx) => {
-                    // Holds trivially because of all the other asserts in this function
-                    debug_assert_ne!(ridx, tkn.rid);
-                    //warn!("execute_mut ResolveOp::Sync {}", ridx);
-                    let _aftkn = self.affinity_mngr.switch(ridx);
-                    self.replicas[ridx].try_sync(&self.log);
-                    // _aftkn is dropped here, reverting affinity change
-                }
-            }
-        }
+            let mut data = Vec::new();
+            let position = replica.with_position(&self.log, |ltail, d| {
+                d.snapshot(&mut data);
+                self.base + ltail
+            });
+            Ok(Checkpoint {
+                position,
+                data,
+                log_entries: self.log.slog.len(),
+                config: self.config,
+            })
+        })
     }
 
-    fn try_execute<'a, 'rop>(
-        &'a self,
-        op: <D as Dispatch>::ReadOperation<'rop>,
-        tkn: ThreadToken,
-        cl: Option<CombinerLock<'a, D>>,
-    ) -> Result<<D as Dispatch>::Response, (ReplicaError<D>, <D as Dispatch>::ReadOperation<'rop>)>
-    {
-        if let Some(combiner_lock) = cl {
-            self.replicas[tkn.rid].execute_locked(&self.log, op, tkn.rtkn, combiner_lock)
-        } else {
-            self.replicas[tkn.rid].execute(&self.log, op, tkn.rtkn)
-        }
+    /// Creates a new instance (like [`NodeReplicated::new`]) with the state
+    /// of `checkpoint` on all replicas (see [`NodeReplicated::checkpoint`]).
+    ///
+    /// The log and the replicas are sized like the ones of the instance the
+    /// checkpoint was taken from ([`Checkpoint::log_entries`] and
+    /// [`Checkpoint::config`]).
+    pub fn restore(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        checkpoint: &Checkpoint,
+    ) -> Result<Self, NodeReplicatedError> {
+        let mut nr =
+            Self::from_checkpoint(num_replicas, chg_mem_affinity, checkpoint, None, |_d| {})?;
+        nr.base = checkpoint.position;
+        Ok(nr)
+    }
+
+    /// Same as [`NodeReplicated::restore`], but the operations are persisted
+    /// in a write-ahead log stored in `segment` (like
+    /// [`NodeReplicated::recover_from`]).
+    ///
+    /// Only the records of `segment` after the [`Checkpoint::position`] of
+    /// `checkpoint` are replayed on top of the image. Fails if `segment` has
+    /// fewer records.
+    pub fn restore_from(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        checkpoint: &Checkpoint,
+        segment: impl Segment + 'static,
+        policy: SyncPolicy,
+    ) -> Result<Self, NodeReplicatedError>
+    where
+        D::WriteOperation: LogSerialize,
+    {
+        let (wal, ops) = Wal::open(Box::try_new(segment)?, policy)?;
+        let suffix = ops
+            .get(checkpoint.position..)
+            .ok_or(NodeReplicatedError::InvalidCheckpoint)?;
+
+        let mut nr = Self::from_checkpoint(
+            num_replicas,
+            chg_mem_affinity,
+            checkpoint,
+            Some(Arc::try_new(wal)?),
+            |d| {
+                for op in suffix.iter().flatten() {
+                    d.apply_mut(op.clone());
+                }
+            },
+        )?;
+        nr.base = ops.len();
+        Ok(nr)
+    }
+
+    /// Creates `num_replicas` replicas from the image of `checkpoint` (with
+    /// the log size and configuration of the instance it was taken from).
+    /// `replay` is called on every replica's copy of the image before the
+    /// replica is created.
+    fn from_checkpoint(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        checkpoint: &Checkpoint,
+        wal: Option<Arc<Wal<D::WriteOperation>>>,
+        replay: impl Fn(&mut D),
+    ) -> Result<Self, NodeReplicatedError> {
+        let max_replicas = core::cmp::max(num_replicas.get(), MAX_REPLICAS_PER_LOG);
+        let config = checkpoint.config;
+        let log =
+            Log::new_with_entries_and_config(checkpoint.log_entries, max_replicas, config, ())
+                .map_err(|_| NodeReplicatedError::InvalidCheckpoint)?;
+        let affinity_mngr = AffinityManager::new(Box::try_new(chg_mem_affinity)?);
+
+        Self::from_parts(
+            num_replicas,
+            affinity_mngr,
+            Locality::new(num_replicas),
+            log,
+            config,
+            wal,
+            |log_token| {
+                let mut d =
+                    D::restore(&checkpoint.data).ok_or(NodeReplicatedError::InvalidCheckpoint)?;
+                replay(&mut d);
+                Ok(Replica::with_config(log_token, config, d)?)
+            },
+        )
+    }
+}
+
+impl<D> NodeReplicated<D>
+where
+    D: Clone + Dispatch + Sized + Sync,
+{
+    /// Same as [`NodeReplicated::new`], but provide the initial data-structure
+    /// `ds` (which may not have a [`Default`] constructor).
+    ///
+    /// `ds` will be cloned for each replica. Every clone is made while the
+    /// memory affinity is set to the replica it belongs to (see
+    /// [`AffinityChange::Replica`]), so the replica state ends up on the right
+    /// NUMA node.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Clone)]
+    /// struct Seeded(usize);
+    /// # impl Dispatch for Seeded {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = usize;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         unreachable!("no mutable op is issued")
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::with_data(replicas, |_| { 0 }, Seeded(42)).unwrap();
+    /// let ttkn = nrht.register(1).unwrap();
+    /// assert_eq!(nrht.execute((), ttkn), 42);
+    /// ```
+    pub fn with_data(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        ds: D,
+    ) -> Result<Self, NodeReplicatedError> {
+        Self::with_replica_factory(
+            num_replicas,
+            chg_mem_affinity,
+            log::DEFAULT_LOG_BYTES,
+            None,
+            |log_token| Ok(Replica::with_data(log_token, ds.clone())),
+        )
+    }
+
+    /// Adds a replica to a live [`NodeReplicated`] instance and returns its
+    /// [`ReplicaId`].
+    ///
+    /// The new replica is bootstrapped from a copy of an active replica and
+    /// starts consuming the [`Log`] from the position that replica has
+    /// reached. Threads on other replicas can keep issuing operations in the
+    /// meantime (only the replica we copy from is briefly blocked).
+    ///
+    /// Slots of replicas removed with [`NodeReplicated::remove_replica`] are
+    /// re-used. The copy is made while the memory affinity is set to the new
+    /// replica (see [`AffinityChange::Replica`]).
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default, Clone)]
+    /// struct Counter(usize);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = usize;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += 1;
+    /// #         self.0
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(1).unwrap();
+    /// let nrht = NodeReplicated::<Counter>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    /// nrht.execute_mut((), ttkn);
+    ///
+    /// let rid = nrht.add_replica().unwrap();
+    /// let ttkn = nrht.register(rid).unwrap();
+    /// assert_eq!(nrht.execute((), ttkn), 1);
+    /// ```
+    pub fn add_replica(&self) -> Result<ReplicaId, NodeReplicatedError> {
+        self.with_membership(|| {
+            let (source, _active) = self.enter_healthy()?;
+
+            // The slot of a poisoned replica is only free once it was removed.
+            let reuse = |log_token: &log::LogToken| match self.try_replica(log_token.0 - 1) {
+                Some(r) => r.is_retired(),
+                None => true,
+            };
+            source
+                .fork(&self.log, reuse, |log_token, data| {
+                    let replica_id = log_token.0 - 1;
+                    // Make the copy on the proper NUMA node
+                    let _aff_tkn = self.affinity_mngr.switch(replica_id);
+
+                    if let Some(retired) = self.try_replica(replica_id) {
+                        retired.revive(log_token, data.clone());
+                    } else {
+                        // `self.config` was validated when the instance was created.
+                        let r = Replica::allocate(log_token, self.config, data.clone())
+                            .with_wal(self.wal.clone())
+                            .with_waiter(self.log.waiter);
+                        let r = match Box::try_new(r) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                // Don't let GC wait for a replica that doesn't exist.
+                                self.log.unregister(&log::LogToken(replica_id + 1));
+                                return Err(e.into());
+                            }
+                        };
+                        self.replicas[replica_id].store(Box::into_raw(r), Ordering::Release);
+                    }
+
+                    Ok(replica_id)
+                })
+                .unwrap_or(Err(NodeReplicatedError::TooManyReplicas))
+        })
+    }
+}
+
+impl<D> NodeReplicated<D>
+where
+    D: Dispatch + Sized + Sync,
+{
+    /// Allocates the [`Log`] and `num_replicas` replicas for a new
+    /// [`NodeReplicated`] instance.
+    ///
+    /// `mk_replica` is invoked once per replica while the memory affinity is
+    /// changed to the replica that is being created. All replicas persist
+    /// their operations to `wal` (if there is one).
+    fn with_replica_factory(
+        num_replicas: NonZeroUsize,
+        chg_mem_affinity: impl Fn(AffinityChange) -> usize + Send + Sync + 'static,
+        log_size: usize,
+        wal: Option<Arc<Wal<D::WriteOperation>>>,
+        mk_replica: impl FnMut(log::LogToken) -> Result<Replica<D>, NodeReplicatedError>,
+    ) -> Result<Self, NodeReplicatedError> {
+        let affinity_mngr = AffinityManager::new(Box::try_new(chg_mem_affinity)?);
+        let max_replicas = core::cmp::max(num_replicas.get(), MAX_REPLICAS_PER_LOG);
+        let log = Log::new_with_replicas(log_size, max_replicas, ());
+
+        Self::from_parts(
+            num_replicas,
+            affinity_mngr,
+            Locality::new(num_replicas),
+            log,
+            ReplicaConfig::default(),
+            wal,
+            mk_replica,
+        )
+    }
+
+    /// Creates `num_replicas` replicas configured with `config` that share
+    /// `log` (see [`NodeReplicated::with_replica_factory`]).
+    fn from_parts(
+        num_replicas: NonZeroUsize,
+        affinity_mngr: AffinityManager,
+        locality: Locality,
+        log: Log<D::WriteOperation>,
+        config: ReplicaConfig,
+        wal: Option<Arc<Wal<D::WriteOperation>>>,
+        mut mk_replica: impl FnMut(log::LogToken) -> Result<Replica<D>, NodeReplicatedError>,
+    ) -> Result<Self, NodeReplicatedError> {
+        let max_replicas = log.max_replicas();
+        let mut replicas = Vec::new();
+        replicas.try_reserve(max_replicas)?;
+        // This succeeds, we did `try_reserve` earlier so no `try_push` is
+        // necessary.
+        replicas.resize_with(max_replicas, || AtomicPtr::new(ptr::null_mut()));
+
+        // Replicas that are already allocated are freed (on drop) in case we
+        // fail to allocate one of the others.
+        let nr = NodeReplicated {
+            replicas,
+            membership: AtomicBool::new(false),
+            log,
+            affinity_mngr,
+            locality,
+            wal,
+            base: 0,
+            following: AtomicBool::new(false),
+            id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
+            config,
+            eviction: None,
+            _replicas: PhantomData,
+        };
+
+        for replica_id in 0..num_replicas.get() {
+            let log_token = nr
+                .log
+                .register()
+                .expect("Succeeds (num_replicas <= max_replicas)");
+
+            let r = {
+                // Allocate the replica on the proper NUMA node
+                let _aff_tkn = nr.affinity_mngr.switch(replica_id);
+                Box::try_new(
+                    mk_replica(log_token)?
+                        .with_wal(nr.wal.clone())
+                        .with_waiter(nr.log.waiter),
+                )?
+                // aff_tkn is dropped here
+            };
+
+            nr.replicas[replica_id].store(Box::into_raw(r), Ordering::Release);
+        }
+
+        Ok(nr)
+    }
+
+    /// Returns the replica with the given id (if there is one).
+    fn try_replica(&self, replica_id: ReplicaId) -> Option<&Replica<D>> {
+        let ptr = self.replicas.get(replica_id)?.load(Ordering::Acquire);
+        // Safety: Replicas are not deallocated before `self` is dropped.
+        unsafe { ptr.as_ref() }
+    }
+
+    /// Checks that `tkn` was handed out by this instance and is still
+    /// registered.
+    fn check_token(&self, tkn: ThreadToken) -> Result<(), NodeReplicatedError> {
+        let registered = tkn.nr == self.id
+            && matches!(self.try_replica(tkn.rid), Some(r) if r.is_registered(tkn.rtkn));
+        if registered {
+            Ok(())
+        } else {
+            Err(NodeReplicatedError::InvalidToken)
+        }
+    }
+
+    /// Checks that the instance accepts mutable operations, i.e., it doesn't
+    /// follow a leader.
+    fn check_writable(&self) -> Result<(), NodeReplicatedError> {
+        if self.following.load(Ordering::Acquire) {
+            Err(NodeReplicatedError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns an active replica that is neither poisoned nor dormant.
+    ///
+    /// Fails if all active replicas are poisoned or dormant.
+    fn healthy_replica(&self) -> Result<&Replica<D>, NodeReplicatedError> {
+        let (rid, replica) = (0..self.replicas.len())
+            .filter_map(|rid| Some((rid, self.try_replica(rid)?)))
+            .filter(|(_rid, r)| !r.is_retired() && !r.is_dormant())
+            .min_by_key(|(_rid, r)| r.is_poisoned())
+            .ok_or(NodeReplicatedError::InvalidReplica)?;
+        if replica.is_poisoned() {
+            return Err(NodeReplicatedError::Poisoned(rid));
+        }
+        Ok(replica)
+    }
+
+    /// Returns a healthy replica (see [`NodeReplicated::healthy_replica`])
+    /// that isn't evicted while the returned guard lives, e.g., to copy its
+    /// state.
+    fn enter_healthy(&self) -> Result<(&Replica<D>, Active<'_, D>), NodeReplicatedError> {
+        loop {
+            let replica = self.healthy_replica()?;
+            if let Some(active) = replica.enter() {
+                return Ok((replica, active));
+            }
+        }
+    }
+
+    /// Returns the replica with the given id, panics if there is none.
+    fn replica(&self, replica_id: ReplicaId) -> &Replica<D> {
+        self.try_replica(replica_id)
+            .expect("No replica with this ReplicaId")
+    }
+
+    /// Runs `f` while holding the lock that serializes changes to the set of
+    /// replicas.
+    fn with_membership<R>(&self, f: impl FnOnce() -> R) -> R {
+        let mut iteration = 1;
+        while self
+            .membership
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.log.waiter.wait(iteration);
+            iteration += 1;
+        }
+
+        let r = f();
+        self.membership.store(false, Ordering::Release);
+        self.log.waiter.notify();
+        r
+    }
+
+    /// Removes a replica from a live [`NodeReplicated`] instance.
+    ///
+    /// The replica is retired: it stops consuming the [`Log`] and garbage
+    /// collection of the log no longer waits for it. Its data-structure is
+    /// dropped once threads that copy or checkpoint it are done. A retired
+    /// replica can be brought back with [`NodeReplicated::add_replica`].
+    ///
+    /// All threads of the replica have to be unregistered (see
+    /// [`NodeReplicated::unregister`]) and at least one other replica that is
+    /// not poisoned has to remain active, otherwise
+    /// [`NodeReplicatedError::ReplicaInUse`] is returned. Removing a poisoned
+    /// replica and adding it again rebuilds it from a healthy replica.
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// # impl Dispatch for Void {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = ();
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {}
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {}
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(1).unwrap();
+    /// assert!(nrht.remove_replica(1).is_err());
+    ///
+    /// nrht.unregister(ttkn);
+    /// assert!(nrht.remove_replica(1).is_ok());
+    /// assert!(nrht.register(1).is_none());
+    /// ```
+    pub fn remove_replica(&self, replica_id: ReplicaId) -> Result<(), NodeReplicatedError> {
+        self.with_membership(|| {
+            let replica = self
+                .try_replica(replica_id)
+                .filter(|r| !r.is_retired())
+                .ok_or(NodeReplicatedError::InvalidReplica)?;
+
+            let healthy = (0..self.replicas.len())
+                .filter(|rid| *rid != replica_id)
+                .filter_map(|rid| self.try_replica(rid))
+                .filter(|r| !r.is_retired() && !r.is_poisoned() && !r.is_dormant())
+                .count();
+            if healthy == 0 || !replica.retire(&self.log) {
+                return Err(NodeReplicatedError::ReplicaInUse);
+            }
+
+            replica.release();
+            Ok(())
+        })
+    }
+
+    /// Registers a thread with a given replica in the [`NodeReplicated`]
+    /// data-structure. Returns an Option containing a [`ThreadToken`] if the
+    /// registration was successful. None if the registration failed.
+    ///
+    /// The [`ThreadToken`] is used to identify the thread to issue the
+    /// operation for subsequent [`NodeReplicated::execute`] and
+    /// [`NodeReplicated::execute_mut`] calls.
+    ///
+    /// # Arguments
+    ///
+    /// - `replica_id`: Which replica the thread should be registered with. This
+    /// should be less than the `num_replicas` argument provided in the
+    /// constructor (see [`NodeReplicated::new`]). In most cases, `replica_id`
+    /// will correspond to the NUMA node that the thread is running on.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// impl Dispatch for Void {
+    ///     type ReadOperation<'rop> = ();
+    ///     type WriteOperation = ();
+    ///     type Response = ();
+    ///
+    ///     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {}
+    ///     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {}
+    /// }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    /// assert!(nrht.register(replicas.get()).is_none());
+    /// ```
+    pub fn register(&self, replica_id: ReplicaId) -> Option<ThreadToken> {
+        let rtkn = self.try_replica(replica_id)?.register()?;
+        Some(ThreadToken {
+            rid: replica_id,
+            rtkn,
+            nr: self.id,
+        })
+    }
+
+    /// Registers the calling thread with the replica that is local to the
+    /// NUMA node it currently runs on.
+    ///
+    /// The node is determined by the function installed with
+    /// [`NodeReplicated::set_node_lookup`] (by default `getcpu` on Linux with
+    /// the `std` feature, node 0 otherwise) and mapped to a replica by the
+    /// function installed with [`NodeReplicated::set_node_mapping`] (by
+    /// default node `n` maps to replica `n % num_replicas`).
+    ///
+    /// Threads should be pinned to a node before they register, otherwise
+    /// they may end up far away from their replica later on.
+    ///
+    /// Fails under the same conditions as [`NodeReplicated::register`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// # impl Dispatch for Void {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = ();
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {}
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {}
+    /// # }
+    ///
+    /// // Two replicas on a machine with four nodes, two nodes share a replica.
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let mut nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// nrht.set_node_lookup(|| 3).unwrap();
+    /// nrht.set_node_mapping(|node| node / 2).unwrap();
+    ///
+    /// let ttkn = nrht.register_local().unwrap();
+    /// nrht.unregister(ttkn);
+    ///
+    /// // Replica 1 hands out the slot again, under a new token.
+    /// let ttkn1 = nrht.register(1).unwrap();
+    /// assert_ne!(ttkn1, ttkn);
+    /// ```
+    pub fn register_local(&self) -> Option<ThreadToken> {
+        self.register(self.locality.local_replica())
+    }
+
+    /// Installs the function that [`NodeReplicated::register_local`] uses to
+    /// find the NUMA node of the calling thread.
+    pub fn set_node_lookup(
+        &mut self,
+        node_lookup: impl Fn() -> usize + Send + Sync + 'static,
+    ) -> Result<(), NodeReplicatedError> {
+        self.locality.node_lookup = Some(Box::try_new(node_lookup)?);
+        Ok(())
+    }
+
+    /// Installs the function that [`NodeReplicated::register_local`] uses to
+    /// map a NUMA node to a replica.
+    ///
+    /// Needed if the replicas don't correspond one-to-one to nodes, e.g.,
+    /// because there are fewer replicas than nodes or replicas were added or
+    /// removed.
+    pub fn set_node_mapping(
+        &mut self,
+        node_mapping: impl Fn(usize) -> ReplicaId + Send + Sync + 'static,
+    ) -> Result<(), NodeReplicatedError> {
+        self.locality.node_mapping = Some(Box::try_new(node_mapping)?);
+        Ok(())
+    }
+
+    /// Unregisters a thread from the [`NodeReplicated`] data-structure.
+    ///
+    /// Afterwards, the slot that the thread occupied in its replica can be
+    /// handed out again by [`NodeReplicated::register`]. This allows thread
+    /// pools that recycle their workers to register more threads over time
+    /// than a replica has slots.
+    ///
+    /// # Arguments
+    /// - `tkn`: The token of the thread that unregisters (see
+    ///   [`NodeReplicated::register`]). It is a bug to use `tkn` (or a copy
+    ///   of it) for anything after this method returns.
+    ///
+    /// # Panics
+    /// If `tkn` is invalid, see [`NodeReplicated::try_unregister`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// # impl Dispatch for Void {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = ();
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {}
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {}
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    /// nrht.unregister(ttkn);
+    ///
+    /// // The slot is handed out again, the old token stays invalid.
+    /// let ttkn0 = nrht.register(0).unwrap();
+    /// assert_ne!(ttkn0, ttkn);
+    /// assert!(nrht.try_execute((), ttkn).is_err());
+    /// ```
+    pub fn unregister(&self, tkn: ThreadToken) {
+        self.try_unregister(tkn).expect("Can't unregister thread")
+    }
+
+    /// Unregisters a thread, like [`NodeReplicated::unregister`], but returns
+    /// an error instead of panicking if `tkn` is invalid
+    /// ([`NodeReplicatedError::InvalidToken`]).
+    pub fn try_unregister(&self, tkn: ThreadToken) -> Result<(), NodeReplicatedError> {
+        self.check_token(tkn)?;
+        let _active = self.enter(tkn)?;
+        loop {
+            match self.replica(tkn.rid).unregister(&self.log, tkn.rtkn) {
+                Ok(()) => return Ok(()),
+                Err(ReplicaError::NoLogSpace(stuck_ridx, cl)) => {
+                    self.unstuck(tkn, stuck_ridx, Some(&cl))
+                }
+                Err(ReplicaError::GcFailed(stuck_ridx)) => self.unstuck(tkn, stuck_ridx, None),
+                // The next attempt discards the outstanding operations.
+                Err(ReplicaError::Poisoned) => continue,
+                Err(ReplicaError::Wal(_) | ReplicaError::Skipped) => continue,
+            }
+        }
+    }
+
+    fn replica_execute_mut<'a>(
+        &'a self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+        cl: Option<CombinerLock<'a, D>>,
+    ) -> Result<<D as Dispatch>::Response, ReplicaError<D>> {
+        if let Some(combiner_lock) = cl {
+            // We expect to have already enqueued the op (it's a re-try since have the combiner lock),
+            // so technically its not needed to supply it again (but we currently do it anyways...)
+            self.replica(tkn.rid)
+                .execute_mut_locked(&self.log, op, tkn.rtkn, combiner_lock)
+        } else {
+            self.replica(tkn.rid).execute_mut(&self.log, op, tkn.rtkn)
+        }
+    }
+
+    /// Executes a mutable operation against the data-structure.
+    ///
+    /// Thanks to the [`Log`] that [`NodeReplicated`] uses, all replicas will
+    /// execute all mutable operations in the same order.
+    ///
+    ///  This method is similar to the one found in [`Replica::execute_mut`],
+    /// but in addition, it handles liveness issues due to lagging replicas
+    /// (which a single replica can not).
+    ///
+    /// # Arguments
+    /// - `op`: Which operation to execute.
+    /// - `tkn`: Which thread executes the operation (see also
+    ///   [`NodeReplicated::register`]).
+    ///
+    /// # Flow
+    /// Eventually, this method calls [`Replica::execute_mut`] which will call
+    /// into [`Dispatch::dispatch_mut`].
+    ///
+    /// # Panics
+    /// If the operation fails, e.g., because `tkn` is invalid, the replica of
+    /// `tkn` is poisoned or the instance follows a leader.
+    /// [`NodeReplicated::try_execute_mut`] returns the error instead.
+    ///
+    /// # Example
+    /// ```
+    /// #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// impl Dispatch for Void {
+    ///     type ReadOperation<'rop> = ();
+    ///     type WriteOperation = usize;
+    ///     type Response = usize;
+    ///
+    ///     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    ///         unreachable!("no read-op is issued")
+    ///     }
+    ///     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    ///         assert!(op == 99);
+    ///         // "eventually", because if we just do one `execute_mut` call
+    ///         // we won't immediately advance the 2nd replica
+    ///         println!("Having two replicas means I'm (eventually) called twice");
+    ///         0xbeef
+    ///     }
+    /// }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Void>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    ///
+    /// assert_eq!(nrht.execute_mut(99, ttkn), 0xbeef);
+    /// ```
+    pub fn execute_mut(
+        &self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+    ) -> <D as Dispatch>::Response {
+        self.try_execute_mut(op, tkn)
+            .expect("Can't execute operation")
+    }
+
+    /// Executes a mutable operation against the data-structure, like
+    /// [`NodeReplicated::execute_mut`], but returns an error instead of
+    /// panicking if the replica of `tkn` is poisoned or the instance follows
+    /// a leader ([`NodeReplicatedError::ReadOnly`], see
+    /// [`NodeReplicated::follow`]).
+    ///
+    /// A replica is poisoned if [`Dispatch::dispatch_mut`] panicked while
+    /// applying operations to it (see [`ReplicaError::Poisoned`]). Other
+    /// replicas are not affected: they skip the operation that panicked if
+    /// they reach it afterwards, and if the replica of the thread that issued
+    /// it is one of them, the thread gets [`NodeReplicatedError::Skipped`].
+    /// This relies on `dispatch_mut` panicking on an operation on either
+    /// every replica or none.
+    /// Without the `std` feature, the panic continues to unwind in the thread
+    /// that applied the operation; with it, the panic is caught and that
+    /// thread gets an error as well.
+    ///
+    /// With a write-ahead log, [`NodeReplicatedError::Wal`] is returned if the
+    /// combiner couldn't write its batch. The operation is applied anyway.
+    pub fn try_execute_mut(
+        &self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+    ) -> Result<<D as Dispatch>::Response, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        self.check_writable()?;
+        let _active = self.enter(tkn)?;
+        /// An enum to keep track of a stack of operations we should do on Replicas.
+        ///
+        /// e.g., either `Sync` an out-of-date, behind replica, or call `execute_locked` or
+        /// `execute_mut_locked` to resume the operation with a combiner lock.
+        enum ResolveOp<'a, D: core::marker::Sync + Dispatch + Sized> {
+            /// Resumes a replica that earlier returned with an Error (and the CombinerLock).
+            Exec(Option<CombinerLock<'a, D>>),
+            /// Indicates need to [`Replica::sync()`] a replica with the given ID.
+            Sync(ReplicaId),
+        }
+
+        let mut q = ArrayVec::<ResolveOp<D>, { crate::log::MAX_REPLICAS_PER_LOG }>::new();
+        loop {
+            match q.pop().unwrap_or(ResolveOp::Exec(None)) {
+                ResolveOp::Exec(cl) => match self.replica_execute_mut(op.clone(), tkn, cl) {
+                    Ok(resp) => {
+                        assert!(q.is_empty());
+                        return Ok(resp);
+                    }
+                    Err(ReplicaError::NoLogSpace(stuck_ridx, cl_acq)) if stuck_ridx == tkn.rid => {
+                        // We hold up the log ourselves, apply our entries.
+                        self.replica(tkn.rid).sync_locked(&self.log, &cl_acq);
+                        q.push(ResolveOp::Exec(Some(cl_acq)));
+                    }
+                    Err(ReplicaError::NoLogSpace(stuck_ridx, cl_acq)) => {
+                        q.push(ResolveOp::Exec(Some(cl_acq)));
+                        q.push(ResolveOp::Sync(stuck_ridx));
+                    }
+                    Err(ReplicaError::GcFailed(stuck_ridx)) => {
+                        // If we hold up the log ourselves, our replica applies
+                        // its entries while we wait for the response.
+                        if stuck_ridx != tkn.rid && !self.evict(stuck_ridx) {
+                            let _aftkn = self.affinity_mngr.switch(stuck_ridx);
+                            self.replica(stuck_ridx).stats.remote_syncs.inc();
+                            self.replica(stuck_ridx).sync(&self.log);
+                            // Affinity is reverted here, _aftkn is dropped.
+                        }
+                        return match self
+                            .replica(tkn.rid)
+                            .get_response(&self.log, tkn.rtkn.tid())
+                        {
+                            Ok(resp) => Ok(resp),
+                            Err(ReplicaError::Skipped) => Err(NodeReplicatedError::Skipped),
+                            Err(e) => panic!("GcFailed has to produced a response: {:?}", e),
+                        };
+                    }
+                    Err(ReplicaError::Poisoned) => {
+                        return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                    }
+                    Err(ReplicaError::Skipped) => return Err(NodeReplicatedError::Skipped),
+                    Err(ReplicaError::Wal(e)) => {
+                        // Our operation is applied, drop its response.
+                        self.replica(tkn.rid).abandon(tkn.rtkn);
+                        return Err(NodeReplicatedError::Wal(e));
+                    }
+                },
+                ResolveOp::Sync(ridx) => {
+                    // We never push a sync of our own replica in this function.
+                    debug_assert_ne!(ridx, tkn.rid);
+                    //warn!("execute_mut ResolveOp::Sync {}", ridx);
+                    if self.evict(ridx) {
+                        continue;
+                    }
+                    let _aftkn = self.affinity_mngr.switch(ridx);
+                    self.replica(ridx).stats.remote_syncs.inc();
+                    self.replica(ridx).try_sync(&self.log);
+                    // _aftkn is dropped here, reverting affinity change
+                }
+            }
+        }
+    }
+
+    /// Executes a batch of mutable operations against the data-structure.
+    ///
+    /// Returns the responses in the same order as the operations in `ops`.
+    /// Operations are submitted in rounds that fill up the context of the
+    /// thread (see [`ReplicaConfig::pending_ops`]), which amortizes the
+    /// synchronization cost compared to calling
+    /// [`NodeReplicated::execute_mut`] for each operation.
+    ///
+    /// # Panics
+    /// If an operation fails, e.g., because `tkn` is invalid, the replica of
+    /// `tkn` is poisoned or the instance follows a leader.
+    /// [`NodeReplicated::try_execute_mut_batch`] returns the error instead.
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Counter(usize);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = usize;
+    /// #     type Response = usize;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += op;
+    /// #         self.0
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Counter>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    ///
+    /// let resps = nrht.execute_mut_batch(core::iter::repeat(1).take(100), ttkn);
+    /// assert_eq!(resps, (1..=100).collect::<Vec<usize>>());
+    /// ```
+    pub fn execute_mut_batch(
+        &self,
+        ops: impl IntoIterator<Item = <D as Dispatch>::WriteOperation>,
+        tkn: ThreadToken,
+    ) -> Vec<<D as Dispatch>::Response> {
+        match self.try_execute_mut_batch(ops, tkn) {
+            Ok(resps) => resps,
+            Err(e) => panic!("Can't execute operations: {:?}", e.error),
+        }
+    }
+
+    /// Executes a batch of mutable operations, like
+    /// [`NodeReplicated::execute_mut_batch`], but returns an error instead of
+    /// panicking if the replica of `tkn` is poisoned or the instance follows
+    /// a leader (see [`NodeReplicated::try_execute_mut`]).
+    ///
+    /// The error holds the responses of the operations that completed before
+    /// the failure and tells which operations may have been applied (see
+    /// [`BatchError`]).
+    pub fn try_execute_mut_batch(
+        &self,
+        ops: impl IntoIterator<Item = <D as Dispatch>::WriteOperation>,
+        tkn: ThreadToken,
+    ) -> Result<Vec<<D as Dispatch>::Response>, BatchError<<D as Dispatch>::Response>> {
+        self.check_token(tkn)?;
+        self.check_writable()?;
+        self.append_batch(ops, tkn)
+    }
+
+    /// Appends `ops` to the log through the replica of `tkn` and returns their
+    /// responses (see [`NodeReplicated::try_execute_mut_batch`]).
+    fn append_batch(
+        &self,
+        ops: impl IntoIterator<Item = <D as Dispatch>::WriteOperation>,
+        tkn: ThreadToken,
+    ) -> Result<Vec<<D as Dispatch>::Response>, BatchError<<D as Dispatch>::Response>> {
+        let _active = self.enter(tkn)?;
+        let submitted = core::cell::Cell::new(0);
+        let mut ops = ops
+            .into_iter()
+            .inspect(|_op| submitted.set(submitted.get() + 1));
+        let mut resps = Vec::with_capacity(ops.size_hint().0);
+        let replica = self.replica(tkn.rid);
+
+        let mut cl = None;
+        let mut iteration = 0;
+        loop {
+            match replica.execute_mut_batch(&self.log, &mut ops, tkn.rtkn, &mut resps, cl.take()) {
+                Ok(true) => return Ok(resps),
+                Ok(false) => {
+                    self.log.waiter.wait(iteration);
+                    iteration += 1;
+                }
+                Err(ReplicaError::NoLogSpace(stuck_ridx, cl_acq)) => {
+                    self.unstuck(tkn, stuck_ridx, Some(&cl_acq));
+                    cl = Some(cl_acq);
+                }
+                Err(ReplicaError::GcFailed(stuck_ridx)) => self.unstuck(tkn, stuck_ridx, None),
+                Err(ReplicaError::Poisoned) => {
+                    return Err(BatchError {
+                        error: NodeReplicatedError::Poisoned(tkn.rid),
+                        responses: resps,
+                        submitted: submitted.get(),
+                    });
+                }
+                Err(ReplicaError::Skipped) => {
+                    return Err(BatchError {
+                        error: NodeReplicatedError::Skipped,
+                        responses: resps,
+                        submitted: submitted.get(),
+                    });
+                }
+                Err(ReplicaError::Wal(e)) => {
+                    return Err(BatchError {
+                        error: NodeReplicatedError::Wal(e),
+                        responses: resps,
+                        submitted: submitted.get(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn replica_execute<'a, 'rop>(
+        &'a self,
+        op: <D as Dispatch>::ReadOperation<'rop>,
+        tkn: ThreadToken,
+        cl: Option<CombinerLock<'a, D>>,
+    ) -> Result<<D as Dispatch>::Response, (ReplicaError<D>, <D as Dispatch>::ReadOperation<'rop>)>
+    {
+        if let Some(combiner_lock) = cl {
+            self.replica(tkn.rid)
+                .execute_locked(&self.log, op, tkn.rtkn, combiner_lock)
+        } else {
+            self.replica(tkn.rid).execute(&self.log, op, tkn.rtkn)
+        }
     }
 
     /// Executes a immutable operation against the data-structure.
@@ -637,6 +1958,11 @@ where
     /// Eventually, this method calls [`Replica::execute`] which will call into
     /// [`Dispatch::dispatch`].
     ///
+    /// # Panics
+    /// If the operation fails, e.g., because `tkn` is invalid or the replica
+    /// of `tkn` is poisoned. [`NodeReplicated::try_execute`] returns the error
+    /// instead.
+    ///
     /// # Example
     /// ```
     /// #![feature(generic_associated_types)]
@@ -671,6 +1997,20 @@ where
         op: <D as Dispatch>::ReadOperation<'_>,
         tkn: ThreadToken,
     ) -> <D as Dispatch>::Response {
+        self.try_execute(op, tkn).expect("Can't execute operation")
+    }
+
+    /// Executes an immutable operation against the data-structure, like
+    /// [`NodeReplicated::execute`], but returns an error instead of panicking
+    /// if the replica of `tkn` is poisoned (see
+    /// [`NodeReplicated::try_execute_mut`]).
+    pub fn try_execute(
+        &self,
+        op: <D as Dispatch>::ReadOperation<'_>,
+        tkn: ThreadToken,
+    ) -> Result<<D as Dispatch>::Response, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        let _active = self.enter(tkn)?;
         /// An enum to keep track of a stack of operations we should do on Replicas.
         ///
         /// e.g., either `Sync` an out-of-date, behind replica, or call `execute_locked` or
@@ -686,79 +2026,1619 @@ where
         q.push(ResolveOp::Exec(None, op));
         loop {
             match q.pop().unwrap() {
-                ResolveOp::Exec(cl, op) => match self.try_execute(op, tkn, cl) {
+                ResolveOp::Exec(cl, op) => match self.replica_execute(op, tkn, cl) {
                     Ok(resp) => {
                         assert!(q.is_empty());
-                        return resp;
+                        return Ok(resp);
+                    }
+                    Err((ReplicaError::NoLogSpace(stuck_ridx, cl_acq), op))
+                        if stuck_ridx == tkn.rid =>
+                    {
+                        // We hold up the log ourselves, apply our entries.
+                        self.replica(tkn.rid).sync_locked(&self.log, &cl_acq);
+                        q.push(ResolveOp::Exec(Some(cl_acq), op));
                     }
                     Err((ReplicaError::NoLogSpace(stuck_ridx, cl_acq), op)) => {
-                        assert!(stuck_ridx != tkn.rid);
                         q.push(ResolveOp::Exec(Some(cl_acq), op));
                         q.push(ResolveOp::Sync(stuck_ridx));
                     }
                     Err((ReplicaError::GcFailed(stuck_ridx), op)) => {
-                        assert_ne!(stuck_ridx, tkn.rid);
                         q.push(ResolveOp::Exec(None, op));
                         q.push(ResolveOp::Sync(stuck_ridx));
                     }
+                    Err((ReplicaError::Poisoned, _op)) => {
+                        return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                    }
+                    Err((ReplicaError::Skipped, _op)) => {
+                        return Err(NodeReplicatedError::Skipped);
+                    }
+                    // Only writers care about the operations of the round.
+                    Err((ReplicaError::Wal(_e), op)) => q.push(ResolveOp::Exec(None, op)),
                 },
+                ResolveOp::Sync(ridx) if ridx == tkn.rid => {
+                    // We hold up the log ourselves, apply our entries.
+                    self.replica(ridx).try_sync(&self.log);
+                }
                 ResolveOp::Sync(ridx) => {
-                    // Holds trivially because of all the other asserts in this function
-                    debug_assert_ne!(ridx, tkn.rid);
+                    if self.evict(ridx) {
+                        continue;
+                    }
                     let _aftkn = self.affinity_mngr.switch(ridx);
-                    self.replicas[ridx].try_sync(&self.log);
+                    self.replica(ridx).stats.remote_syncs.inc();
+                    self.replica(ridx).try_sync(&self.log);
                     // _aftkn is dropped here, reverting affinity change
                 }
             }
         }
     }
 
-    /// Executes a mutable operation asynchronously on a replica, and returns
-    /// the response in `resp`
-    ///
-    /// # Note
-    /// Currently a trivial "async" implementation as we just call the blocking
-    /// call [`NodeReplicated::execute`] and wrap it in `async`.
-    #[cfg(feature = "async")]
-    pub async fn async_execute_mut<'a>(
-        &'a self,
-        op: <D as Dispatch>::WriteOperation,
-        tkn: ThreadToken,
-        resp: &mut ReusableBoxFuture<'a, <D as Dispatch>::Response>,
-    ) {
-        resp.set(async move { self.execute_mut(op, tkn) });
+    /// Executes an immutable operation against the data-structure and
+    /// tolerates slightly stale results.
+    ///
+    /// Unlike [`NodeReplicated::execute`], the replica of `tkn` does not have
+    /// to catch up with all mutable operations that completed before the
+    /// call: The operation is served from the replica right away as long as
+    /// it is at most `max_lag` log entries behind. This avoids becoming
+    /// combiner and accessing the [`Log`], so it suits reads like monitoring
+    /// queries which don't have to be linearizable. If the replica lags
+    /// further behind, it syncs up like [`NodeReplicated::execute`] does
+    /// (which may apply all outstanding operations). With a `max_lag` of 0,
+    /// this is the same as [`NodeReplicated::execute`].
+    ///
+    /// # Panics
+    /// If the operation fails, e.g., because `tkn` is invalid or the replica
+    /// of `tkn` is poisoned. [`NodeReplicated::try_execute_stale`] returns the
+    /// error instead.
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Counter(usize);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = usize;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += 1;
+    /// #         self.0
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Counter>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn0 = nrht.register(0).unwrap();
+    /// let ttkn1 = nrht.register(1).unwrap();
+    /// nrht.execute_mut((), ttkn0);
+    /// nrht.execute_mut((), ttkn0);
+    ///
+    /// // Replica 1 did not apply the two operations yet.
+    /// assert_eq!(nrht.execute_stale((), ttkn1, 2), 0);
+    /// assert_eq!(nrht.execute_stale((), ttkn1, 0), 2);
+    /// ```
+    pub fn execute_stale(
+        &self,
+        op: <D as Dispatch>::ReadOperation<'_>,
+        tkn: ThreadToken,
+        max_lag: usize,
+    ) -> <D as Dispatch>::Response {
+        self.try_execute_stale(op, tkn, max_lag)
+            .expect("Can't execute operation")
+    }
+
+    /// Executes an immutable operation that tolerates stale results, like
+    /// [`NodeReplicated::execute_stale`], but returns an error instead of
+    /// panicking if the replica of `tkn` is poisoned (see
+    /// [`NodeReplicated::try_execute_mut`]).
+    pub fn try_execute_stale(
+        &self,
+        mut op: <D as Dispatch>::ReadOperation<'_>,
+        tkn: ThreadToken,
+        max_lag: usize,
+    ) -> Result<<D as Dispatch>::Response, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        let _active = self.enter(tkn)?;
+        let replica = self.replica(tkn.rid);
+        loop {
+            match replica.execute_stale(&self.log, op, tkn.rtkn, max_lag) {
+                Ok(resp) => return Ok(resp),
+                Err((ReplicaError::NoLogSpace(stuck_ridx, cl), rop)) => {
+                    op = rop;
+                    self.unstuck(tkn, stuck_ridx, Some(&cl));
+                }
+                Err((ReplicaError::GcFailed(stuck_ridx), rop)) => {
+                    op = rop;
+                    self.unstuck(tkn, stuck_ridx, None);
+                }
+                Err((ReplicaError::Poisoned, _op)) => {
+                    return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                }
+                Err((ReplicaError::Skipped, _op)) => {
+                    return Err(NodeReplicatedError::Skipped);
+                }
+                Err((ReplicaError::Wal(_e), rop)) => op = rop,
+            }
+        }
+    }
+
+    /// Runs the closure `f` against the data-structure of the replica of
+    /// `tkn` and returns its result.
+    ///
+    /// Like [`NodeReplicated::execute`], the replica first catches up with
+    /// all mutable operations that completed before the call, then `f` runs
+    /// under the read lock of the replica. Unlike
+    /// [`Dispatch::ReadOperation`]s, `f` can return any type, which suits
+    /// ad-hoc queries such as iterating over or aggregating the
+    /// data-structure.
+    ///
+    /// `f` must not mutate the data-structure (e.g., through interior
+    /// mutability), and should be short: it holds up mutable operations on
+    /// the replica.
+    ///
+    /// # Panics
+    /// If `tkn` is invalid or the replica of `tkn` is poisoned.
+    /// [`NodeReplicated::try_read_with`] returns the error instead.
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Numbers(Vec<usize>);
+    /// # impl Dispatch for Numbers {
+    /// #     type ReadOperation<'rop> = usize;
+    /// #     type WriteOperation = usize;
+    /// #     type Response = Option<usize>;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0.get(op).copied()
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0.push(op);
+    /// #         None
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(2).unwrap();
+    /// let nrht = NodeReplicated::<Numbers>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn0 = nrht.register(0).unwrap();
+    /// let ttkn1 = nrht.register(1).unwrap();
+    /// for i in 1..=4 {
+    ///     nrht.execute_mut(i, ttkn0);
+    /// }
+    ///
+    /// let sum: usize = nrht.read_with(ttkn1, |d| d.0.iter().sum());
+    /// assert_eq!(sum, 10);
+    /// ```
+    pub fn read_with<R>(&self, tkn: ThreadToken, f: impl FnOnce(&D) -> R) -> R {
+        self.try_read_with(tkn, f).expect("Can't execute operation")
+    }
+
+    /// Runs the closure `f` against the data-structure, like
+    /// [`NodeReplicated::read_with`], but returns an error instead of
+    /// panicking if the replica of `tkn` is poisoned.
+    pub fn try_read_with<R>(
+        &self,
+        tkn: ThreadToken,
+        mut f: impl FnOnce(&D) -> R,
+    ) -> Result<R, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        let _active = self.enter(tkn)?;
+        let replica = self.replica(tkn.rid);
+        loop {
+            match replica.read_with(&self.log, tkn.rtkn, f) {
+                Ok(r) => return Ok(r),
+                Err((ReplicaError::NoLogSpace(stuck_ridx, cl), rf)) => {
+                    f = rf;
+                    self.unstuck(tkn, stuck_ridx, Some(&cl));
+                }
+                Err((ReplicaError::GcFailed(stuck_ridx), rf)) => {
+                    f = rf;
+                    self.unstuck(tkn, stuck_ridx, None);
+                }
+                Err((ReplicaError::Poisoned, _rf)) => {
+                    return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                }
+                Err((ReplicaError::Skipped, _rf)) => {
+                    return Err(NodeReplicatedError::Skipped);
+                }
+                Err((ReplicaError::Wal(_e), rf)) => f = rf,
+            }
+        }
+    }
+
+    /// Executes a mutable operation against the data-structure, like
+    /// [`NodeReplicated::try_execute_mut`], but gives up after busy waiting
+    /// for `spins` iterations.
+    ///
+    /// There is no clock in `no_std`, so the time an operation may take is
+    /// bounded with a budget of spin iterations: Each attempt to make progress
+    /// (waiting for a response, becoming combiner or syncing a lagging
+    /// replica) uses up at least one iteration.
+    ///
+    /// # Returns
+    /// [`NodeReplicatedError::Stalled`] with the replica that held up the
+    /// operation if the budget ran out. The operation may still be applied
+    /// later, as it can't be withdrawn once other threads might have seen
+    /// it, but its response is discarded.
+    ///
+    /// # Example
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// use core::num::NonZeroUsize;
+    /// use node_replication::nr::NodeReplicated;
+    /// # use node_replication::nr::Dispatch;
+    ///
+    /// #[derive(Default)]
+    /// struct Counter(usize);
+    /// # impl Dispatch for Counter {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = ();
+    /// #     type Response = usize;
+    /// #
+    /// #     fn dispatch<'rop>(&self, op: <Self as Dispatch>::ReadOperation<'rop>) -> <Self as Dispatch>::Response {
+    /// #         self.0
+    /// #     }
+    /// #     fn dispatch_mut(&mut self, op: <Self as Dispatch>::WriteOperation) -> <Self as Dispatch>::Response {
+    /// #         self.0 += 1;
+    /// #         self.0
+    /// #     }
+    /// # }
+    ///
+    /// let replicas = NonZeroUsize::new(1).unwrap();
+    /// let nrht = NodeReplicated::<Counter>::new(replicas, |_| { 0 }).unwrap();
+    /// let ttkn = nrht.register(0).unwrap();
+    /// assert_eq!(nrht.execute_mut_timeout((), ttkn, 1 << 20).unwrap(), 1);
+    /// assert_eq!(nrht.execute_timeout((), ttkn, 1 << 20).unwrap(), 1);
+    /// ```
+    pub fn execute_mut_timeout(
+        &self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+        mut spins: usize,
+    ) -> Result<<D as Dispatch>::Response, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        self.check_writable()?;
+        let _active = self.enter(tkn)?;
+        let replica = self.replica(tkn.rid);
+        let mut stalled = tkn.rid;
+        let mut enqueued = false;
+        let mut combiner_lock = None;
+        let mut iteration = 1;
+        loop {
+            // The context can be full of operations of earlier calls that
+            // timed out, it drains while we combine.
+            if !enqueued {
+                enqueued = replica.make_pending(op.clone(), tkn.rtkn.tid());
+            }
+
+            let attempt = match combiner_lock.take() {
+                Some(cl) => replica.combine(&self.log, cl),
+                None => replica.try_combine(&self.log),
+            }
+            .and_then(|()| match enqueued {
+                true => replica.get_response_within(&self.log, tkn.rtkn.tid(), &mut spins),
+                false => Ok(None),
+            });
+            match attempt {
+                Ok(Some(resp)) => return Ok(resp),
+                Ok(None) => {}
+                Err(ReplicaError::NoLogSpace(stuck_ridx, cl)) => {
+                    stalled = stuck_ridx;
+                    self.unstuck(tkn, stuck_ridx, Some(&cl));
+                    combiner_lock = Some(cl);
+                }
+                Err(ReplicaError::GcFailed(stuck_ridx)) => {
+                    stalled = stuck_ridx;
+                    self.unstuck(tkn, stuck_ridx, None);
+                }
+                Err(ReplicaError::Poisoned) => {
+                    return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                }
+                Err(ReplicaError::Skipped) => return Err(NodeReplicatedError::Skipped),
+                Err(ReplicaError::Wal(e)) if enqueued => {
+                    replica.abandon(tkn.rtkn);
+                    return Err(NodeReplicatedError::Wal(e));
+                }
+                // Our operation isn't part of the round.
+                Err(ReplicaError::Wal(_e)) => {}
+            }
+
+            if spins == 0 {
+                if enqueued {
+                    replica.abandon(tkn.rtkn);
+                }
+                return Err(NodeReplicatedError::Stalled(stalled));
+            }
+            spins -= 1;
+            self.log.waiter.wait(iteration);
+            iteration += 1;
+        }
+    }
+
+    /// Executes an immutable operation against the data-structure, like
+    /// [`NodeReplicated::try_execute`], but gives up after busy waiting for
+    /// `spins` iterations (see [`NodeReplicated::execute_mut_timeout`]).
+    ///
+    /// # Returns
+    /// [`NodeReplicatedError::Stalled`] with the replica that held up the
+    /// operation if the replica of `tkn` could not catch up with the log
+    /// within the budget.
+    pub fn execute_timeout(
+        &self,
+        op: <D as Dispatch>::ReadOperation<'_>,
+        tkn: ThreadToken,
+        mut spins: usize,
+    ) -> Result<<D as Dispatch>::Response, NodeReplicatedError> {
+        self.check_token(tkn)?;
+        let _active = self.enter(tkn)?;
+        let replica = self.replica(tkn.rid);
+        let ctail = self.log.get_ctail();
+        let mut stalled = tkn.rid;
+        let mut combiner_lock = None;
+        loop {
+            match replica.sync_within(&self.log, ctail, combiner_lock.take(), &mut spins) {
+                Ok(true) => return Ok(replica.execute_unsynced(op, tkn.rtkn)),
+                Ok(false) => return Err(NodeReplicatedError::Stalled(stalled)),
+                Err(ReplicaError::NoLogSpace(stuck_ridx, cl)) => {
+                    stalled = stuck_ridx;
+                    self.unstuck(tkn, stuck_ridx, Some(&cl));
+                    combiner_lock = Some(cl);
+                }
+                Err(ReplicaError::GcFailed(stuck_ridx)) => {
+                    stalled = stuck_ridx;
+                    self.unstuck(tkn, stuck_ridx, None);
+                }
+                Err(ReplicaError::Poisoned) => {
+                    return Err(NodeReplicatedError::Poisoned(tkn.rid));
+                }
+                Err(ReplicaError::Skipped) => return Err(NodeReplicatedError::Skipped),
+                Err(ReplicaError::Wal(_e)) => {}
+            }
+        }
+    }
+
+    /// Executes a mutable operation asynchronously on a replica, and returns
+    /// the response in `resp`
+    ///
+    /// The future enqueues `op` in the context of the thread when it is first
+    /// polled. While another thread is combining on the replica, the future
+    /// returns [`Poll::Pending`] and is woken by the combiner once it is done,
+    /// so a task can have many operations in-flight at the same time.
+    ///
+    /// # Note
+    /// All futures of one [`ThreadToken`] share a context: They must not be
+    /// polled concurrently from multiple threads.
+    ///
+    /// # Panics
+    /// If the operation fails, e.g., because `tkn` is invalid, the replica of
+    /// `tkn` is poisoned or the instance follows a leader.
+    /// [`NodeReplicated::try_async_execute_mut`] returns the error instead.
+    #[cfg(feature = "async")]
+    pub async fn async_execute_mut<'a>(
+        &'a self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+        resp: &mut ReusableBoxFuture<'a, <D as Dispatch>::Response>,
+    ) {
+        self.check_token(tkn).expect("Invalid ThreadToken");
+        self.check_writable().expect("Can't execute operation");
+        let fut = ExecuteMut {
+            nr: self,
+            op,
+            tkn,
+            pos: None,
+        };
+        resp.set(async move { fut.await.expect("Can't execute operation") });
+    }
+
+    /// Executes a mutable operation asynchronously, like
+    /// [`NodeReplicated::async_execute_mut`], but the future resolves to an
+    /// error instead of panicking if the replica of `tkn` is poisoned or the
+    /// instance follows a leader (see [`NodeReplicated::try_execute_mut`]).
+    #[cfg(feature = "async")]
+    pub async fn try_async_execute_mut<'a>(
+        &'a self,
+        op: <D as Dispatch>::WriteOperation,
+        tkn: ThreadToken,
+        resp: &mut ReusableBoxFuture<'a, Result<<D as Dispatch>::Response, NodeReplicatedError>>,
+    ) {
+        match self.check_token(tkn).and_then(|()| self.check_writable()) {
+            Ok(()) => resp.set(ExecuteMut {
+                nr: self,
+                op,
+                tkn,
+                pos: None,
+            }),
+            Err(e) => resp.set(async move { Err(e) }),
+        }
+    }
+
+    /// Executes an immutable operation asynchronously on a replica, and returns
+    /// the response in `resp`.
+    ///
+    /// If the replica is behind, the future tries to advance it. While
+    /// another thread is combining on the replica, the future returns
+    /// [`Poll::Pending`] and is woken by the combiner once it is done.
+    ///
+    /// # Panics
+    /// If the operation fails, e.g., because `tkn` is invalid or the replica
+    /// of `tkn` is poisoned. [`NodeReplicated::try_async_execute`] returns the
+    /// error instead.
+    #[cfg(feature = "async")]
+    pub fn async_execute<'a, 'rop: 'a>(
+        &'a self,
+        op: <D as Dispatch>::ReadOperation<'rop>,
+        tkn: ThreadToken,
+        resp: &mut ReusableBoxFuture<'a, <D as Dispatch>::Response>,
+    ) {
+        self.check_token(tkn).expect("Invalid ThreadToken");
+        let fut = Execute {
+            nr: self,
+            op: Some(op),
+            tkn,
+            ctail: None,
+        };
+        resp.set(async move { fut.await.expect("Can't execute operation") });
+    }
+
+    /// Executes an immutable operation asynchronously, like
+    /// [`NodeReplicated::async_execute`], but the future resolves to an error
+    /// instead of panicking if the replica of `tkn` is poisoned.
+    #[cfg(feature = "async")]
+    pub fn try_async_execute<'a, 'rop: 'a>(
+        &'a self,
+        op: <D as Dispatch>::ReadOperation<'rop>,
+        tkn: ThreadToken,
+        resp: &mut ReusableBoxFuture<'a, Result<<D as Dispatch>::Response, NodeReplicatedError>>,
+    ) {
+        match self.check_token(tkn) {
+            Ok(()) => resp.set(Execute {
+                nr: self,
+                op: Some(op),
+                tkn,
+                ctail: None,
+            }),
+            Err(e) => resp.set(async move { Err(e) }),
+        }
+    }
+
+    /// Makes a replica which holds up the log (`stuck_ridx`) progress while
+    /// thread `tkn` waits for it.
+    ///
+    /// If that's the replica of `tkn`, it applies the outstanding entries of
+    /// the log instead (under the combiner lock `cl` if the thread holds it
+    /// already) and the thread retries.
+    fn unstuck(&self, tkn: ThreadToken, stuck_ridx: ReplicaId, cl: Option<&CombinerLock<D>>) {
+        if stuck_ridx == tkn.rid {
+            let replica = self.replica(tkn.rid);
+            match cl {
+                Some(cl) => replica.sync_locked(&self.log, cl),
+                None => replica.try_sync(&self.log),
+            }
+            return;
+        }
+        if self.evict(stuck_ridx) {
+            return;
+        }
+        let _aftkn = self.affinity_mngr.switch(stuck_ridx);
+        self.replica(stuck_ridx).stats.remote_syncs.inc();
+        self.replica(stuck_ridx).try_sync(&self.log);
+        // _aftkn is dropped here, reverting affinity change
+    }
+
+    /// Evicts the replica `stuck_ridx` which holds up the log if it lags too
+    /// far behind (see [`NodeReplicatedBuilder::evict_after`]). Returns true
+    /// if it was evicted.
+    fn evict(&self, stuck_ridx: ReplicaId) -> bool {
+        match &self.eviction {
+            Some(e) => self.replica(stuck_ridx).evict(&self.log, e.max_lag),
+            None => false,
+        }
+    }
+
+    /// Makes sure the replica of `tkn` is not evicted while the calling thread
+    /// operates on it, rebuilds it first if it is dormant.
+    ///
+    /// Returns None (there is nothing to do) if replicas are never evicted.
+    fn enter(&self, tkn: ThreadToken) -> Result<Option<Active<'_, D>>, NodeReplicatedError> {
+        let eviction = match &self.eviction {
+            Some(eviction) => eviction,
+            None => return Ok(None),
+        };
+
+        let replica = self.replica(tkn.rid);
+        loop {
+            if let Some(active) = replica.enter() {
+                return Ok(Some(active));
+            }
+            self.rebuild(tkn.rid, eviction.clone)?;
+        }
+    }
+
+    /// Rebuilds the dormant replica `replica_id` with a copy (made by `clone`)
+    /// of the data-structure of a healthy replica. The replica continues on
+    /// the log where the healthy one is, instead of replaying the operations
+    /// it missed (they may be overwritten).
+    fn rebuild(
+        &self,
+        replica_id: ReplicaId,
+        clone: fn(&D) -> D,
+    ) -> Result<(), NodeReplicatedError> {
+        self.with_membership(|| {
+            let replica = self.replica(replica_id);
+            if !replica.is_evicted() {
+                // Another thread rebuilt it already.
+                return Ok(());
+            }
+
+            // The replica applied all operations it appended before it was
+            // evicted, the source has to be past them.
+            let (source, _active) = self.enter_healthy()?;
+            source.sync(&self.log);
+
+            let reuse = |log_token: &log::LogToken| log_token.0 - 1 == replica_id;
+            source
+                .fork(&self.log, reuse, |log_token, data| {
+                    // Make the copy on the proper NUMA node
+                    let _aff_tkn = self.affinity_mngr.switch(replica_id);
+                    replica.revive(log_token, clone(data));
+                })
+                .expect("The slot of a dormant replica is free");
+            replica.stats.rebuilds.inc();
+            Ok(())
+        })
+    }
+
+    /// Applies the outstanding operations of the log to the replica of `tkn`.
+    ///
+    /// # Panics
+    /// If `tkn` is invalid.
+    #[doc(hidden)]
+    pub fn sync(&self, tkn: ThreadToken) {
+        self.check_token(tkn).expect("Invalid ThreadToken");
+        let _active = self.enter(tkn).expect("Can't rebuild replica");
+        self.replica(tkn.rid).sync(&self.log)
+    }
+
+    /// Returns a snapshot of the runtime statistics of all active replicas
+    /// and the shared log.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        let replicas = (0..self.replicas.len())
+            .filter_map(|rid| Some((rid, self.try_replica(rid)?)))
+            .filter(|(_rid, r)| !r.is_retired())
+            .map(|(rid, r)| (rid, r.stats()))
+            .collect();
+
+        Stats {
+            replicas,
+            log: self.log.stats(),
+        }
+    }
+}
+
+/// Future returned (in `resp`) by [`NodeReplicated::try_async_execute_mut`]
+/// (and [`NodeReplicated::async_execute_mut`]).
+#[cfg(feature = "async")]
+struct ExecuteMut<'a, D: Dispatch + Sync> {
+    nr: &'a NodeReplicated<D>,
+    op: <D as Dispatch>::WriteOperation,
+    tkn: ThreadToken,
+    /// Position of `op` in the thread context once it is enqueued.
+    pos: Option<usize>,
+}
+
+/// We never hand out references to the fields of a pinned future.
+#[cfg(feature = "async")]
+impl<D: Dispatch + Sync> Unpin for ExecuteMut<'_, D> {}
+
+#[cfg(feature = "async")]
+impl<D> Future for ExecuteMut<'_, D>
+where
+    D: Dispatch + Sized + Sync,
+{
+    type Output = Result<<D as Dispatch>::Response, NodeReplicatedError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _active = match this.nr.enter(this.tkn) {
+            Ok(active) => active,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let replica = this.nr.replica(this.tkn.rid);
+        loop {
+            match replica.poll_execute_mut(&this.nr.log, &this.op, &mut this.pos, this.tkn.rtkn, cx)
+            {
+                Poll::Ready(Ok(resp)) => {
+                    this.pos = None;
+                    return Poll::Ready(Ok(resp));
+                }
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(ReplicaError::NoLogSpace(stuck_ridx, cl))) => {
+                    this.nr.unstuck(this.tkn, stuck_ridx, Some(&cl))
+                }
+                Poll::Ready(Err(ReplicaError::GcFailed(stuck_ridx))) => {
+                    this.nr.unstuck(this.tkn, stuck_ridx, None)
+                }
+                Poll::Ready(Err(ReplicaError::Poisoned)) => {
+                    return Poll::Ready(Err(NodeReplicatedError::Poisoned(this.tkn.rid)));
+                }
+                Poll::Ready(Err(ReplicaError::Skipped)) => {
+                    this.pos = None;
+                    return Poll::Ready(Err(NodeReplicatedError::Skipped));
+                }
+                // Our operation is applied, its response is dropped with the
+                // future.
+                Poll::Ready(Err(ReplicaError::Wal(e))) => {
+                    return Poll::Ready(Err(NodeReplicatedError::Wal(e)));
+                }
+            }
+        }
+    }
+}
+
+/// A future that is dropped before it completed leaves its operation in the
+/// context, it is applied but nobody takes its response.
+#[cfg(feature = "async")]
+impl<D: Dispatch + Sync> Drop for ExecuteMut<'_, D> {
+    fn drop(&mut self) {
+        if let Some(pos) = self.pos {
+            if let Some(replica) = self.nr.try_replica(self.tkn.rid) {
+                replica.abandon_at(self.tkn.rtkn, pos);
+            }
+        }
     }
+}
 
-    /// Executes an immutable operation asynchronously on a replica, and returns
-    /// the response in `resp`.
-    ///
-    /// # Note
-    /// Currently a trivial "async" implementation as we just call the blocking
-    /// call [`NodeReplicated::execute`] and wrap it in `async`.
-    #[cfg(feature = "async")]
-    pub fn async_execute<'a, 'rop: 'a>(
-        &'a self,
-        op: <D as Dispatch>::ReadOperation<'rop>,
-        tkn: ThreadToken,
-        resp: &mut ReusableBoxFuture<'a, <D as Dispatch>::Response>,
-    ) {
-        resp.set(async move { self.execute(op, tkn) });
-    }
+/// Future returned (in `resp`) by [`NodeReplicated::try_async_execute`] (and
+/// [`NodeReplicated::async_execute`]).
+#[cfg(feature = "async")]
+struct Execute<'a, 'rop, D: Dispatch + Sync> {
+    nr: &'a NodeReplicated<D>,
+    op: Option<<D as Dispatch>::ReadOperation<'rop>>,
+    tkn: ThreadToken,
+    /// Completed tail of the log when the future was first polled.
+    ctail: Option<usize>,
+}
 
-    #[doc(hidden)]
-    pub fn sync(&self, tkn: ThreadToken) {
-        self.replicas[tkn.rid].sync(&self.log)
+/// We never hand out references to the fields of a pinned future.
+#[cfg(feature = "async")]
+impl<D: Dispatch + Sync> Unpin for Execute<'_, '_, D> {}
+
+#[cfg(feature = "async")]
+impl<D> Future for Execute<'_, '_, D>
+where
+    D: Dispatch + Sized + Sync,
+{
+    type Output = Result<<D as Dispatch>::Response, NodeReplicatedError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _active = match this.nr.enter(this.tkn) {
+            Ok(active) => active,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let replica = this.nr.replica(this.tkn.rid);
+        let nr = this.nr;
+        let ctail = *this.ctail.get_or_insert_with(|| nr.log.get_ctail());
+        loop {
+            match replica.poll_execute(&this.nr.log, &mut this.op, ctail, this.tkn.rtkn, cx) {
+                Poll::Ready(Ok(resp)) => return Poll::Ready(Ok(resp)),
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(ReplicaError::NoLogSpace(stuck_ridx, cl))) => {
+                    this.nr.unstuck(this.tkn, stuck_ridx, Some(&cl))
+                }
+                Poll::Ready(Err(ReplicaError::GcFailed(stuck_ridx))) => {
+                    this.nr.unstuck(this.tkn, stuck_ridx, None)
+                }
+                Poll::Ready(Err(ReplicaError::Poisoned)) => {
+                    return Poll::Ready(Err(NodeReplicatedError::Poisoned(this.tkn.rid)));
+                }
+                Poll::Ready(Err(ReplicaError::Skipped)) => {
+                    return Poll::Ready(Err(NodeReplicatedError::Skipped));
+                }
+                Poll::Ready(Err(ReplicaError::Wal(_e))) => {}
+            }
+        }
     }
 }
 
-#[cfg(feature = "async")]
 #[cfg(test)]
 mod test {
+    extern crate std;
+
     use super::replica::test::Data;
+    #[cfg(feature = "async")]
     use super::reusable_box::ReusableBoxFuture;
     use super::*;
+    use crate::context::MAX_PENDING_OPS;
     use core::num::NonZeroUsize;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Tests that `with_data` clones the seed into every replica while the
+    // affinity is switched to the replica being created.
+    #[test]
+    fn test_with_data() {
+        let switches = Arc::new(AtomicUsize::new(0));
+        let sw = switches.clone();
+        let replicas = NonZeroUsize::new(3).unwrap();
+        let nr = NodeReplicated::<Data>::with_data(
+            replicas,
+            move |ac| match ac {
+                AffinityChange::Replica(rid) => {
+                    assert_eq!(rid, sw.fetch_add(1, Ordering::SeqCst));
+                    rid
+                }
+                AffinityChange::Revert(_) => 0,
+            },
+            Data { junk: 42 },
+        )
+        .expect("Can't create Ds");
+        assert_eq!(switches.load(Ordering::SeqCst), replicas.get());
+
+        for rid in 0..replicas.get() {
+            let ttkn = nr.register(rid).expect("Unable to register with replica");
+            assert_eq!(nr.execute(0, ttkn), Ok(42));
+        }
+    }
+
+    // Tests that closures passed to `read_with` observe all completed
+    // mutable operations, also on a replica that did not issue them.
+    #[test]
+    fn test_read_with() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+
+        for i in 0..50 {
+            assert_eq!(nr.read_with(ttkn1, |d| d.junk), i);
+            nr.execute_mut(0, ttkn0).unwrap();
+        }
+        let junk: std::string::String = nr.read_with(ttkn1, |d| std::format!("{}", d.junk));
+        assert_eq!(junk, "50");
+        assert!(matches!(
+            nr.try_read_with(ThreadToken { rid: 7, ..ttkn1 }, |d| d.junk),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+    }
+
+    // Tests that a batch larger than a thread context and the log completes
+    // and is applied on all replicas.
+    #[test]
+    fn test_execute_mut_batch() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+
+        let ops = 2 * nr.log.slog.len() + 3;
+        let resps = nr.execute_mut_batch(std::vec![0; ops], ttkn);
+        assert_eq!(resps.len(), ops);
+        assert!(resps.iter().all(|r| *r == Ok(107)));
+
+        assert!(nr.execute_mut_batch(core::iter::empty(), ttkn).is_empty());
+        assert_eq!(nr.execute(0, ttkn1), Ok(ops as u64));
+    }
+
+    // Tests that operations time out while another replica holds up the log
+    // and that responses of timed out operations don't show up later.
+    #[test]
+    fn test_execute_timeout() {
+        use std::sync::mpsc::channel;
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = Arc::new(NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds"));
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+
+        // Replica 1 stops making progress while its combiner lock is held.
+        let (locked_tx, locked_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        let nr1 = nr.clone();
+        let stuck = std::thread::spawn(move || {
+            nr1.replica(1).verify(&nr1.log, |_d| {
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+        });
+        locked_rx.recv().unwrap();
+
+        let mut ops = 0;
+        loop {
+            ops += 1;
+            match nr.execute_mut_timeout(0, ttkn, 1000) {
+                Ok(resp) => assert_eq!(resp, Ok(107)),
+                Err(NodeReplicatedError::Stalled(rid)) => {
+                    assert_eq!(rid, 1);
+                    break;
+                }
+                Err(e) => panic!("Unexpected error {:?}", e),
+            }
+        }
+        assert!(matches!(
+            nr.execute_timeout(0, ttkn1, 1000),
+            Err(NodeReplicatedError::Stalled(1))
+        ));
+
+        release_tx.send(()).unwrap();
+        stuck.join().unwrap();
+
+        assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        assert_eq!(
+            nr.execute_timeout(0, ttkn1, usize::MAX).unwrap(),
+            Ok(ops + 1)
+        );
+    }
+
+    // Tests that a thread whose context is full of timed out operations can
+    // still execute operations once the replica makes progress again.
+    #[test]
+    fn test_execute_mut_after_timeouts() {
+        use std::sync::mpsc::channel;
+
+        let replicas = NonZeroUsize::new(1).unwrap();
+        let nr = Arc::new(NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds"));
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+
+        // Nobody can combine while the combiner lock is held.
+        let (locked_tx, locked_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        let nr1 = nr.clone();
+        let stuck = std::thread::spawn(move || {
+            nr1.replica(0).verify(&nr1.log, |_d| {
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+        });
+        locked_rx.recv().unwrap();
+
+        for _i in 0..2 * MAX_PENDING_OPS {
+            assert!(matches!(
+                nr.execute_mut_timeout(0, ttkn, 100),
+                Err(NodeReplicatedError::Stalled(0))
+            ));
+        }
+
+        release_tx.send(()).unwrap();
+        stuck.join().unwrap();
+
+        assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        assert_eq!(nr.execute(0, ttkn), Ok(MAX_PENDING_OPS as u64 + 1));
+    }
+
+    // Tests that GC doesn't wait for a removed replica and that an added
+    // replica starts out with the current state of the data-structure.
+    #[test]
+    fn test_add_remove_replica() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+
+        nr.remove_replica(1).unwrap();
+        assert!(nr.replica(1).is_released());
+        assert!(matches!(
+            nr.remove_replica(1),
+            Err(NodeReplicatedError::InvalidReplica)
+        ));
+        assert!(matches!(
+            nr.remove_replica(0),
+            Err(NodeReplicatedError::ReplicaInUse)
+        ));
+        assert!(nr.register(1).is_none());
+
+        let ops = 3 * nr.log.slog.len();
+        for _i in 0..ops {
+            assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        }
+        assert!(nr.log.head.load(Ordering::Relaxed) > 0);
+        assert_eq!(nr.log.ltails[1].load(Ordering::Relaxed), 0);
+
+        // Slot of the removed replica is re-used first.
+        assert_eq!(nr.add_replica().unwrap(), 1);
+        assert!(!nr.replica(1).is_released());
+        assert_eq!(nr.add_replica().unwrap(), 2);
+
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+        let ttkn2 = nr.register(2).expect("Unable to register with replica");
+        assert_eq!(nr.execute(0, ttkn1), Ok(ops as u64));
+        assert_eq!(nr.execute_mut(0, ttkn1), Ok(107));
+        assert_eq!(nr.execute(0, ttkn2), Ok(ops as u64 + 1));
+        assert_eq!(nr.execute(0, ttkn), Ok(ops as u64 + 1));
+    }
+
+    // Tests that a replica whose threads stop issuing operations is evicted
+    // once it holds up GC, and that it is rebuilt from a healthy replica when
+    // it is used again.
+    #[test]
+    fn test_evict_stalled_replica() {
+        let nr = NodeReplicated::<Data>::builder()
+            .replicas(2)
+            .log_entries(64)
+            .replica_config(ReplicaConfig {
+                max_threads: 2,
+                pending_ops: 4,
+            })
+            .evict_after(32)
+            .build()
+            .expect("Can't create Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+        assert_eq!(nr.execute_mut(0, ttkn1), Ok(107));
+
+        let ops = 3 * nr.log.slog.len();
+        for _i in 0..ops {
+            assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        }
+        assert!(nr.replica(1).is_dormant());
+        assert!(nr.log.head.load(Ordering::Relaxed) > nr.log.ltails[1].load(Ordering::Relaxed));
+
+        // The replica doesn't replay the overwritten entries.
+        assert_eq!(nr.execute(0, ttkn1), Ok(ops as u64 + 1));
+        assert!(!nr.replica(1).is_dormant());
+        assert_eq!(nr.execute_mut(0, ttkn1), Ok(107));
+        assert_eq!(nr.execute(0, ttkn), Ok(ops as u64 + 2));
+        #[cfg(feature = "stats")]
+        assert_eq!(nr.replica(1).stats().rebuilds, 1);
+    }
+
+    // Tests that replicas agree on the state while one of them is repeatedly
+    // evicted and rebuilt as its thread pauses between operations.
+    #[test]
+    fn test_evict_concurrent() {
+        let nr = Arc::new(
+            NodeReplicated::<Data>::builder()
+                .replicas(3)
+                .log_entries(1024)
+                .replica_config(ReplicaConfig {
+                    max_threads: 2,
+                    pending_ops: 4,
+                })
+                .evict_after(256)
+                .build()
+                .expect("Can't create Ds"),
+        );
+
+        let ops = 20000;
+        // Counts the threads (of replica 0 and 1) that are done.
+        let done = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..3)
+            .map(|rid| {
+                let nr = nr.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let ttkn = nr.register(rid).expect("Unable to register with replica");
+                    for i in 0..ops {
+                        // Pause until the others evicted the replica (or
+                        // finished without it).
+                        if rid == 2 && i % 1000 == 999 {
+                            while !nr.replica(2).is_dormant() && done.load(Ordering::Acquire) < 2 {
+                                std::thread::yield_now();
+                            }
+                        }
+                        assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+                    }
+                    done.fetch_add(1, Ordering::Release);
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        for rid in 0..3 {
+            let ttkn = nr.register(rid).expect("Unable to register with replica");
+            assert_eq!(nr.execute(0, ttkn), Ok(3 * ops));
+        }
+        #[cfg(feature = "stats")]
+        assert!(nr.replica(2).stats().rebuilds >= 1);
+    }
+
+    // Tests that we can't add more replicas than the log supports.
+    #[test]
+    fn test_add_replica_limit() {
+        let replicas = NonZeroUsize::new(1).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+
+        for rid in 1..MAX_REPLICAS_PER_LOG {
+            assert_eq!(nr.add_replica().unwrap(), rid);
+        }
+        assert!(matches!(
+            nr.add_replica(),
+            Err(NodeReplicatedError::TooManyReplicas)
+        ));
+    }
+
+    /// Panics on `None` operations.
+    #[derive(Default, Clone)]
+    struct Fragile(u64);
+
+    impl Dispatch for Fragile {
+        type ReadOperation<'rop> = ();
+        type WriteOperation = Option<u64>;
+        type Response = u64;
+
+        fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+            self.0
+        }
+
+        fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+            self.0 += op.expect("Invalid operation");
+            self.0
+        }
+    }
+
+    // Tests that a panic in `dispatch_mut` only poisons the replica that
+    // applied the operation and that the replica can be rebuilt afterwards.
+    #[test]
+    fn test_poisoned_replica() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Fragile>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
 
+        assert_eq!(nr.execute_mut(Some(1), ttkn0), 1);
+        let r = catch_unwind(AssertUnwindSafe(|| nr.try_execute_mut(None, ttkn0)));
+        #[cfg(feature = "std")]
+        assert!(matches!(r, Ok(Err(NodeReplicatedError::Poisoned(0)))));
+        #[cfg(not(feature = "std"))]
+        r.expect_err("Operation didn't panic");
+        assert!(matches!(
+            nr.try_execute((), ttkn0),
+            Err(NodeReplicatedError::Poisoned(0))
+        ));
+        assert!(matches!(
+            nr.try_execute_mut(Some(1), ttkn0),
+            Err(NodeReplicatedError::Poisoned(0))
+        ));
+        assert!(matches!(
+            nr.try_execute_stale((), ttkn0, 0),
+            Err(NodeReplicatedError::Poisoned(0))
+        ));
+        assert!(matches!(
+            nr.try_execute_mut_batch([Some(1)], ttkn0),
+            Err(BatchError {
+                error: NodeReplicatedError::Poisoned(0),
+                ..
+            })
+        ));
+        #[cfg(feature = "async")]
+        {
+            use futures::executor::block_on;
+
+            let mut fut = ReusableBoxFuture::new(async { Ok(0) });
+            block_on(nr.try_async_execute_mut(Some(1), ttkn0, &mut fut));
+            assert!(matches!(
+                block_on(&mut fut),
+                Err(NodeReplicatedError::Poisoned(0))
+            ));
+            nr.try_async_execute((), ttkn0, &mut fut);
+            assert!(matches!(
+                block_on(fut),
+                Err(NodeReplicatedError::Poisoned(0))
+            ));
+        }
+
+        // The other replica skips the operation that panicked.
+        assert_eq!(nr.execute_mut(Some(1), ttkn1), 2);
+        assert_eq!(nr.execute((), ttkn1), 2);
+
+        // Rebuild the poisoned replica from the healthy one.
+        assert!(matches!(
+            nr.remove_replica(1),
+            Err(NodeReplicatedError::ReplicaInUse)
+        ));
+        nr.unregister(ttkn0);
+        nr.remove_replica(0).unwrap();
+        assert_eq!(nr.add_replica().unwrap(), 0);
+
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        assert_eq!(nr.execute((), ttkn0), 2);
+        assert_eq!(nr.execute_mut(Some(1), ttkn0), 3);
+        assert_eq!(nr.execute((), ttkn1), 3);
+    }
+
+    // Tests that a batch in which an operation panics returns the responses
+    // of the operations before it and which operations were submitted.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_poisoned_batch() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Fragile>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+
+        // The first round completes, the second one fails.
+        let ops = (0..MAX_PENDING_OPS + 4).map(|i| (i != MAX_PENDING_OPS + 1).then_some(1));
+        match nr.try_execute_mut_batch(ops, ttkn0) {
+            Err(BatchError {
+                error: NodeReplicatedError::Poisoned(0),
+                responses,
+                submitted,
+            }) => {
+                assert_eq!(responses, (1..=MAX_PENDING_OPS as u64).collect::<Vec<_>>());
+                assert_eq!(submitted, MAX_PENDING_OPS + 4);
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        // The other replica applied the whole batch except for the operation
+        // that panicked.
+        assert_eq!(nr.execute((), ttkn1), MAX_PENDING_OPS as u64 + 3);
+    }
+
+    // Tests that an operation that panicked on another replica is skipped by
+    // the replica that issued it, and that only the issuing thread gets an
+    // error.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_skipped_operation() {
+        use core::sync::atomic::AtomicBool;
+
+        static RESUME: AtomicBool = AtomicBool::new(false);
+
+        // Like `Fragile`, but applying `Some(0)` on behalf of another replica
+        // blocks until `RESUME` is set.
+        #[derive(Default)]
+        struct Stalling(u64);
+
+        impl Dispatch for Stalling {
+            type ReadOperation<'rop> = ();
+            type WriteOperation = Option<u64>;
+            type Response = u64;
+
+            fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+                self.0
+            }
+
+            fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+                self.0 += op.expect("Invalid operation");
+                self.0
+            }
+
+            fn apply_mut(&mut self, op: Self::WriteOperation) {
+                while op == Some(0) && !RESUME.load(Ordering::Acquire) {
+                    std::thread::yield_now();
+                }
+                self.dispatch_mut(op);
+            }
+        }
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = Arc::new(NodeReplicated::<Stalling>::new(replicas, |_ac| 0).unwrap());
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+
+        // Replica 0 stalls on the operation of replica 1 while it applies its
+        // own one, so replica 1 gets to the operation of replica 0 first.
+        assert_eq!(nr.execute_mut(Some(0), ttkn1), 0);
+        let issuer = {
+            let nr = nr.clone();
+            std::thread::spawn(move || nr.try_execute_mut(None, ttkn0))
+        };
+        while nr.log.tail.load(Ordering::Relaxed) < 2 {
+            std::thread::yield_now();
+        }
+        assert!(matches!(
+            nr.try_execute_mut(Some(1), ttkn1),
+            Err(NodeReplicatedError::Poisoned(1))
+        ));
+        RESUME.store(true, Ordering::Release);
+        assert!(matches!(
+            issuer.join().unwrap(),
+            Err(NodeReplicatedError::Skipped)
+        ));
+
+        // Replica 0 is not affected, it applies the operation of replica 1
+        // along with its own.
+        assert_eq!(nr.execute_mut(Some(1), ttkn0), 2);
+        assert_eq!(nr.execute((), ttkn0), 2);
+    }
+
+    // Tests that operations are replayed from the write-ahead log into all
+    // replicas of a new instance.
+    #[test]
+    fn test_recover_from() {
+        use super::wal::test::MemSegment;
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let segment = MemSegment::default();
+        let nr = NodeReplicated::<Data>::recover_from(
+            replicas,
+            |_ac| 0,
+            segment.clone(),
+            SyncPolicy::Always,
+        )
+        .expect("Can't create Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+        for i in 0..100 {
+            assert_eq!(nr.execute_mut(i, ttkn0), Ok(107));
+            assert_eq!(nr.execute_mut(i, ttkn1), Ok(107));
+        }
+        drop(nr);
+
+        let nr = NodeReplicated::<Data>::recover_from(
+            replicas,
+            |_ac| 0,
+            segment.clone(),
+            SyncPolicy::Never,
+        )
+        .expect("Can't recover Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+        assert_eq!(nr.execute(0, ttkn0), Ok(200));
+        assert_eq!(nr.execute(0, ttkn1), Ok(200));
+
+        // New operations are appended to the recovered log.
+        assert_eq!(nr.execute_mut(0, ttkn1), Ok(107));
+        drop(nr);
+        let nr =
+            NodeReplicated::<Data>::recover_from(replicas, |_ac| 0, segment, SyncPolicy::Never)
+                .expect("Can't recover Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        assert_eq!(nr.execute(0, ttkn0), Ok(201));
+    }
+
+    impl LogSerialize for Option<u64> {
+        fn serialize(&self, buf: &mut Vec<u8>) {
+            if let Some(v) = self {
+                v.serialize(buf);
+            }
+        }
+
+        fn deserialize(buf: &[u8]) -> Option<Self> {
+            match buf.is_empty() {
+                true => Some(None),
+                false => u64::deserialize(buf).map(Some),
+            }
+        }
+    }
+
+    // Tests that an operation that panicked is not replayed when the instance
+    // is recovered from the write-ahead log.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_recover_poisoned() {
+        use super::wal::test::MemSegment;
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let segment = MemSegment::default();
+        let nr = NodeReplicated::<Fragile>::recover_from(
+            replicas,
+            |_ac| 0,
+            segment.clone(),
+            SyncPolicy::Always,
+        )
+        .expect("Can't create Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        let ttkn1 = nr.register(1).expect("Unable to register with replica");
+        assert_eq!(nr.execute_mut(Some(1), ttkn0), 1);
+        assert!(matches!(
+            nr.try_execute_mut(None, ttkn0),
+            Err(NodeReplicatedError::Poisoned(0))
+        ));
+        assert_eq!(nr.execute_mut(Some(1), ttkn1), 2);
+        drop(nr);
+
+        let nr =
+            NodeReplicated::<Fragile>::recover_from(replicas, |_ac| 0, segment, SyncPolicy::Always)
+                .expect("Can't recover Ds");
+        let ttkn0 = nr.register(0).expect("Unable to register with replica");
+        assert_eq!(nr.execute((), ttkn0), 2);
+        assert_eq!(nr.execute_mut(Some(1), ttkn0), 3);
+    }
+
+    // Tests that a follower applies the operations of all replicas of the
+    // leader and is read-only until the leader closes the transport.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_follow() {
+        use super::transport::ChannelTransport;
+        use std::sync::Arc;
+
+        let (to_follower, from_leader) = ChannelTransport::pair();
+        let leader = NodeReplicated::<Data>::builder()
+            .replicas(2)
+            .transport(to_follower)
+            .build()
+            .expect("Can't create Ds");
+        let follower = Arc::new(NodeReplicated::<Data>::builder().build().unwrap());
+        let ftkn = follower.register(0).unwrap();
+        let following = {
+            let follower = follower.clone();
+            std::thread::spawn(move || follower.follow(from_leader, ftkn))
+        };
+        while !follower.following.load(Ordering::Acquire) {
+            std::thread::yield_now();
+        }
+
+        let ttkn = follower.register(0).unwrap();
+        assert!(matches!(
+            follower.try_execute_mut(1, ttkn),
+            Err(NodeReplicatedError::ReadOnly)
+        ));
+        assert!(matches!(
+            follower.follow(ChannelTransport::pair().0, ttkn),
+            Err(NodeReplicatedError::ReadOnly)
+        ));
+
+        let ttkn0 = leader.register(0).unwrap();
+        let ttkn1 = leader.register(1).unwrap();
+        for i in 0..50 {
+            assert_eq!(leader.execute_mut(i, ttkn0), Ok(107));
+            assert_eq!(leader.execute_mut(i, ttkn1), Ok(107));
+        }
+        drop(leader);
+        following.join().unwrap().unwrap();
+
+        assert_eq!(follower.execute(0, ttkn), Ok(100));
+        assert_eq!(follower.execute_mut(0, ttkn), Ok(107));
+    }
+
+    impl Snapshot for Data {
+        fn snapshot(&self, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&self.junk.to_le_bytes());
+        }
+
+        fn restore(buf: &[u8]) -> Option<Self> {
+            use core::convert::TryInto;
+            let junk = u64::from_le_bytes(buf.try_into().ok()?);
+            Some(Data { junk })
+        }
+    }
+
+    // Tests that a checkpoint combined with the suffix of the write-ahead log
+    // restores the latest state.
+    #[test]
+    fn test_checkpoint_restore_from() {
+        use super::wal::test::MemSegment;
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let segment = MemSegment::default();
+        let nr = NodeReplicated::<Data>::recover_from(
+            replicas,
+            |_ac| 0,
+            segment.clone(),
+            SyncPolicy::Always,
+        )
+        .expect("Can't create Ds");
+        let ttkn = nr.register(1).expect("Unable to register with replica");
+        for i in 0..10 {
+            assert_eq!(nr.execute_mut(i, ttkn), Ok(107));
+        }
+        let checkpoint = nr.checkpoint().expect("Can't take checkpoint");
+        assert_eq!(checkpoint.position, 10);
+        for i in 0..5 {
+            assert_eq!(nr.execute_mut(i, ttkn), Ok(107));
+        }
+        drop(nr);
+
+        let nr = NodeReplicated::<Data>::restore_from(
+            replicas,
+            |_ac| 0,
+            &checkpoint,
+            segment.clone(),
+            SyncPolicy::Always,
+        )
+        .expect("Can't restore Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        assert_eq!(nr.execute(0, ttkn), Ok(15));
+        assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        assert_eq!(nr.checkpoint().expect("Can't take checkpoint").position, 16);
+        drop(nr);
+
+        let ahead = Checkpoint {
+            position: 17,
+            ..checkpoint
+        };
+        assert!(matches!(
+            NodeReplicated::<Data>::restore_from(
+                replicas,
+                |_ac| 0,
+                &ahead,
+                segment,
+                SyncPolicy::Always
+            ),
+            Err(NodeReplicatedError::InvalidCheckpoint)
+        ));
+    }
+
+    // Tests that restoring from an invalid image fails.
+    #[test]
+    fn test_restore_invalid() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let checkpoint = Checkpoint {
+            position: 0,
+            data: std::vec![1, 2, 3],
+            log_entries: 64,
+            config: ReplicaConfig::default(),
+        };
+        assert!(matches!(
+            NodeReplicated::<Data>::restore(replicas, |_ac| 0, &checkpoint),
+            Err(NodeReplicatedError::InvalidCheckpoint)
+        ));
+    }
+
+    // Tests that a restored instance has the log size and the replica
+    // configuration of the instance the checkpoint was taken from.
+    #[test]
+    fn test_restore_config() {
+        let config = ReplicaConfig {
+            max_threads: 2,
+            pending_ops: 4,
+        };
+        let nr = NodeReplicatedBuilder::<Data>::default()
+            .replicas(2)
+            .log_entries(64)
+            .replica_config(config)
+            .build()
+            .expect("Can't create Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        assert_eq!(nr.execute_mut(1, ttkn), Ok(107));
+        let checkpoint = nr.checkpoint().expect("Can't take checkpoint");
+        assert_eq!(checkpoint.log_entries, 64);
+        assert_eq!(checkpoint.config, config);
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Data>::restore(replicas, |_ac| 0, &checkpoint)
+            .expect("Can't restore Ds");
+        assert_eq!(nr.log.slog.len(), 64);
+        assert_eq!(nr.log.gc_from_head(), 8);
+        assert!(nr.register(0).is_some());
+        assert!(nr.register(0).is_some());
+        assert!(nr.register(0).is_none());
+    }
+
+    // Tests that the replicas and the log count combiner rounds, batches and
+    // the lag of the replicas.
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_stats() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+        for i in 0..3 {
+            assert_eq!(nr.execute_mut(i, ttkn), Ok(107));
+        }
+
+        let stats = nr.stats();
+        assert_eq!(stats.replicas.len(), 2);
+        let (rid, r0) = &stats.replicas[0];
+        assert_eq!(*rid, 0);
+        assert_eq!(r0.combiner.ops, 3);
+        assert_eq!(r0.combiner.combines, 3);
+        assert_eq!(r0.combiner.batch_sizes[0], 3);
+        assert_eq!(stats.replicas[1].1.combiner.ops, 0);
+        assert_eq!(stats.log.ctail, 3);
+        assert_eq!(stats.log.lag, std::vec![Some(0), Some(3)]);
+
+        let ttkn = nr.register(1).expect("Unable to register with replica");
+        assert_eq!(nr.execute(0, ttkn), Ok(3));
+        assert_eq!(nr.stats().log.lag, std::vec![Some(0), Some(0)]);
+
+        nr.unregister(ttkn);
+        nr.remove_replica(1).unwrap();
+        let stats = nr.stats();
+        assert_eq!(stats.replicas.len(), 1);
+        assert_eq!(stats.log.lag, std::vec![Some(0), None]);
+    }
+
+    // Tests that many in-flight async operations of multiple threads all
+    // complete (and complete with their own response).
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_execute_inflight() {
+        use futures::executor::block_on;
+        use futures::future::join_all;
+        use std::vec::Vec;
+
+        const THREADS: usize = 4;
+        const INFLIGHT: usize = 8;
+        const ROUNDS: usize = 256;
+
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr = Arc::new(NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds"));
+
+        let mut threads = Vec::with_capacity(THREADS);
+        for t in 0..THREADS {
+            let nr = nr.clone();
+            threads.push(std::thread::spawn(move || {
+                let ttkn = nr.register(t % replicas.get()).unwrap();
+                for _round in 0..ROUNDS {
+                    let mut futures: Vec<ReusableBoxFuture<<Data as Dispatch>::Response>> = (0
+                        ..INFLIGHT)
+                        .map(|_| ReusableBoxFuture::new(async { Ok(0) }))
+                        .collect();
+                    for (i, fut) in futures.iter_mut().enumerate() {
+                        if i % 2 == 0 {
+                            block_on(nr.async_execute_mut(0, ttkn, fut));
+                        } else {
+                            nr.async_execute(0, ttkn, fut);
+                        }
+                    }
+
+                    for (i, resp) in block_on(join_all(futures)).iter().enumerate() {
+                        if i % 2 == 0 {
+                            assert_eq!(*resp, Ok(107));
+                        } else {
+                            assert!(resp.unwrap() <= (THREADS * ROUNDS * INFLIGHT / 2) as u64);
+                        }
+                    }
+                }
+            }));
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let ttkn = nr.register(0).unwrap();
+        nr.sync(ttkn);
+        assert_eq!(
+            nr.execute(0, ttkn),
+            Ok((THREADS * ROUNDS * INFLIGHT / 2) as u64)
+        );
+    }
+
+    // Tests that dropping futures before they complete neither fills up the
+    // context for good nor hands their responses to later operations.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_execute_mut_cancelled() {
+        use futures::executor::block_on;
+        use futures::task::noop_waker;
+        use std::sync::mpsc::channel;
+
+        let replicas = NonZeroUsize::new(1).unwrap();
+        let nr = Arc::new(NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds"));
+        let ttkn = nr.register(0).expect("Unable to register with replica");
+
+        // The futures stay pending while the combiner lock is held.
+        let (locked_tx, locked_rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+        let nr1 = nr.clone();
+        let stuck = std::thread::spawn(move || {
+            nr1.replica(0).verify(&nr1.log, |_d| {
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+        });
+        locked_rx.recv().unwrap();
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut: ReusableBoxFuture<<Data as Dispatch>::Response> =
+            ReusableBoxFuture::new(async { Ok(0) });
+        for _i in 0..2 * MAX_PENDING_OPS {
+            block_on(nr.async_execute_mut(0, ttkn, &mut fut));
+            assert!(fut.poll(&mut cx).is_pending());
+        }
+        drop(fut);
+
+        release_tx.send(()).unwrap();
+        stuck.join().unwrap();
+
+        assert_eq!(nr.execute_mut(0, ttkn), Ok(107));
+        assert_eq!(nr.execute(0, ttkn), Ok(MAX_PENDING_OPS as u64 + 1));
+    }
+
+    #[cfg(feature = "async")]
     #[tokio::test]
     async fn test_box_reuse() {
         use futures::executor::block_on;
@@ -778,4 +3658,108 @@ mod test {
         let res = block_on(resp).unwrap();
         assert_eq!(res, 1);
     }
+
+    /// `register_local` maps the node of the thread to a replica.
+    #[test]
+    fn test_register_local() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let mut nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let node = Arc::new(AtomicUsize::new(3));
+        let lookup = node.clone();
+        nr.set_node_lookup(move || lookup.load(Ordering::Relaxed))
+            .unwrap();
+
+        // By default, nodes wrap around the replicas.
+        let ttkn = nr.register_local().unwrap();
+        assert_eq!(ttkn.rid, 1);
+
+        nr.set_node_mapping(|node| if node < 4 { 0 } else { 7 })
+            .unwrap();
+        assert_eq!(nr.register_local().unwrap().rid, 0);
+
+        // Mapped to a replica that doesn't exist.
+        node.store(4, Ordering::Relaxed);
+        assert!(nr.register_local().is_none());
+    }
+
+    /// Tokens of other instances, or of threads that unregistered, are
+    /// rejected.
+    #[test]
+    fn test_invalid_token() {
+        let replicas = NonZeroUsize::new(2).unwrap();
+        let nr1 = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let nr2 = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn1 = nr1.register(0).unwrap();
+        let ttkn2 = nr2.register(0).unwrap();
+        assert_eq!(ttkn1.rtkn, ttkn2.rtkn);
+
+        assert!(matches!(
+            nr2.try_execute_mut(1, ttkn1),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+        assert!(matches!(
+            nr1.try_execute(0, ttkn2),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+        assert_eq!(nr1.try_execute_mut(1, ttkn1).unwrap(), Ok(107));
+
+        // Out of range replica and thread index.
+        let forged = ThreadToken { rid: 7, ..ttkn1 };
+        assert!(matches!(
+            nr1.try_execute(0, forged),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+        let forged = ThreadToken {
+            rtkn: ReplicaToken(replica::MAX_THREADS_PER_REPLICA + 1, 0),
+            ..ttkn1
+        };
+        assert!(matches!(
+            nr1.try_execute_mut(1, forged),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+
+        nr1.unregister(ttkn1);
+        assert!(matches!(
+            nr1.try_execute(0, ttkn1),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+        assert!(matches!(
+            nr1.try_unregister(ttkn1),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+
+        // The slot of `ttkn1` is handed out again, but to a new generation.
+        let ttkn3 = nr1.register(0).expect("Unable to register with replica");
+        assert_eq!(ttkn3.rtkn.tid(), ttkn1.rtkn.tid());
+        assert!(matches!(
+            nr1.try_execute_mut(1, ttkn1),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+        assert_eq!(nr1.try_execute(0, ttkn3).unwrap(), Ok(1));
+
+        // Hand-made tokens don't belong to any instance.
+        assert!(matches!(
+            nr1.try_execute(0, ThreadToken::new(0, ttkn3.rtkn)),
+            Err(NodeReplicatedError::InvalidToken)
+        ));
+    }
+
+    /// More replicas than [`MAX_REPLICAS_PER_LOG`] can be created.
+    #[test]
+    fn test_many_replicas() {
+        let replicas = NonZeroUsize::new(2 * MAX_REPLICAS_PER_LOG).unwrap();
+        let nr = NodeReplicated::<Data>::new(replicas, |_ac| 0).expect("Can't create Ds");
+        let ttkn = nr.register(0).unwrap();
+        for i in 0..3 {
+            assert_eq!(nr.execute_mut(i, ttkn), Ok(107));
+        }
+
+        let ttkn = nr.register(replicas.get() - 1).unwrap();
+        assert_eq!(nr.execute(0, ttkn), Ok(3));
+        assert!(nr.register(replicas.get()).is_none());
+        assert!(matches!(
+            nr.add_replica(),
+            Err(NodeReplicatedError::TooManyReplicas)
+        ));
+    }
 }
@@ -0,0 +1,379 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! A ready-made [`AffinityChange`] handler for Linux.
+//!
+//! [`NumaAffinity`] reads the CPUs of every NUMA node from
+//! `/sys/devices/system/node` and migrates threads to the node of a replica
+//! whenever NR asks to change the affinity. On [`AffinityChange::Revert`] the
+//! exact CPU mask (and memory policy) the thread had before is restored.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # #![feature(generic_associated_types)]
+//! use std::num::NonZeroUsize;
+//! use node_replication::nr::numa::NumaAffinity;
+//! use node_replication::nr::{Dispatch, NodeReplicated};
+//!
+//! #[derive(Default)]
+//! struct Void;
+//! #
+//! # impl Dispatch for Void {
+//! #     type ReadOperation<'rop> = ();
+//! #     type WriteOperation = ();
+//! #     type Response = ();
+//! #
+//! #     fn dispatch(&self, _op: Self::ReadOperation<'_>) {}
+//! #     fn dispatch_mut(&mut self, _op: Self::WriteOperation) {}
+//! # }
+//!
+//! let numa = NumaAffinity::from_sysfs()
+//!     .expect("can't read NUMA topology")
+//!     .with_memory_policy(true);
+//! let replicas = NonZeroUsize::new(numa.nodes()).unwrap();
+//! let nr = NodeReplicated::<Void>::new(replicas, move |ac| numa.change(ac)).unwrap();
+//! ```
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::mem;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::{AffinityChange, ReplicaId};
+
+/// Where the kernel exposes the NUMA topology.
+const SYSFS_NODE_PATH: &str = "/sys/devices/system/node";
+
+/// Number of `c_ulong` words in the node masks we pass to the kernel (enough
+/// for 1024 nodes, the largest `CONFIG_NODES_SHIFT` Linux supports).
+const NODE_MASK_WORDS: usize = 1024 / (8 * mem::size_of::<libc::c_ulong>());
+
+/// A node mask as used by `get_mempolicy(2)` and `set_mempolicy(2)`.
+type NodeMask = [libc::c_ulong; NODE_MASK_WORDS];
+
+/// Affinity and memory policy of a thread before it was migrated.
+struct Saved {
+    cpus: libc::cpu_set_t,
+    policy: Option<(libc::c_int, NodeMask)>,
+}
+
+std::thread_local! {
+    /// Saved state of the current thread, one entry for every outstanding
+    /// [`AffinityChange::Replica`] (they can be nested).
+    static SAVED: RefCell<Vec<Saved>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Migrates threads to the NUMA node of a replica and back.
+///
+/// Replica `rid` is placed on the `rid`-th NUMA node with CPUs (in ascending
+/// node id order), wrapping around if there are more replicas than nodes.
+#[derive(Debug, Clone)]
+pub struct NumaAffinity {
+    /// (node id, CPUs of the node) for every node that has CPUs.
+    nodes: Vec<(usize, Vec<usize>)>,
+    /// Also prefer memory from the replica's node while migrated.
+    mem_policy: bool,
+}
+
+impl NumaAffinity {
+    /// Reads the NUMA topology from `/sys/devices/system/node`.
+    ///
+    /// Fails if the topology can't be read or if there is no node with CPUs
+    /// (e.g., on kernels without NUMA support).
+    pub fn from_sysfs() -> io::Result<Self> {
+        Self::from_path(Path::new(SYSFS_NODE_PATH))
+    }
+
+    fn from_path(path: &Path) -> io::Result<Self> {
+        let mut nodes = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let node = match name
+                .to_str()
+                .and_then(|n| n.strip_prefix("node"))
+                .and_then(|n| n.parse::<usize>().ok())
+            {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let cpulist = fs::read_to_string(entry.path().join("cpulist"))?;
+            let cpus = parse_cpulist(&cpulist)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, cpulist.trim()))?;
+            if !cpus.is_empty() {
+                nodes.push((node, cpus));
+            }
+        }
+
+        if nodes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no NUMA node with CPUs",
+            ));
+        }
+        nodes.sort_unstable();
+
+        Ok(NumaAffinity {
+            nodes,
+            mem_policy: false,
+        })
+    }
+
+    /// Whether to also set the memory policy of migrated threads to prefer
+    /// memory from the replica's node (disabled by default).
+    ///
+    /// With the default (first-touch) policy, memory is already allocated on
+    /// the node a thread runs on. Setting the policy makes this explicit and
+    /// also applies if the thread had a different policy before.
+    pub fn with_memory_policy(mut self, enable: bool) -> Self {
+        self.mem_policy = enable;
+        self
+    }
+
+    /// Number of NUMA nodes with CPUs.
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The NUMA node id that replica `rid` is placed on.
+    pub fn node(&self, rid: ReplicaId) -> usize {
+        self.nodes[rid % self.nodes.len()].0
+    }
+
+    /// The CPUs that replica `rid` is placed on.
+    pub fn cpus(&self, rid: ReplicaId) -> &[usize] {
+        &self.nodes[rid % self.nodes.len()].1
+    }
+
+    /// Handles an [`AffinityChange`] for the calling thread, this is meant to
+    /// be passed to [`super::NodeReplicated::new`].
+    ///
+    /// Failing to change the affinity is not fatal for NR (only slower), so
+    /// errors are logged and otherwise ignored.
+    pub fn change(&self, change: AffinityChange) -> usize {
+        match change {
+            AffinityChange::Replica(rid) => {
+                let saved = Saved {
+                    cpus: get_affinity(),
+                    policy: if self.mem_policy {
+                        get_mempolicy()
+                    } else {
+                        None
+                    },
+                };
+
+                // Safety: An all-zero `cpu_set_t` is an empty set.
+                let mut cpus: libc::cpu_set_t = unsafe { mem::zeroed() };
+                for &cpu in self.cpus(rid) {
+                    if cpu < 8 * mem::size_of::<libc::cpu_set_t>() {
+                        // Safety: `cpu` is within the set.
+                        unsafe { libc::CPU_SET(cpu, &mut cpus) };
+                    }
+                }
+                set_affinity(&cpus);
+
+                if self.mem_policy {
+                    let mut mask: NodeMask = [0; NODE_MASK_WORDS];
+                    let node = self.node(rid);
+                    let bits = 8 * mem::size_of::<libc::c_ulong>();
+                    if node < NODE_MASK_WORDS * bits {
+                        mask[node / bits] |= 1 << (node % bits);
+                        set_mempolicy(libc::MPOL_PREFERRED, &mask);
+                    }
+                }
+
+                SAVED.with(|s| {
+                    let mut s = s.borrow_mut();
+                    s.push(saved);
+                    s.len() - 1
+                })
+            }
+            AffinityChange::Revert(depth) => {
+                let saved = SAVED.with(|s| {
+                    let mut s = s.borrow_mut();
+                    if depth < s.len() {
+                        // Everything above `depth` belongs to changes that
+                        // were never reverted, restoring `depth` undoes them
+                        // too.
+                        s.truncate(depth + 1);
+                        s.pop()
+                    } else {
+                        None
+                    }
+                });
+
+                match saved {
+                    Some(saved) => {
+                        set_affinity(&saved.cpus);
+                        if let Some((mode, mask)) = saved.policy {
+                            set_mempolicy(mode, &mask);
+                        }
+                    }
+                    None => warn!("Nothing to revert for affinity change {}", depth),
+                }
+
+                0
+            }
+        }
+    }
+}
+
+/// Returns the NUMA node the calling thread currently runs on (0 if it can't
+/// be determined).
+pub fn current_node() -> usize {
+    let mut cpu: libc::c_uint = 0;
+    let mut node: libc::c_uint = 0;
+    // Safety: The kernel writes `cpu` and `node`, the cache argument is unused
+    // since Linux 2.6.24.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_getcpu,
+            &mut cpu as *mut libc::c_uint,
+            &mut node as *mut libc::c_uint,
+            core::ptr::null_mut::<libc::c_void>(),
+        )
+    };
+    if r != 0 {
+        warn!("getcpu failed: {}", io::Error::last_os_error());
+        return 0;
+    }
+    node as usize
+}
+
+/// Parses a list of CPUs as found in sysfs (e.g., `0-3,8,10-11`).
+fn parse_cpulist(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        match range.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (from.parse::<usize>().ok()?, to.parse::<usize>().ok()?);
+                if from > to {
+                    return None;
+                }
+                cpus.extend(from..=to);
+            }
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+fn get_affinity() -> libc::cpu_set_t {
+    // Safety: An all-zero `cpu_set_t` is an empty set, the kernel writes at
+    // most `size_of::<cpu_set_t>()` bytes.
+    unsafe {
+        let mut cpus: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut cpus) != 0 {
+            warn!("sched_getaffinity failed: {}", io::Error::last_os_error());
+        }
+        cpus
+    }
+}
+
+fn set_affinity(cpus: &libc::cpu_set_t) {
+    // Safety: The kernel reads at most `size_of::<cpu_set_t>()` bytes.
+    let r = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), cpus) };
+    if r != 0 {
+        warn!("sched_setaffinity failed: {}", io::Error::last_os_error());
+    }
+}
+
+fn get_mempolicy() -> Option<(libc::c_int, NodeMask)> {
+    let mut mode: libc::c_int = 0;
+    let mut mask: NodeMask = [0; NODE_MASK_WORDS];
+    // Safety: The kernel writes `mode` and at most `maxnode - 1` bits of
+    // `mask`.
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_get_mempolicy,
+            &mut mode as *mut libc::c_int,
+            mask.as_mut_ptr(),
+            NODE_MASK_WORDS * 8 * mem::size_of::<libc::c_ulong>() + 1,
+            core::ptr::null_mut::<libc::c_void>(),
+            0 as libc::c_ulong,
+        )
+    };
+    if r != 0 {
+        warn!("get_mempolicy failed: {}", io::Error::last_os_error());
+        return None;
+    }
+    Some((mode, mask))
+}
+
+fn set_mempolicy(mode: libc::c_int, mask: &NodeMask) {
+    // `MPOL_DEFAULT` and `MPOL_LOCAL` must come with an empty mask.
+    let (ptr, maxnode) = if mask.iter().all(|w| *w == 0) {
+        (core::ptr::null(), 0)
+    } else {
+        (
+            mask.as_ptr(),
+            NODE_MASK_WORDS * 8 * mem::size_of::<libc::c_ulong>() + 1,
+        )
+    };
+    // Safety: The kernel reads at most `maxnode - 1` bits of `mask`.
+    let r = unsafe { libc::syscall(libc::SYS_set_mempolicy, mode, ptr, maxnode) };
+    if r != 0 {
+        warn!("set_mempolicy failed: {}", io::Error::last_os_error());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpulist() {
+        assert_eq!(parse_cpulist("0\n"), Some(std::vec![0]));
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11\n"),
+            Some(std::vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpulist("\n"), Some(std::vec![]));
+        assert_eq!(parse_cpulist("3-1"), None);
+        assert_eq!(parse_cpulist("a-b"), None);
+    }
+
+    #[test]
+    fn test_current_node() {
+        if let Ok(numa) = NumaAffinity::from_sysfs() {
+            let node = current_node();
+            assert!(numa.nodes.iter().any(|(n, _cpus)| *n == node));
+        }
+    }
+
+    /// Reverting restores the exact mask the thread had before.
+    #[test]
+    fn test_change_revert() {
+        let numa = match NumaAffinity::from_sysfs() {
+            Ok(numa) => numa.with_memory_policy(true),
+            // No NUMA support in the test environment.
+            Err(_) => return,
+        };
+
+        let original = get_affinity();
+        let cpu = numa.cpus(0)[0];
+        // Safety: An all-zero `cpu_set_t` is an empty set.
+        let mut only: libc::cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut only) };
+        set_affinity(&only);
+
+        let old = numa.change(AffinityChange::Replica(0));
+        let during = get_affinity();
+        for &c in numa.cpus(0) {
+            assert!(unsafe { libc::CPU_ISSET(c, &during) });
+        }
+
+        let nested = numa.change(AffinityChange::Replica(1));
+        numa.change(AffinityChange::Revert(nested));
+        assert!(unsafe { libc::CPU_EQUAL(&get_affinity(), &during) });
+
+        numa.change(AffinityChange::Revert(old));
+        assert!(unsafe { libc::CPU_EQUAL(&get_affinity(), &only) });
+
+        set_affinity(&original);
+    }
+}
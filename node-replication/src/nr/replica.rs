@@ -7,25 +7,36 @@
 //! the data-structure are synchronized with respect to the order in the shared
 //! [`Log`].
 
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::cell::RefCell;
+use core::cell::{RefCell, UnsafeCell};
 use core::fmt::{self, Debug};
-use core::hint::spin_loop;
+use core::mem::ManuallyDrop;
+#[cfg(feature = "async")]
+use core::sync::atomic::fence;
 #[cfg(not(loom))]
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "async")]
+use core::task::{self, Poll, Waker};
 
 use crossbeam_utils::CachePadded;
 #[cfg(loom)]
-use loom::sync::atomic::{AtomicUsize, Ordering};
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use super::context::Context;
 use super::log::{Log, LogToken};
 use super::rwlock::RwLock;
+use super::wal::{Wal, WalError};
 use super::Dispatch;
+use crate::stats::ReplicaCounters;
+#[cfg(feature = "stats")]
+use crate::stats::ReplicaStats;
+use crate::wait::Waiter;
 
 pub use crate::replica::ReplicaId;
 pub use crate::replica::ReplicaToken;
 pub use crate::replica::MAX_THREADS_PER_REPLICA;
+pub use crate::replica::{ConfigError, ReplicaConfig};
 
 /// Errors a replica can encounter (and return to clients) when they execute
 /// operations.
@@ -33,7 +44,12 @@ pub use crate::replica::MAX_THREADS_PER_REPLICA;
 /// Note that these errors are not fatal and are resolved as part of the
 /// [`crate::nr::NodeReplicated`] logic and not passed on to clients. Therefore,
 /// clients of the library don't need to worry about this if they don't
-/// implement their own version of [`crate::nr::NodeReplicated`].
+/// implement their own version of [`crate::nr::NodeReplicated`]. The exceptions
+/// are [`ReplicaError::Poisoned`], [`ReplicaError::Skipped`] and
+/// [`ReplicaError::Wal`] which are reported as
+/// [`crate::nr::NodeReplicatedError::Poisoned`],
+/// [`crate::nr::NodeReplicatedError::Skipped`] and
+/// [`crate::nr::NodeReplicatedError::Wal`].
 pub enum ReplicaError<'r, D>
 where
     D: Sized + Dispatch + Sync,
@@ -72,6 +88,32 @@ where
     /// If we get this error during [`Replica::execute_mut`] it means that the
     /// system did manage to execute the operations since GC happens afterwards.
     GcFailed(ReplicaId),
+
+    /// The replica is poisoned because [`Dispatch::dispatch_mut`] panicked
+    /// while operations were applied to it.
+    ///
+    /// The data-structure of a poisoned replica may be in an inconsistent state
+    /// and the replica no longer applies operations from the log. Operations of
+    /// its threads that did not complete are lost. Other replicas of the same
+    /// log are not affected: those that reach the operation that panicked
+    /// afterwards skip it. The replica can be rebuilt from a
+    /// healthy one, see [`crate::nr::NodeReplicated::remove_replica`] and
+    /// [`crate::nr::NodeReplicated::add_replica`].
+    Poisoned,
+
+    /// The operation of the thread panicked on another replica and was
+    /// skipped by this one (see [`ReplicaError::Poisoned`]). Only the thread
+    /// that issued the operation gets this error, the replica is not
+    /// affected.
+    Skipped,
+
+    /// The combiner couldn't write its batch of operations to the write-ahead
+    /// log (see [`crate::nr::wal`]).
+    ///
+    /// The operations are applied and their responses handed back to the
+    /// threads (like with [`ReplicaError::GcFailed`]), but they may not be
+    /// durable.
+    Wal(WalError),
 }
 
 impl<D> Debug for ReplicaError<'_, D>
@@ -86,10 +128,17 @@ where
             ReplicaError::GcFailed(rid) => {
                 write!(f, "ReplicaError::GcFailed(rid = {})", rid)
             }
+            ReplicaError::Poisoned => write!(f, "ReplicaError::Poisoned"),
+            ReplicaError::Skipped => write!(f, "ReplicaError::Skipped"),
+            ReplicaError::Wal(e) => write!(f, "ReplicaError::Wal({:?})", e),
         }
     }
 }
 
+/// The context of a thread registered with a [`Replica`]. The response of an
+/// operation that was skipped is `None` (see [`ReplicaError::Skipped`]).
+type ThreadContext<D> = Context<<D as Dispatch>::WriteOperation, Option<<D as Dispatch>::Response>>;
+
 /// An instance of a replicated data structure which uses a shared [`Log`] to
 /// scale operations on the data structure across cores and processors.
 ///
@@ -132,13 +181,54 @@ where
     /// with the replica when calling [`Replica::register()`].
     next: CachePadded<AtomicUsize>,
 
+    /// Marks thread indices (below `next`) whose threads unregistered with
+    /// [`Replica::unregister()`]. Index `i` is true if the slot of thread
+    /// [`crate::replica::ThreadIdx`] `i + 1` is free and can be handed out
+    /// again by [`Replica::register()`].
+    vacant: Vec<AtomicBool>,
+
+    /// The generation of every thread slot, bumped when the thread of the
+    /// slot unregisters. Index `i` belongs to thread
+    /// [`crate::replica::ThreadIdx`] `i + 1` (see [`ReplicaToken`]).
+    generations: Vec<AtomicUsize>,
+
+    /// Set while the replica is retired (see [`Replica::retire()`]). A retired
+    /// replica no longer consumes the log and doesn't accept new threads until
+    /// it is revived with [`Replica::revive()`].
+    retired: AtomicBool,
+
+    /// Set once [`Dispatch::dispatch_mut`] panicked on this replica (see
+    /// [`ReplicaError::Poisoned`]). Cleared when the replica is revived with
+    /// [`Replica::revive()`].
+    poisoned: AtomicBool,
+
+    /// Set while the replica is dormant (see [`Replica::evict()`]). A dormant
+    /// replica gave up its slot in the log and has to be rebuilt with
+    /// [`Replica::revive()`] before its threads can use it again.
+    dormant: AtomicBool,
+
+    /// Number of threads that currently operate on the replica (see
+    /// [`Replica::enter()`]). The replica is only evicted while there are
+    /// none.
+    active: CachePadded<AtomicUsize>,
+
+    /// Number of thread contexts that have [`Waker`]s registered, i.e., tasks
+    /// that wait for the combiner (see [`Replica::poll_execute_mut()`]).
+    #[cfg(feature = "async")]
+    waiters: AtomicUsize,
+
     /// List of per-thread contexts. Threads buffer write operations here when
     /// they cannot perform flat combining (because another thread might already
     /// be doing so).
     ///
-    /// The vector is initialized with [`MAX_THREADS_PER_REPLICA`] [`Context`]
-    /// elements.
-    contexts: Vec<Context<<D as Dispatch>::WriteOperation, <D as Dispatch>::Response>>,
+    /// The vector is initialized with one [`Context`] for each of the
+    /// `max_threads` threads of the [`ReplicaConfig`]. It is emptied when the
+    /// replica is released (see [`Replica::release()`]) and filled again by
+    /// [`Replica::revive()`].
+    ///
+    /// A response is `None` if the operation was skipped (see
+    /// [`ReplicaError::Skipped`]).
+    contexts: UnsafeCell<Vec<ThreadContext<D>>>,
 
     /// A buffer of operations for flat combining.
     ///
@@ -147,28 +237,75 @@ where
     /// `compare_and_swap` on the tail of the log.
     buffer: RefCell<Vec<<D as Dispatch>::WriteOperation>>,
 
+    /// The operations the combiner appends instead of `buffer` if
+    /// [`Dispatch::coalesce`] coalesced them (only allocated if `D` does).
+    coalesced: RefCell<Vec<<D as Dispatch>::WriteOperation>>,
+
+    /// Number of operations of `buffer` each operation in `coalesced` replaces.
+    replaced: RefCell<Vec<usize>>,
+
+    /// The responses [`Dispatch::fan_out`] derives for the operations that a
+    /// coalesced operation replaced.
+    fanned: RefCell<Vec<<D as Dispatch>::Response>>,
+
     /// Number of operations collected by the combiner from each thread at any
     /// given point of time. Index `i` holds the number of operations collected
     /// from thread with [`crate::replica::ThreadIdx`] `i + 1`.
-    inflight: RefCell<[usize; MAX_THREADS_PER_REPLICA]>,
+    inflight: RefCell<Vec<usize>>,
 
     /// A buffer of results collected after flat combining. With the help of
     /// `inflight`, the combiner enqueues these results into the appropriate
     /// thread context.
-    result: RefCell<Vec<<D as Dispatch>::Response>>,
+    result: RefCell<Vec<Option<<D as Dispatch>::Response>>>,
+
+    /// A buffer the combiner serializes its batch of operations into if the
+    /// replica writes them to a write-ahead log (see [`crate::nr::wal`]).
+    records: RefCell<Vec<u8>>,
 
     /// The underlying data structure. This is shared among all threads that are
     /// registered with this replica. Each replica maintains its own copy of
     /// `data`.
-    data: CachePadded<RwLock<D>>,
+    ///
+    /// Dropped early if the replica is released (see [`Replica::release()`]),
+    /// otherwise when the replica is dropped.
+    data: CachePadded<RwLock<ManuallyDrop<D>>>,
+
+    /// Configuration the replica was created with, used to set up the
+    /// contexts again when a released replica is revived.
+    config: ReplicaConfig,
+
+    /// Write-ahead log that the combiner writes the operations to that it
+    /// appends to the shared log (if the operations are persisted, see
+    /// [`crate::nr::wal`]).
+    wal: Option<Arc<Wal<<D as Dispatch>::WriteOperation>>>,
+
+    /// How threads wait for the replica (see [`crate::wait`]).
+    waiter: Waiter,
+
+    /// Runtime statistics of the replica (see [`crate::stats`]).
+    pub(crate) stats: ReplicaCounters,
 }
 
 /// The Replica is [`Sync`].
 ///
 /// Member variables are protected by the combiner lock of the replica
-/// (`combiner`). Contexts are thread-safe.
+/// (`combiner`). Contexts are thread-safe, the vector that holds them is only
+/// replaced while no thread uses the replica (see [`Replica::release()`]).
 unsafe impl<D> Sync for Replica<D> where D: Sized + Sync + Dispatch {}
 
+impl<D> Drop for Replica<D>
+where
+    D: Sized + Dispatch + Sync,
+{
+    fn drop(&mut self) {
+        if !self.is_released() {
+            let mut data = self.data.write(self.next.load(Ordering::Relaxed));
+            // Safety: Not dropped by `release()`, and never used again.
+            unsafe { ManuallyDrop::drop(&mut data) };
+        }
+    }
+}
+
 impl<D> core::fmt::Debug for Replica<D>
 where
     D: Sized + Sync + Dispatch,
@@ -285,6 +422,11 @@ where
     /// would be a disaster.
     fn drop(&mut self) {
         self.replica.combiner.store(0, Ordering::Release);
+        self.replica.waiter.notify();
+
+        // Tasks that failed to acquire the lock wait for us to release it.
+        #[cfg(feature = "async")]
+        self.replica.wake_waiters();
     }
 }
 
@@ -297,6 +439,54 @@ where
     }
 }
 
+/// Poisons the replica if it is dropped while unwinding from a panic in
+/// [`Dispatch::dispatch_mut`] (see [`Replica::apply()`]).
+struct PoisonOnUnwind<'a, D>
+where
+    D: Sized + Dispatch + Sync,
+{
+    replica: &'a Replica<D>,
+    slog: &'a Log<<D as Dispatch>::WriteOperation>,
+}
+
+impl<D> Drop for PoisonOnUnwind<'_, D>
+where
+    D: Sized + Dispatch + Sync,
+{
+    fn drop(&mut self) {
+        if let Some(wal) = self.replica.wal.as_ref() {
+            // Recovery skips the operation that panicked.
+            if let Some(pos) = self.slog.poisoned_at(&self.replica.log_tkn) {
+                if let Err(e) = wal.poisoned(wal.base() + pos) {
+                    warn!(
+                        "Can't mark operation {} as poisoned in the WAL: {:?}",
+                        pos, e
+                    );
+                }
+            }
+        }
+        self.replica.poison(self.slog);
+    }
+}
+
+/// Marks a thread as operating on the replica, which keeps the replica from
+/// being evicted (see [`Replica::enter()`]).
+pub(crate) struct Active<'a, D>
+where
+    D: Sized + Dispatch + Sync,
+{
+    replica: &'a Replica<D>,
+}
+
+impl<D> Drop for Active<'_, D>
+where
+    D: Sized + Dispatch + Sync,
+{
+    fn drop(&mut self) {
+        self.replica.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl<D> Replica<D>
 where
     D: Sized + Dispatch + Sync,
@@ -311,42 +501,120 @@ where
     ///   data-structure. If not, operations when executed on different replicas
     ///   may give different results.
     pub fn with_data(log_tkn: LogToken, d: D) -> Replica<D> {
-        let mut contexts = Vec::with_capacity(MAX_THREADS_PER_REPLICA);
-        // Add `MAX_THREADS_PER_REPLICA` contexts
-        for _idx in 0..MAX_THREADS_PER_REPLICA {
-            contexts.push(Default::default());
+        Replica::allocate(log_tkn, ReplicaConfig::default(), d)
+    }
+
+    /// Similar to [`Replica::with_data`], but the number of threads and the
+    /// depth of their contexts is given by `config` (instead of
+    /// [`MAX_THREADS_PER_REPLICA`] and [`crate::context::MAX_PENDING_OPS`]).
+    ///
+    /// The log of `log_tkn` must have been created with the same
+    /// configuration (see [`crate::log::Log::new_with_config`]). Fails if
+    /// `config` is unusable (see [`ReplicaConfig::validate`]).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #![feature(generic_associated_types)]
+    /// use node_replication::nr::{Dispatch, Log, Replica, ReplicaConfig};
+    ///
+    /// #[derive(Default)]
+    /// struct Void;
+    /// impl Dispatch for Void {
+    ///     type ReadOperation<'rop> = ();
+    ///     type WriteOperation = ();
+    ///     type Response = ();
+    ///
+    ///     fn dispatch<'rop>(&self, _op: Self::ReadOperation<'rop>) {}
+    ///     fn dispatch_mut(&mut self, _op: Self::WriteOperation) {}
+    /// }
+    ///
+    /// // A replica for at most four threads with up to eight pending
+    /// // operations each.
+    /// let config = ReplicaConfig { max_threads: 4, pending_ops: 8 };
+    /// let log = Log::<()>::new_with_config(1024 * 1024, 1, config, ()).unwrap();
+    /// let replica = Replica::with_config(log.register().unwrap(), config, Void).unwrap();
+    /// for _i in 0..4 {
+    ///     assert!(replica.register().is_some());
+    /// }
+    /// assert!(replica.register().is_none());
+    /// ```
+    pub fn with_config(
+        log_tkn: LogToken,
+        config: ReplicaConfig,
+        d: D,
+    ) -> Result<Replica<D>, ConfigError> {
+        config.validate()?;
+        Ok(Replica::allocate(log_tkn, config, d))
+    }
+
+    /// Creates a replica for a valid `config`.
+    pub(crate) fn allocate(log_tkn: LogToken, config: ReplicaConfig, d: D) -> Replica<D> {
+        let mut contexts = Vec::with_capacity(config.max_threads);
+        let mut vacant = Vec::with_capacity(config.max_threads);
+        let mut generations = Vec::with_capacity(config.max_threads);
+        // Add `max_threads` contexts
+        for idx in 0..config.max_threads {
+            contexts.push(Context::new(idx + 1, config.pending_ops));
+            vacant.push(AtomicBool::new(false));
+            generations.push(AtomicUsize::new(0));
         }
 
         Replica {
             log_tkn,
             combiner: CachePadded::new(AtomicUsize::new(0)),
             next: CachePadded::new(AtomicUsize::new(1)),
-            contexts,
-            buffer:
-                RefCell::new(
-                    Vec::with_capacity(
-                        MAX_THREADS_PER_REPLICA
-                            * Context::<
-                                <D as Dispatch>::WriteOperation,
-                                <D as Dispatch>::Response,
-                            >::batch_size(),
-                    ),
-                ),
-            inflight: RefCell::new([0; MAX_THREADS_PER_REPLICA]),
-            result:
-                RefCell::new(
-                    Vec::with_capacity(
-                        MAX_THREADS_PER_REPLICA
-                            * Context::<
-                                <D as Dispatch>::WriteOperation,
-                                <D as Dispatch>::Response,
-                            >::batch_size(),
-                    ),
-                ),
-            data: CachePadded::new(RwLock::<D>::new(d)),
+            vacant,
+            generations,
+            retired: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            dormant: AtomicBool::new(false),
+            active: CachePadded::new(AtomicUsize::new(0)),
+            #[cfg(feature = "async")]
+            waiters: AtomicUsize::new(0),
+            contexts: UnsafeCell::new(contexts),
+            buffer: RefCell::new(Vec::with_capacity(config.max_batch())),
+            coalesced: RefCell::new(Vec::new()),
+            replaced: RefCell::new(Vec::new()),
+            fanned: RefCell::new(Vec::new()),
+            inflight: RefCell::new(alloc::vec![0; config.max_threads]),
+            result: RefCell::new(Vec::with_capacity(config.max_batch())),
+            records: RefCell::new(Vec::new()),
+            data: CachePadded::new(RwLock::with_readers(
+                ManuallyDrop::new(d),
+                config.max_threads,
+            )),
+            config,
+            wal: None,
+            waiter: Default::default(),
+            stats: Default::default(),
         }
     }
 
+    /// Makes the combiner of the replica write the operations it appends to
+    /// the shared log to `wal` as well.
+    pub(crate) fn with_wal(
+        mut self,
+        wal: Option<Arc<Wal<<D as Dispatch>::WriteOperation>>>,
+    ) -> Self {
+        self.wal = wal;
+        self
+    }
+
+    /// Makes the threads that wait for the replica (or its data) use `waiter`
+    /// instead of the installed strategy.
+    pub(crate) fn with_waiter(mut self, waiter: Waiter) -> Self {
+        self.waiter = waiter;
+        self.data.waiter = waiter;
+        self
+    }
+
+    /// Returns a snapshot of the runtime statistics of the replica.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> ReplicaStats {
+        self.stats.snapshot()
+    }
+
     /// Registers a thread with this replica. Returns a [`ReplicaToken`] if the
     /// registration was successfull. None if the registration failed.
     ///
@@ -397,11 +665,36 @@ where
     /// let thrtkn = replica.register().expect("Failed to register with replica.");
     /// ```
     pub fn register(&self) -> Option<ReplicaToken> {
+        let idx = self.claim_thread_slot()?;
+
+        // Back off in case we raced with `retire()`.
+        if self.retired.load(Ordering::SeqCst) {
+            self.vacant[idx.0 - 1].store(true, Ordering::SeqCst);
+            return None;
+        }
+
+        Some(idx)
+    }
+
+    /// Hands out a free thread index for [`Replica::register()`].
+    fn claim_thread_slot(&self) -> Option<ReplicaToken> {
+        // Prefer the slot of a thread that unregistered earlier.
+        let next = core::cmp::min(self.next.load(Ordering::SeqCst), self.vacant.len() + 1);
+        for idx in 1..next {
+            if self.vacant[idx - 1]
+                .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                let generation = self.generations[idx - 1].load(Ordering::SeqCst);
+                return Some(ReplicaToken(idx, generation));
+            }
+        }
+
         // Loop until we either run out of identifiers or we manage to increment `next`.
         loop {
             let idx = self.next.load(Ordering::SeqCst);
 
-            if idx > MAX_THREADS_PER_REPLICA {
+            if idx > self.vacant.len() {
                 return None;
             };
 
@@ -413,8 +706,99 @@ where
                 continue;
             };
 
-            return Some(ReplicaToken(idx));
+            return Some(ReplicaToken(idx, 0));
+        }
+    }
+
+    /// Unregisters a thread from this replica, the slot of the thread is
+    /// handed out again by subsequent [`Replica::register`] calls.
+    ///
+    /// Before the slot is released, we wait until all operations the thread
+    /// still has in its context are executed and their responses are dropped.
+    ///
+    /// # Arguments
+    /// - `slog`: Is a reference to the shared log (see
+    ///   [`Replica::execute_mut`]).
+    /// - `idx`: The token of the thread obtained from [`Replica::register`].
+    ///   It is a bug to use `idx` (or a copy of it) for anything after this
+    ///   method returned `Ok`.
+    ///
+    /// # Returns
+    /// An error if we needed to combine to drain the context of the thread but
+    /// couldn't make progress because another replica was lagging behind. In
+    /// this case, the thread is still registered and the call should be
+    /// retried after the reported replica has made progress.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #![feature(generic_associated_types)]
+    /// # use node_replication::nr::Dispatch;
+    /// use node_replication::nr::Log;
+    /// use node_replication::nr::Replica;
+    ///
+    /// #[derive(Default)]
+    /// struct Data {
+    ///     junk: u64,
+    /// }
+    /// #
+    /// # impl Dispatch for Data {
+    /// #     type ReadOperation<'rop> = ();
+    /// #     type WriteOperation = u64;
+    /// #     type Response = Option<u64>;
+    /// #
+    /// #     fn dispatch<'rop>(
+    /// #         &self,
+    /// #         _op: Self::ReadOperation<'rop>,
+    /// #     ) -> Self::Response {
+    /// #         Some(self.junk)
+    /// #     }
+    /// #
+    /// #     fn dispatch_mut(
+    /// #         &mut self,
+    /// #         op: Self::WriteOperation,
+    /// #     ) -> Self::Response {
+    /// #         self.junk = op;
+    /// #         None
+    /// #     }
+    /// # }
+    ///
+    /// let log = Log::<<Data as Dispatch>::WriteOperation>::default();
+    /// let logtkn = log.register().unwrap();
+    /// let replica = Replica::<Data>::new(logtkn);
+    ///
+    /// let thrtkn = replica.register().expect("Failed to register with replica.");
+    /// replica.unregister(&log, thrtkn).expect("Failed to unregister.");
+    ///
+    /// // The slot of the unregistered thread is re-used, under a new token.
+    /// let again = replica.register().unwrap();
+    /// assert_eq!(again.tid(), thrtkn.tid());
+    /// assert_ne!(again, thrtkn);
+    /// ```
+    pub fn unregister(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        idx: ReplicaToken,
+    ) -> Result<(), ReplicaError<D>> {
+        let ctxt = &self.contexts()[idx.tid() - 1];
+        let mut iteration = 0;
+        while ctxt.has_outstanding() {
+            if self.is_poisoned() {
+                // Nobody will complete the operations, discard them.
+                let _combiner_lock = self.lock_combiner();
+                ctxt.clear();
+                break;
+            }
+            if ctxt.res().is_none() {
+                self.try_combine(slog)?;
+                self.waiter.wait(iteration);
+                iteration += 1;
+            }
         }
+
+        self.generations[idx.tid() - 1].fetch_add(1, Ordering::SeqCst);
+        self.vacant[idx.tid() - 1].store(true, Ordering::SeqCst);
+        Ok(())
     }
 
     /// Executes a mutable operation against this replica and returns a
@@ -485,7 +869,18 @@ where
         idx: ReplicaToken,
     ) -> Result<<D as Dispatch>::Response, ReplicaError<D>> {
         // Enqueue the operation onto the thread local batch and then try to flat combine.
-        while !self.make_pending(op.clone(), idx.tid()) {}
+        // The batch only fills up with operations that were abandoned (see
+        // `Replica::abandon`), combining drains it.
+        let mut iteration = 0;
+        while !self.make_pending(op.clone(), idx.tid()) {
+            match self.try_combine(slog) {
+                // Our operation isn't part of the round.
+                Ok(()) | Err(ReplicaError::Wal(_)) => {}
+                Err(e) => return Err(e),
+            }
+            self.waiter.wait(iteration);
+            iteration += 1;
+        }
         self.try_combine(slog)?;
 
         // Return the response to the caller function.
@@ -589,17 +984,78 @@ where
         idx: ReplicaToken,
     ) -> Result<<D as Dispatch>::Response, (ReplicaError<D>, <D as Dispatch>::ReadOperation<'rop>)>
     {
+        self.execute_stale(slog, op, idx, 0)
+    }
+
+    /// Executes an immutable operation against this replica, like
+    /// [`Replica::execute`], but the replica may lag behind the log by up to
+    /// `max_lag` entries.
+    ///
+    /// If the replica is close enough to the completed tail of the log, the
+    /// operation is served from the local state right away, without becoming
+    /// combiner or touching the log. Otherwise, we combine (like
+    /// [`Replica::execute`]) until we are within `max_lag` entries again. A
+    /// round of combining applies everything that is in the log at that time
+    /// and the pending operations of this replica, so the replica may end up
+    /// closer to the tail than `max_lag` requires.
+    pub fn execute_stale<'rop>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        op: <D as Dispatch>::ReadOperation<'rop>,
+        idx: ReplicaToken,
+        max_lag: usize,
+    ) -> Result<<D as Dispatch>::Response, (ReplicaError<D>, <D as Dispatch>::ReadOperation<'rop>)>
+    {
+        if let Err(e) = self.sync_for_reads(slog, max_lag) {
+            return Err((e, op));
+        }
+
+        return Ok(self.data.read(idx.tid() - 1).dispatch(op));
+    }
+
+    /// Runs `f` against the data structure of this replica, like
+    /// [`Replica::execute`] runs an immutable operation.
+    ///
+    /// The replica is synced up against the log first and `f` runs under the
+    /// read lock of the replica, so it can't observe partially applied
+    /// operations. On failure, `f` is returned with the error.
+    pub fn read_with<F, R>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        idx: ReplicaToken,
+        f: F,
+    ) -> Result<R, (ReplicaError<D>, F)>
+    where
+        F: FnOnce(&D) -> R,
+    {
+        if let Err(e) = self.sync_for_reads(slog, 0) {
+            return Err((e, f));
+        }
+
+        Ok(f(&self.data.read(idx.tid() - 1)))
+    }
+
+    /// Combines until this replica is at most `max_lag` entries behind the
+    /// completed tail of the log (every round of combining syncs up fully).
+    fn sync_for_reads(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        max_lag: usize,
+    ) -> Result<(), ReplicaError<D>> {
+        if self.is_poisoned() {
+            return Err(ReplicaError::Poisoned);
+        }
+
         // We can perform the read only if our replica is synced up against
         // the shared log. If it isn't, then try to combine until it is synced up.
-        let ctail = slog.get_ctail();
+        let ctail = slog.get_ctail().saturating_sub(max_lag);
+        let mut iteration = 0;
         while !slog.is_replica_synced_for_reads(&self.log_tkn, ctail) {
-            if let Err(e) = self.try_combine(slog) {
-                return Err((e, op));
-            }
-            spin_loop();
+            self.try_combine(slog)?;
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
-
-        return Ok(self.data.read(idx.tid() - 1).dispatch(op));
+        Ok(())
     }
 
     /// See [`Replica::execute()`] for a general description of this method.
@@ -620,6 +1076,10 @@ where
         combiner_lock: CombinerLock<'lock, D>,
     ) -> Result<<D as Dispatch>::Response, (ReplicaError<D>, <D as Dispatch>::ReadOperation<'rop>)>
     {
+        if self.is_poisoned() {
+            return Err((ReplicaError::Poisoned, op));
+        }
+
         // We can perform the read only if our replica is synced up against
         // the shared log. If it isn't, then try to combine until it is synced up.
         let ctail = slog.get_ctail();
@@ -632,11 +1092,13 @@ where
         // (because we return errors in some cases now, all of the ones that
         // make this assert fail?), we can get rid of the while below...
         assert!(slog.is_replica_synced_for_reads(&self.log_tkn, ctail));
+        let mut iteration = 0;
         while !slog.is_replica_synced_for_reads(&self.log_tkn, ctail) {
             if let Err(e) = self.try_combine(slog) {
                 return Err((e, op));
             }
-            spin_loop();
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
 
         Ok(self.data.read(idx.tid() - 1).dispatch(op))
@@ -652,26 +1114,106 @@ where
         slog: &Log<<D as Dispatch>::WriteOperation>,
         idx: usize,
     ) -> Result<<D as Dispatch>::Response, ReplicaError<D>> {
+        let mut spins = usize::MAX;
+        loop {
+            if let Some(resp) = self.get_response_within(slog, idx, &mut spins)? {
+                return Ok(resp);
+            }
+        }
+    }
+
+    /// Busy waits until a response is available within the thread's context,
+    /// like [`Replica::get_response`], but gives up after `spins` iterations.
+    ///
+    /// Returns None if no response arrived in time. The iterations spent are
+    /// subtracted from `spins`.
+    pub(crate) fn get_response_within(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        idx: usize,
+        spins: &mut usize,
+    ) -> Result<Option<<D as Dispatch>::Response>, ReplicaError<D>> {
         let mut iter = 0;
         let interval = 1 << 29;
 
         // Keep trying to retrieve a response from the thread context. After trying `interval`
         // times with no luck, try to perform flat combining to make some progress.
         loop {
-            let r = self.contexts[idx - 1].res();
+            let r = self.contexts()[idx - 1].res();
             if let Some(resp) = r {
-                return Ok(resp);
+                return resp.map(Some).ok_or(ReplicaError::Skipped);
+            }
+            if self.is_poisoned() {
+                return Err(ReplicaError::Poisoned);
+            }
+            if *spins == 0 {
+                return Ok(None);
             }
 
+            *spins -= 1;
             iter += 1;
 
-            if iter == interval {
+            // Nobody collects our operation if there is no combiner (e.g., it
+            // was enqueued after the last round), don't wait for it.
+            if iter == interval || self.combiner.load(Ordering::Relaxed) == 0 {
                 self.try_combine(slog)?;
                 iter = 0;
             }
+            self.waiter.wait(iter);
+        }
+    }
+
+    /// Gives up waiting for the response of the oldest outstanding operation
+    /// of thread `idx`. The operation is still applied, but its response is
+    /// dropped.
+    pub(crate) fn abandon(&self, idx: ReplicaToken) {
+        self.contexts()[idx.tid() - 1].abandon();
+    }
+
+    /// Makes progress on the log until the replica applied all operations up
+    /// to `ctail`, or until `spins` iterations are spent (and returns false).
+    ///
+    /// Starts with a round of flat combining with `combiner_lock` if it is
+    /// given (see [`ReplicaError::NoLogSpace`]).
+    pub(crate) fn sync_within<'r>(
+        &'r self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        ctail: usize,
+        mut combiner_lock: Option<CombinerLock<'r, D>>,
+        spins: &mut usize,
+    ) -> Result<bool, ReplicaError<D>> {
+        let mut iteration = 0;
+        loop {
+            if self.is_poisoned() {
+                return Err(ReplicaError::Poisoned);
+            }
+            if slog.is_replica_synced_for_reads(&self.log_tkn, ctail) {
+                return Ok(true);
+            }
+            if *spins == 0 {
+                return Ok(false);
+            }
+
+            *spins -= 1;
+            match combiner_lock.take() {
+                Some(combiner_lock) => self.combine(slog, combiner_lock)?,
+                None => self.try_combine(slog)?,
+            }
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
     }
 
+    /// Executes an immutable operation against the current state of the
+    /// replica, without syncing it with the log first.
+    pub(crate) fn execute_unsynced(
+        &self,
+        op: <D as Dispatch>::ReadOperation<'_>,
+        idx: ReplicaToken,
+    ) -> <D as Dispatch>::Response {
+        self.data.read(idx.tid() - 1).dispatch(op)
+    }
+
     /// Executes a passed in closure against the replica's underlying data structure.
     /// Useful for unit testing; can be used to verify certain properties of the data
     /// structure after issuing a bunch of operations against it.
@@ -683,26 +1225,34 @@ where
     pub fn verify<F: FnMut(&D)>(&self, slog: &Log<<D as Dispatch>::WriteOperation>, mut v: F) {
         // Acquire the combiner lock before attempting anything on the data structure.
         // Use an idx greater than the maximum that can be allocated.
+        let mut iteration = 0;
         while self.combiner.compare_exchange_weak(
             0,
-            MAX_THREADS_PER_REPLICA + 2,
+            self.vacant.len() + 2,
             Ordering::Acquire,
             Ordering::Acquire,
         ) != Ok(0)
         {
-            spin_loop();
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
 
         let mut data = self.data.write(self.next.load(Ordering::Relaxed));
-        let mut f = |o: <D as Dispatch>::WriteOperation, _mine: bool| {
-            data.dispatch_mut(o);
-        };
+        if !self.is_poisoned() && !self.is_dormant() && !self.is_released() {
+            let mut f = |o: <D as Dispatch>::WriteOperation, _mine: bool| {
+                data.apply_mut(o);
+            };
 
-        slog.exec(&self.log_tkn, &mut f);
+            slog.exec(&self.log_tkn, &mut f);
+        }
 
-        v(&data);
+        if !self.is_released() {
+            v(&data);
+        }
+        drop(data);
 
         self.combiner.store(0, Ordering::Release);
+        self.waiter.notify();
     }
 
     /// Synchronizes the replica by applying the outstanding operations in the
@@ -726,9 +1276,14 @@ where
     /// - [`Replica::try_sync`]
     pub fn sync(&self, slog: &Log<<D as Dispatch>::WriteOperation>) {
         let ctail = slog.get_ctail();
+        let mut iteration = 0;
         while !slog.is_replica_synced_for_reads(&self.log_tkn, ctail) {
+            if self.is_retired() || self.is_poisoned() || self.is_dormant() {
+                return;
+            }
             self.try_sync(slog);
-            spin_loop();
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
     }
 
@@ -744,86 +1299,601 @@ where
     #[inline(always)]
     pub(crate) fn try_sync(&self, slog: &Log<<D as Dispatch>::WriteOperation>) {
         // Try to become the combiner here. If this fails, then simply return.
-        if let Some(_combiner_lock) = self.acquire_combiner_lock() {
-            // Successfully became the combiner; perform one round of flat combining.
-            self.exec(slog);
+        if let Some(combiner_lock) = self.acquire_combiner_lock() {
+            self.sync_locked(slog, &combiner_lock);
         }
     }
 
-    /// Enqueues an operation inside a thread local context. Returns a boolean
-    /// indicating whether the operation was enqueued (true) or not (false).
-    #[inline(always)]
-    fn make_pending(&self, op: <D as Dispatch>::WriteOperation, idx: usize) -> bool {
-        self.contexts[idx - 1].enqueue(op, ())
+    /// Like [`Replica::try_sync`], for a caller that holds the combiner lock
+    /// of the replica already.
+    pub(crate) fn sync_locked(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        _combiner_lock: &CombinerLock<'_, D>,
+    ) {
+        // A retired, poisoned or dormant replica no longer owns its slot in
+        // the log.
+        if !self.is_retired() && !self.is_poisoned() && !self.is_dormant() {
+            let _r = self.apply(slog, || self.exec(slog));
+        }
     }
 
-    // Try to become acquire the combiner lock here. If this fails, then return None.
-    #[inline(always)]
-    fn acquire_combiner_lock(&self) -> Option<CombinerLock<D>> {
-        // First, check if there already is a flat combiner. If there is no active flat combiner
-        // then try to acquire the combiner lock. If there is, then just return.
-        for _ in 0..4 {
-            if self.combiner.load(Ordering::Relaxed) != 0 {
-                #[cfg(loom)]
-                loom::thread::yield_now();
-                return None;
+    /// Executes a batch of mutable operations taken from `ops` and appends
+    /// their responses to `resps` (in the same order).
+    ///
+    /// Every call performs one step: It collects the responses that are
+    /// ready, enqueues the next batch of operations (filling the context of
+    /// the thread) once all
+    /// previous ones completed and tries to flat combine (using
+    /// `combiner_lock` if we already hold it). The caller repeats this until
+    /// it returns `Ok(true)`, i.e., all operations of `ops` completed.
+    pub(crate) fn execute_mut_batch<'r>(
+        &'r self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        ops: &mut impl Iterator<Item = <D as Dispatch>::WriteOperation>,
+        idx: ReplicaToken,
+        resps: &mut Vec<<D as Dispatch>::Response>,
+        combiner_lock: Option<CombinerLock<'r, D>>,
+    ) -> Result<bool, ReplicaError<'r, D>> {
+        let ctxt = &self.contexts()[idx.tid() - 1];
+        self.collect_responses(idx, resps)?;
+
+        if !ctxt.has_outstanding() {
+            // Previous round is done, fill up the context for the next one.
+            for op in ops.by_ref().take(ctxt.batch_size()) {
+                let enqueued = ctxt.enqueue(op, ());
+                debug_assert!(enqueued, "Context is empty");
+            }
+
+            if !ctxt.has_outstanding() {
+                return Ok(true);
             }
         }
 
-        if self
-            .combiner
-            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Acquire)
-            != Ok(0)
-        {
-            #[cfg(loom)]
-            loom::thread::yield_now();
-            None
-        } else {
-            unsafe { Some(CombinerLock::new(self)) }
+        let combined = match combiner_lock {
+            Some(combiner_lock) => self.combine(slog, combiner_lock),
+            None => self.try_combine(slog),
+        };
+        if let Err(ReplicaError::Wal(_)) = combined {
+            // The caller gives up, the responses of the round are ready.
+            let _r = self.collect_responses(idx, resps);
         }
+        combined.map(|()| false)
     }
 
-    /// Appends an operation to the log and attempts to perform flat combining.
-    /// Accepts a thread `tid` as an argument. Required to acquire the combiner lock.
-    fn try_combine<'r>(
-        &'r self,
-        slog: &Log<<D as Dispatch>::WriteOperation>,
+    /// Moves the responses that are ready in the context of thread `idx` to
+    /// `resps` (see [`Replica::execute_mut_batch`]).
+    ///
+    /// Stops at the first operation that was skipped and returns
+    /// [`ReplicaError::Skipped`], the responses after it are dropped.
+    fn collect_responses(
+        &self,
+        idx: ReplicaToken,
+        resps: &mut Vec<<D as Dispatch>::Response>,
     ) -> Result<(), ReplicaError<D>> {
-        // Try to become the combiner here. If this fails, then simply return.
-        if let Some(combiner_lock) = self.acquire_combiner_lock() {
-            // Successfully became the combiner; perform one round of flat combining.
-            self.combine(slog, combiner_lock)?;
-            Ok(())
+        let ctxt = &self.contexts()[idx.tid() - 1];
+        let mut skipped = false;
+        while let Some(resp) = ctxt.res() {
+            match resp {
+                Some(resp) if !skipped => resps.push(resp),
+                _ => skipped = true,
+            }
+        }
+
+        if skipped {
+            Err(ReplicaError::Skipped)
         } else {
-            #[cfg(loom)]
-            loom::thread::yield_now();
             Ok(())
         }
     }
 
-    #[inline(always)]
-    fn exec(&self, slog: &Log<<D as Dispatch>::WriteOperation>) {
-        // Execute any operations on the shared log against this replica.
-        let next = self.next.load(Ordering::Relaxed);
-        {
-            let mut data = self.data.write(next);
-            let mut f = |o: <D as Dispatch>::WriteOperation, mine: bool| {
-                let _resp = data.dispatch_mut(o);
-                if mine {
-                    panic!("Ups -- we just lost a result?");
-                }
-            };
-            slog.exec(&self.log_tkn, &mut f);
+    /// Non-blocking version of [`Replica::execute_mut`].
+    ///
+    /// Enqueues `op` in the context of thread `idx` on the first call and
+    /// stores its position in `pos`. Returns [`Poll::Pending`] while another
+    /// thread is combining, the task is woken once the combiner is done.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_execute_mut(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        op: &<D as Dispatch>::WriteOperation,
+        pos: &mut Option<usize>,
+        idx: ReplicaToken,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<<D as Dispatch>::Response, ReplicaError<D>>> {
+        let ctxt = &self.contexts()[idx.tid() - 1];
+        let p = match *pos {
+            Some(p) => p,
+            None => {
+                let mut combined = false;
+                loop {
+                    if let Some(p) = ctxt.try_enqueue(op.clone(), ()) {
+                        break *pos.insert(p);
+                    }
+                    if combined {
+                        // The context is full of responses that other tasks
+                        // of this thread didn't take yet, they wake us once
+                        // they did.
+                        self.register_waker(idx, cx.waker());
+                        return Poll::Pending;
+                    }
+
+                    // Our context is full, in-flight operations of this
+                    // thread have to complete first.
+                    if !self.try_combine_or_wait(slog, idx, cx.waker())? {
+                        return Poll::Pending;
+                    }
+                    combined = true;
+                }
+            }
+        };
+
+        loop {
+            if let Some(resp) = ctxt.res_at(p) {
+                // We freed a slot in the context, a task of this thread might
+                // wait for one.
+                self.wake(idx.tid());
+                return Poll::Ready(resp.ok_or(ReplicaError::Skipped));
+            }
+
+            if !self.try_combine_or_wait(slog, idx, cx.waker())? {
+                return Poll::Pending;
+            }
         }
     }
 
+    /// Gives up on the operation of thread `idx` that [`Replica::poll_execute_mut`]
+    /// enqueued at `pos`. The operation is still applied, but its response
+    /// is dropped.
+    #[cfg(feature = "async")]
+    pub(crate) fn abandon_at(&self, idx: ReplicaToken, pos: usize) {
+        self.contexts()[idx.tid() - 1].abandon_at(pos);
+    }
+
+    /// Non-blocking version of [`Replica::execute`].
+    ///
+    /// `ctail` is the completed tail of the log when the operation started.
+    /// Returns [`Poll::Pending`] while the replica is behind and another
+    /// thread is combining, the task is woken once the combiner is done.
+    #[cfg(feature = "async")]
+    pub(crate) fn poll_execute<'rop>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        op: &mut Option<<D as Dispatch>::ReadOperation<'rop>>,
+        ctail: usize,
+        idx: ReplicaToken,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<<D as Dispatch>::Response, ReplicaError<D>>> {
+        if self.is_poisoned() {
+            return Poll::Ready(Err(ReplicaError::Poisoned));
+        }
+
+        while !slog.is_replica_synced_for_reads(&self.log_tkn, ctail) {
+            if !self.try_combine_or_wait(slog, idx, cx.waker())? {
+                return Poll::Pending;
+            }
+        }
+
+        let op = op.take().expect("Future polled after completion");
+        Poll::Ready(Ok(self.data.read(idx.tid() - 1).dispatch(op)))
+    }
+
+    /// Performs one round of flat combining if we get the combiner lock and
+    /// returns true. Otherwise, `waker` is woken once the current combiner
+    /// releases the lock and we return false.
+    #[cfg(feature = "async")]
+    fn try_combine_or_wait(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        idx: ReplicaToken,
+        waker: &Waker,
+    ) -> Result<bool, ReplicaError<D>> {
+        // Register before trying the lock: Either we get the lock or the
+        // combiner sees our waker when it releases the lock.
+        self.register_waker(idx, waker);
+        fence(Ordering::SeqCst);
+
+        match self.acquire_combiner_lock() {
+            Some(combiner_lock) => {
+                self.combine(slog, combiner_lock)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Registers `waker` in the context of thread `idx`, it is woken with the
+    /// next responses for the thread or once the combiner releases the lock.
+    #[cfg(feature = "async")]
+    fn register_waker(&self, idx: ReplicaToken, waker: &Waker) {
+        if self.contexts()[idx.tid() - 1].register_waker(waker) {
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Wakes the tasks waiting on the context of thread `tid` (if any).
+    #[cfg(feature = "async")]
     #[inline(always)]
-    fn collect_thread_ops(&self, buffer: &mut Vec<D::WriteOperation>, operations: &mut [usize]) {
-        let num_registered_threads = self.next.load(Ordering::Relaxed);
+    fn wake(&self, tid: usize) {
+        if self.waiters.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let wakers = self.contexts()[tid - 1].take_wakers();
+        if !wakers.is_empty() {
+            self.waiters.fetch_sub(1, Ordering::SeqCst);
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+
+    /// Wakes all tasks that wait on this replica.
+    #[cfg(feature = "async")]
+    fn wake_waiters(&self) {
+        fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let next = core::cmp::min(self.next.load(Ordering::Relaxed), self.vacant.len() + 1);
+        for tid in 1..next {
+            self.wake(tid);
+        }
+    }
+
+    /// Returns true if `idx` is the token of a thread that is currently
+    /// registered with this replica.
+    #[inline(always)]
+    pub(crate) fn is_registered(&self, idx: ReplicaToken) -> bool {
+        let next = self.next.load(Ordering::SeqCst);
+        (1..next).contains(&idx.tid())
+            && !self.vacant[idx.tid() - 1].load(Ordering::SeqCst)
+            && self.generations[idx.tid() - 1].load(Ordering::SeqCst) == idx.generation()
+    }
+
+    /// Returns true if the replica is retired.
+    #[inline(always)]
+    pub(crate) fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the replica is poisoned (see [`ReplicaError::Poisoned`]).
+    #[inline(always)]
+    pub(crate) fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the replica is dormant (see [`Replica::evict()`]).
+    #[inline(always)]
+    pub(crate) fn is_dormant(&self) -> bool {
+        self.dormant.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the data-structure and the contexts of the replica
+    /// were freed (see [`Replica::release()`]).
+    #[inline(always)]
+    pub(crate) fn is_released(&self) -> bool {
+        self.contexts().is_empty()
+    }
+
+    /// Returns the contexts of the threads of the replica.
+    #[inline(always)]
+    fn contexts(&self) -> &[ThreadContext<D>] {
+        // Safety: The vector is only replaced by `release()` and `revive()`,
+        // while no thread is registered or operates on the replica.
+        unsafe { &*self.contexts.get() }
+    }
+
+    /// Poisons the replica and releases its slot in the log so GC no longer
+    /// waits on it. Called by the combiner if it panicked.
+    fn poison(&self, slog: &Log<<D as Dispatch>::WriteOperation>) {
+        error!("Dispatch::dispatch_mut panicked, poisoning replica");
+        self.poisoned.store(true, Ordering::SeqCst);
+        slog.unregister(&self.log_tkn);
+    }
+
+    /// Runs `f` which applies operations of the log to our data-structure and
+    /// poisons the replica if `f` panics.
+    ///
+    /// With the `std` feature the panic is caught and reported as
+    /// [`ReplicaError::Poisoned`], otherwise it continues to unwind.
+    fn apply<R>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        f: impl FnOnce() -> R,
+    ) -> Result<R, ReplicaError<D>> {
+        let guard = PoisonOnUnwind {
+            replica: self,
+            slog,
+        };
+        #[cfg(feature = "std")]
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+            .map_err(|_| ReplicaError::Poisoned);
+        #[cfg(not(feature = "std"))]
+        let r = Ok(f());
+
+        if r.is_ok() {
+            core::mem::forget(guard);
+        }
+        r
+    }
+
+    /// Retires the replica and releases its slot in the log so GC no longer
+    /// waits on it.
+    ///
+    /// Fails (returns false) if threads are still registered with the
+    /// replica, see [`Replica::unregister()`].
+    pub(crate) fn retire(&self, slog: &Log<<D as Dispatch>::WriteOperation>) -> bool {
+        let _combiner_lock = self.lock_combiner();
+
+        // Pairs with the check in `register()`: either we see the thread
+        // that registered, or it sees that we're retired and backs off.
+        self.retired.store(true, Ordering::SeqCst);
+        let next = core::cmp::min(self.next.load(Ordering::SeqCst), self.vacant.len() + 1);
+        if (1..next).any(|idx| !self.vacant[idx - 1].load(Ordering::SeqCst)) {
+            self.retired.store(false, Ordering::SeqCst);
+            return false;
+        }
+
+        slog.unregister(&self.log_tkn);
+        true
+    }
+
+    /// Makes the replica dormant if it lags more than `max_lag` entries behind
+    /// the completed tail of `slog` and releases its slot in the log, so GC no
+    /// longer waits on it. The operations the replica missed may be
+    /// overwritten, it has to be rebuilt with [`Replica::fork()`] and
+    /// [`Replica::revive()`] before it is used again.
+    ///
+    /// Fails (returns false) if the replica is busy: another thread holds the
+    /// combiner lock, operates on the replica (see [`Replica::enter()`]) or
+    /// has outstanding operations.
+    pub(crate) fn evict(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        max_lag: usize,
+    ) -> bool {
+        let _combiner_lock = match self.acquire_combiner_lock() {
+            Some(combiner_lock) => combiner_lock,
+            None => return false,
+        };
+        let lag = slog
+            .get_ctail()
+            .saturating_sub(slog.get_ltail(&self.log_tkn));
+        if lag <= max_lag || self.is_retired() || self.is_poisoned() || self.is_dormant() {
+            return false;
+        }
+
+        // Pairs with the check in `enter()`: either we see the thread that
+        // entered, or it sees that we're dormant and backs off.
+        self.dormant.store(true, Ordering::SeqCst);
+        let next = core::cmp::min(self.next.load(Ordering::SeqCst), self.vacant.len() + 1);
+        if self.active.load(Ordering::SeqCst) != 0
+            || (1..next).any(|idx| self.contexts()[idx - 1].has_outstanding())
+        {
+            self.dormant.store(false, Ordering::SeqCst);
+            return false;
+        }
+
+        slog.unregister(&self.log_tkn);
+        true
+    }
+
+    /// Returns true if the replica is dormant. Waits for an eviction that is
+    /// in progress to finish first.
+    pub(crate) fn is_evicted(&self) -> bool {
+        let _combiner_lock = self.lock_combiner();
+        self.is_dormant()
+    }
+
+    /// Marks the calling thread as operating on the replica until the
+    /// returned guard is dropped, the replica isn't evicted or released
+    /// meanwhile.
+    ///
+    /// Returns None if the replica is dormant or retired.
+    pub(crate) fn enter(&self) -> Option<Active<'_, D>> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        if self.is_dormant() || self.is_retired() {
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Active { replica: self })
+    }
+
+    /// Registers a new replica with `slog` that starts at the log position of
+    /// this replica. Calls `f` with the log token of the new replica and our
+    /// data-structure which can be cloned to bootstrap the new replica.
+    ///
+    /// We hold our combiner lock while `f` runs, the data-structure passed to
+    /// `f` is therefore consistent with the log position of the new replica.
+    /// `reuse` decides which retired slots of the log may be handed out again
+    /// (see [`Log::register_from()`]). Returns None if the log has no space for
+    /// another replica.
+    pub(crate) fn fork<R>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        reuse: impl Fn(&LogToken) -> bool,
+        f: impl FnOnce(LogToken, &D) -> R,
+    ) -> Option<R> {
+        let _combiner_lock = self.lock_combiner();
+        let log_tkn = slog.register_from(&self.log_tkn, reuse)?;
+        // Only the combiner writes to the data-structure, so a read lock keeps
+        // it consistent while our readers go on. Reader slots count their
+        // holders, sharing the first one with its thread is fine.
+        let data = self.data.read(0);
+        Some(f(log_tkn, &data))
+    }
+
+    /// Calls `f` with the position in `slog` that this replica reached and
+    /// its data-structure.
+    ///
+    /// We hold our combiner lock while `f` runs, the position and the
+    /// data-structure are therefore consistent.
+    pub(crate) fn with_position<R>(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+        f: impl FnOnce(usize, &D) -> R,
+    ) -> R {
+        let _combiner_lock = self.lock_combiner();
+        let data = self.data.write(self.next.load(Ordering::Relaxed));
+        f(slog.get_ltail(&self.log_tkn), &data)
+    }
+
+    /// Frees the data-structure and the thread contexts of a retired replica
+    /// (see [`Replica::retire()`]), once the threads that operate on it (see
+    /// [`Replica::enter()`]) are done.
+    ///
+    /// The replica keeps its place: [`Replica::revive()`] sets it up again.
+    pub(crate) fn release(&self) {
+        debug_assert!(self.is_retired());
+        // A retired replica can't be entered, we only wait for the threads
+        // that entered it before.
+        let mut iteration = 0;
+        while self.active.load(Ordering::SeqCst) != 0 {
+            self.waiter.wait(iteration);
+            iteration += 1;
+        }
+
+        let _combiner_lock = self.lock_combiner();
+        if self.is_released() {
+            return;
+        }
+        let mut data = self.data.write(self.next.load(Ordering::Relaxed));
+        // Safety: The replica isn't used until it is revived, which writes a
+        // new data-structure without dropping this one again.
+        unsafe { ManuallyDrop::drop(&mut data) };
+        drop(data);
+        // Safety: No thread is registered or operates on the replica, and
+        // we hold the combiner lock.
+        unsafe { *self.contexts.get() = Vec::new() };
+    }
+
+    /// Brings a retired or dormant replica back with the data-structure `d` and
+    /// the slot `log_tkn` that was handed out to it by [`Replica::fork()`].
+    pub(crate) fn revive(&self, log_tkn: LogToken, d: D) {
+        assert_eq!(
+            log_tkn, self.log_tkn,
+            "Revived with a different slot in the log"
+        );
+        let _combiner_lock = self.lock_combiner();
+        debug_assert!(self.is_retired() || self.is_dormant());
+
+        let mut data = self.data.write(self.next.load(Ordering::Relaxed));
+        if self.is_released() {
+            let contexts = (0..self.config.max_threads)
+                .map(|idx| Context::new(idx + 1, self.config.pending_ops))
+                .collect();
+            // Safety: No thread is registered with a released replica, and we
+            // hold the combiner lock.
+            unsafe { *self.contexts.get() = contexts };
+        } else {
+            // Safety: Replaced right away.
+            unsafe { ManuallyDrop::drop(&mut data) };
+        }
+        *data = ManuallyDrop::new(d);
+        drop(data);
+        self.inflight.borrow_mut().fill(0);
+        self.poisoned.store(false, Ordering::SeqCst);
+        self.dormant.store(false, Ordering::SeqCst);
+        self.retired.store(false, Ordering::SeqCst);
+    }
+
+    /// Enqueues an operation inside a thread local context. Returns a boolean
+    /// indicating whether the operation was enqueued (true) or not (false).
+    #[inline(always)]
+    pub(crate) fn make_pending(&self, op: <D as Dispatch>::WriteOperation, idx: usize) -> bool {
+        self.contexts()[idx - 1].enqueue(op, ())
+    }
+
+    // Try to become acquire the combiner lock here. If this fails, then return None.
+    #[inline(always)]
+    fn acquire_combiner_lock(&self) -> Option<CombinerLock<D>> {
+        // First, check if there already is a flat combiner. If there is no active flat combiner
+        // then try to acquire the combiner lock. If there is, then just return.
+        for _ in 0..4 {
+            if self.combiner.load(Ordering::Relaxed) != 0 {
+                #[cfg(loom)]
+                loom::thread::yield_now();
+                return None;
+            }
+        }
 
+        if self
+            .combiner
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Acquire)
+            != Ok(0)
+        {
+            #[cfg(loom)]
+            loom::thread::yield_now();
+            None
+        } else {
+            unsafe { Some(CombinerLock::new(self)) }
+        }
+    }
+
+    // Spin until we acquire the combiner lock.
+    fn lock_combiner(&self) -> CombinerLock<D> {
+        let mut iteration = 0;
+        loop {
+            if let Some(combiner_lock) = self.acquire_combiner_lock() {
+                return combiner_lock;
+            }
+            self.waiter.wait(iteration);
+            iteration += 1;
+        }
+    }
+
+    /// Appends an operation to the log and attempts to perform flat combining.
+    /// Accepts a thread `tid` as an argument. Required to acquire the combiner lock.
+    pub(crate) fn try_combine(
+        &self,
+        slog: &Log<<D as Dispatch>::WriteOperation>,
+    ) -> Result<(), ReplicaError<D>> {
+        // Try to become the combiner here. If this fails, then simply return.
+        if let Some(combiner_lock) = self.acquire_combiner_lock() {
+            // Successfully became the combiner; perform one round of flat combining.
+            self.combine(slog, combiner_lock)?;
+            Ok(())
+        } else {
+            #[cfg(loom)]
+            loom::thread::yield_now();
+            Ok(())
+        }
+    }
+
+    #[inline(always)]
+    fn exec(&self, slog: &Log<<D as Dispatch>::WriteOperation>) {
+        // Execute any operations on the shared log against this replica.
+        let next = self.next.load(Ordering::Relaxed);
+        {
+            let mut data = self.data.write(next);
+            let mut f = |o: <D as Dispatch>::WriteOperation, mine: bool| {
+                data.apply_mut(o);
+                if mine {
+                    panic!("Ups -- we just lost a result?");
+                }
+            };
+            slog.exec(&self.log_tkn, &mut f);
+        }
+    }
+
+    /// Collects the operations of the first `num_registered_threads - 1`
+    /// threads. The combiner hands out the responses to the same threads, a
+    /// thread that registers in between is collected in the next round.
+    #[inline(always)]
+    fn collect_thread_ops(
+        &self,
+        buffer: &mut Vec<D::WriteOperation>,
+        operations: &mut [usize],
+        num_registered_threads: usize,
+    ) {
         // Collect operations from each thread registered with this replica.
         for i in 1..num_registered_threads {
-            let ctxt_iter = self.contexts[i - 1].iter();
+            // Skip slots of threads that unregistered.
+            if self.vacant[i - 1].load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let ctxt_iter = self.contexts()[i - 1].iter();
             operations[i - 1] = ctxt_iter.len();
             // meta-data is (), throw it away
             buffer.extend(ctxt_iter.map(|op| op.0));
@@ -837,55 +1907,120 @@ where
         slog: &Log<<D as Dispatch>::WriteOperation>,
         combiner_lock: CombinerLock<'r, D>,
     ) -> Result<(), ReplicaError<D>> {
+        if self.is_poisoned() {
+            return Err(ReplicaError::Poisoned);
+        }
+
         let num_registered_threads = self.next.load(Ordering::Relaxed);
         let mut results = self.result.borrow_mut();
         let mut buffer = self.buffer.borrow_mut();
+        let mut coalesced = self.coalesced.borrow_mut();
+        let mut replaced = self.replaced.borrow_mut();
+        let mut fanned = self.fanned.borrow_mut();
         let mut operations = self.inflight.borrow_mut();
         results.clear();
         buffer.clear();
+        coalesced.clear();
+        replaced.clear();
 
-        self.collect_thread_ops(&mut buffer, operations.as_mut_slice());
+        self.collect_thread_ops(
+            &mut buffer,
+            operations.as_mut_slice(),
+            num_registered_threads,
+        );
+        self.stats.combined(buffer.len());
+
+        let coalesce = !buffer.is_empty()
+            && D::coalesce(&buffer, &mut coalesced, &mut replaced)
+            && !coalesced.is_empty()
+            && replaced.len() == coalesced.len()
+            && replaced.iter().sum::<usize>() == buffer.len();
+        let ops: &[_] = if coalesce { &coalesced } else { &buffer };
+
+        // Applies a log entry to the replica. Our entries in the log are `ops`
+        // (in order). If they are coalesced, the response of every entry is
+        // fanned out to the operations of `buffer` it replaced.
+        //
+        // Entries of ours that panicked on another replica are skipped by the
+        // log (see `Log::skipped`), their threads get `None` instead of a
+        // response.
+        let skipped_before = slog.skipped(&self.log_tkn);
+        let (mut own_entries, mut next, mut start) = (0, 0, 0);
+        let mut apply_entry = |data: &mut D, o: <D as Dispatch>::WriteOperation, mine: bool| {
+            let skipped = slog.skipped(&self.log_tkn) - skipped_before;
+            if !mine {
+                data.apply_mut(o);
+            } else if !coalesce {
+                results.resize_with(own_entries + skipped, || None);
+                results.push(Some(data.dispatch_mut(o)));
+                own_entries += 1;
+            } else {
+                let entry = own_entries + skipped;
+                start += replaced[next..entry].iter().sum::<usize>();
+                results.resize_with(start, || None);
+
+                let end = start + replaced[entry];
+                fanned.clear();
+                D::fan_out(&buffer[start..end], data.dispatch_mut(o), &mut fanned);
+                debug_assert_eq!(fanned.len(), end - start, "One response per operation");
+                results.extend(fanned.drain(..).map(Some));
+                results.resize_with(end, || None);
+
+                own_entries += 1;
+                next = entry + 1;
+                start = end;
+            }
+        };
 
         // Append all collected operations into the shared log. We pass a closure
         // in here because operations on the log might need to be consumed for GC.
-        let res = {
+        let appended = self.apply(slog, || {
             let mut data = self.data.write(num_registered_threads);
-            let f = |o: <D as Dispatch>::WriteOperation, mine: bool| {
-                #[cfg(not(loom))]
-                let resp = data.dispatch_mut(o);
-                #[cfg(loom)]
-                let resp = data.dispatch_mut(o);
-                if mine {
-                    results.push(resp);
+            let f =
+                |o: <D as Dispatch>::WriteOperation, mine: bool| apply_entry(&mut data, o, mine);
+            match &self.wal {
+                // Operations are durable before we hand out their responses.
+                Some(wal) => wal.append(ops, &mut self.records.borrow_mut(), |reserved| {
+                    slog.append_then(ops, &self.log_tkn, f, reserved)
+                }),
+                None => slog.append(ops, &self.log_tkn, f).map(|r| (r, Ok(()))),
+            }
+        })?;
+        let res = {
+            match appended {
+                Ok((None, written)) => written.map_err(ReplicaError::Wal),
+                Ok((Some(_r), Err(e))) => {
+                    self.stats.gc_failed.inc();
+                    Err(ReplicaError::Wal(e))
                 }
-            };
-            match slog.append(&buffer, &self.log_tkn, f) {
-                Ok(None) => Ok(()),
-                Ok(Some(r)) => {
+                Ok((Some(r), Ok(()))) => {
                     // We inserted the entries (and can apply them below), but
                     // we want to also notify about the slow `r` so it can be
                     // forced to make some progress
+                    self.stats.gc_failed.inc();
                     Err(ReplicaError::GcFailed(r))
                 }
                 Err(r) => {
                     // return here because we couldn't insert our entries and
                     // need to try again later
+                    self.stats.no_log_space.inc();
                     return Err(ReplicaError::NoLogSpace(r, combiner_lock));
                 }
             }
         };
+        if let Err(ReplicaError::Wal(e)) = &res {
+            // Only the caller of this round learns about it.
+            warn!("Can't write operations to the WAL: {:?}", e);
+        }
 
         // Execute outstanding operations on the shared log against this replica
-        {
+        self.apply(slog, || {
             let mut data = self.data.write(num_registered_threads);
-            let mut f = |o: <D as Dispatch>::WriteOperation, mine: bool| {
-                let resp = data.dispatch_mut(o);
-                if mine {
-                    results.push(resp)
-                }
-            };
+            let mut f =
+                |o: <D as Dispatch>::WriteOperation, mine: bool| apply_entry(&mut data, o, mine);
             slog.exec(&self.log_tkn, &mut f);
-        }
+        })?;
+        results.resize_with(buffer.len(), || None);
 
         // Return/Enqueue responses back into the appropriate thread context(s).
         let (mut s, mut f) = (0, 0);
@@ -895,7 +2030,9 @@ where
             };
 
             f += operations[i - 1];
-            self.contexts[i - 1].enqueue_resps(&results[s..f]);
+            self.contexts()[i - 1].enqueue_resps(&results[s..f]);
+            #[cfg(feature = "async")]
+            self.wake(i);
             s += operations[i - 1];
             operations[i - 1] = 0;
         }
@@ -912,7 +2049,7 @@ pub(crate) mod test {
     use std::vec;
 
     // Really dumb data structure to test against the Replica and shared log.
-    #[derive(Default)]
+    #[derive(Default, Clone)]
     pub(crate) struct Data {
         pub(crate) junk: u64,
     }
@@ -940,29 +2077,82 @@ pub(crate) mod test {
         let repl = Replica::<Data>::new(lt);
         assert_eq!(repl.combiner.load(Ordering::SeqCst), 0);
         assert_eq!(repl.next.load(Ordering::SeqCst), 1);
-        assert_eq!(repl.contexts.len(), MAX_THREADS_PER_REPLICA);
+        assert_eq!(repl.contexts().len(), MAX_THREADS_PER_REPLICA);
         assert_eq!(
             repl.buffer.borrow().capacity(),
-            MAX_THREADS_PER_REPLICA * Context::<u64, Result<u64, ()>>::batch_size()
+            MAX_THREADS_PER_REPLICA * repl.contexts()[0].batch_size()
         );
         assert_eq!(repl.inflight.borrow().len(), MAX_THREADS_PER_REPLICA);
         assert_eq!(
             repl.result.borrow().capacity(),
-            MAX_THREADS_PER_REPLICA * Context::<u64, Result<u64, ()>>::batch_size()
+            MAX_THREADS_PER_REPLICA * repl.contexts()[0].batch_size()
         );
         assert_eq!(repl.data.read(0).junk, 0);
     }
 
+    // Tests that a replica with a small configuration works with a log that
+    // garbage collects accordingly often.
+    #[test]
+    fn test_replica_with_config() {
+        let config = ReplicaConfig {
+            max_threads: 2,
+            pending_ops: 4,
+        };
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::new_with_config(1024, 1, config, ())
+            .expect("Valid configuration");
+        assert_eq!(slog.gc_from_head(), 8);
+        assert_eq!(slog.slog.len(), 16);
+
+        let repl = Replica::<Data>::with_config(slog.register().unwrap(), config, Data::default())
+            .expect("Valid configuration");
+        assert_eq!(repl.contexts().len(), 2);
+        assert_eq!(repl.contexts()[0].batch_size(), 4);
+
+        let tkn1 = repl.register().unwrap();
+        let tkn2 = repl.register().unwrap();
+        assert!(repl.register().is_none());
+        for i in 0..100 {
+            let tkn = if i % 2 == 0 { tkn1 } else { tkn2 };
+            assert_eq!(repl.execute_mut(&slog, i, tkn).unwrap(), Ok(107));
+        }
+        assert_eq!(repl.execute(&slog, 0, tkn1).unwrap(), Ok(100));
+    }
+
+    // Tests that unusable configurations are rejected.
+    #[test]
+    fn test_replica_with_invalid_config() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let no_threads = ReplicaConfig {
+            max_threads: 0,
+            pending_ops: 4,
+        };
+        let pending_ops = ReplicaConfig {
+            max_threads: 2,
+            pending_ops: 3,
+        };
+        for config in [no_threads, pending_ops] {
+            assert!(matches!(config.validate(), Err(ConfigError(_))));
+            assert!(matches!(
+                Log::<<Data as Dispatch>::WriteOperation>::new_with_config(1024, 1, config, ()),
+                Err(ConfigError(_))
+            ));
+            assert!(matches!(
+                Replica::<Data>::with_config(slog.register().unwrap(), config, Data::default()),
+                Err(ConfigError(_))
+            ));
+        }
+    }
+
     // Tests whether we can register with this replica and receive an idx.
     #[test]
     fn test_replica_register() {
         let slog = Log::<<Data as Dispatch>::WriteOperation>::new_with_bytes(1024, ());
         let lt = slog.register().unwrap();
         let repl = Replica::<Data>::new(lt);
-        assert_eq!(repl.register(), Some(ReplicaToken(1)));
+        assert_eq!(repl.register(), Some(ReplicaToken(1, 0)));
         assert_eq!(repl.next.load(Ordering::SeqCst), 2);
         repl.next.store(17, Ordering::SeqCst);
-        assert_eq!(repl.register(), Some(ReplicaToken(17)));
+        assert_eq!(repl.register(), Some(ReplicaToken(17, 0)));
         assert_eq!(repl.next.load(Ordering::SeqCst), 18);
     }
 
@@ -977,6 +2167,63 @@ pub(crate) mod test {
         assert!(repl.register().is_none());
     }
 
+    // Tests that slots of unregistered threads are handed out again, even
+    // if all thread identifiers were used up.
+    #[test]
+    fn test_replica_unregister() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+
+        let mut tkns = vec![];
+        while let Some(tkn) = repl.register() {
+            tkns.push(tkn);
+        }
+        assert_eq!(tkns.len(), MAX_THREADS_PER_REPLICA);
+
+        assert!(repl.unregister(&slog, tkns[7]).is_ok());
+        assert!(repl.vacant[7].load(Ordering::SeqCst));
+        let tkn = repl.register().expect("Slot 8 is free");
+        assert_eq!(tkn.tid(), tkns[7].tid());
+        assert!(!repl.vacant[7].load(Ordering::SeqCst));
+        assert!(repl.is_registered(tkn));
+        assert!(!repl.is_registered(tkns[7]));
+        assert!(repl.register().is_none());
+    }
+
+    // Tests that unregister() completes operations which are still pending
+    // in the context of the thread.
+    #[test]
+    fn test_replica_unregister_drains() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+        let idx = repl.register().unwrap();
+
+        repl.make_pending(121, idx.tid());
+        repl.make_pending(122, idx.tid());
+        assert!(repl.unregister(&slog, idx).is_ok());
+
+        assert_eq!(repl.data.read(0).junk, 2);
+        assert!(!repl.contexts()[idx.tid() - 1].has_outstanding());
+    }
+
+    // Tests that the combiner ignores the contexts of unregistered threads.
+    #[test]
+    fn test_replica_try_combine_vacant() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+
+        repl.next.store(9, Ordering::SeqCst);
+        repl.vacant[7].store(true, Ordering::SeqCst);
+        repl.make_pending(121, 8);
+        assert!(repl.try_combine(&slog).is_ok());
+
+        assert_eq!(repl.data.read(0).junk, 0);
+        assert_eq!(repl.contexts()[7].res(), None);
+    }
+
     // Tests that we can successfully allow operations to go pending on this replica.
     #[test]
     fn test_replica_make_pending() {
@@ -985,7 +2232,7 @@ pub(crate) mod test {
         let repl = Replica::<Data>::new(lt);
         let mut o = vec![];
         assert!(repl.make_pending(121, 8));
-        let ctxt_iter = repl.contexts[7].iter();
+        let ctxt_iter = repl.contexts()[7].iter();
         assert_eq!(ctxt_iter.len(), 1);
         o.extend(ctxt_iter.map(|o| o.0));
         assert_eq!(o.len(), 1);
@@ -998,7 +2245,7 @@ pub(crate) mod test {
         let slog = Log::<<Data as Dispatch>::WriteOperation>::new_with_bytes(1024, ());
         let lt = slog.register().unwrap();
         let repl = Replica::<Data>::new(lt);
-        for _i in 0..Context::<u64, Result<u64, ()>>::batch_size() {
+        for _i in 0..repl.contexts()[0].batch_size() {
             assert!(repl.make_pending(121, 1))
         }
 
@@ -1018,7 +2265,131 @@ pub(crate) mod test {
 
         assert_eq!(repl.combiner.load(Ordering::SeqCst), 0);
         assert_eq!(repl.data.read(0).junk, 1);
-        assert_eq!(repl.contexts[0].res(), Some(Ok(107)));
+        assert_eq!(repl.contexts()[0].res(), Some(Some(Ok(107))));
+    }
+
+    // A counter whose increments the combiner coalesces into one, it counts
+    // the operations it applied.
+    #[derive(Default)]
+    struct Summed(u64, usize);
+
+    impl Dispatch for Summed {
+        type ReadOperation<'rop> = ();
+        type WriteOperation = u64;
+        type Response = u64;
+
+        fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+            self.0
+        }
+
+        fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+            self.0 += op;
+            self.1 += 1;
+            self.0
+        }
+
+        fn coalesce(
+            ops: &[Self::WriteOperation],
+            coalesced: &mut Vec<Self::WriteOperation>,
+            replaced: &mut Vec<usize>,
+        ) -> bool {
+            coalesced.push(ops.iter().sum());
+            replaced.push(ops.len());
+            true
+        }
+
+        fn fan_out(
+            ops: &[Self::WriteOperation],
+            resp: Self::Response,
+            resps: &mut Vec<Self::Response>,
+        ) {
+            let mut before = resp - ops.iter().sum::<u64>();
+            for op in ops {
+                before += op;
+                resps.push(before);
+            }
+        }
+    }
+
+    // Tests that the combiner appends and applies the coalesced batch, but
+    // every thread still gets the response of its own operation.
+    #[test]
+    fn test_replica_try_combine_coalesce() {
+        let slog = Log::<<Summed as Dispatch>::WriteOperation>::default();
+        let repl = Replica::<Summed>::new(slog.register().unwrap());
+        let remote = Replica::<Summed>::new(slog.register().unwrap());
+        for _i in 0..3 {
+            assert!(repl.register().is_some());
+        }
+
+        repl.make_pending(1, 1);
+        repl.make_pending(2, 2);
+        repl.make_pending(3, 3);
+        repl.try_combine(&slog).unwrap();
+
+        assert_eq!(slog.get_ctail(), 1);
+        assert_eq!(repl.contexts()[0].res(), Some(Some(1)));
+        assert_eq!(repl.contexts()[1].res(), Some(Some(3)));
+        assert_eq!(repl.contexts()[2].res(), Some(Some(6)));
+        assert_eq!(repl.data.read(0).1, 1);
+
+        remote.sync(&slog);
+        assert_eq!(remote.data.read(0).0, 6);
+        assert_eq!(remote.data.read(0).1, 1);
+    }
+
+    // A counter that tracks how many responses it had to compute.
+    #[derive(Default)]
+    struct Applied {
+        value: u64,
+        responses: usize,
+    }
+
+    impl Dispatch for Applied {
+        type ReadOperation<'rop> = ();
+        type WriteOperation = u64;
+        type Response = u64;
+
+        fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+            self.value
+        }
+
+        fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+            self.responses += 1;
+            self.value += op;
+            self.value
+        }
+
+        fn apply_mut(&mut self, op: Self::WriteOperation) {
+            self.value += op;
+        }
+    }
+
+    // Tests that only the replica that appended an operation computes its
+    // response, other replicas just apply it.
+    #[test]
+    fn test_replica_apply_mut_foreign() {
+        let slog = Log::<<Applied as Dispatch>::WriteOperation>::default();
+        let repl = Replica::<Applied>::new(slog.register().unwrap());
+        let remote = Replica::<Applied>::new(slog.register().unwrap());
+        assert!(repl.register().is_some());
+        assert!(remote.register().is_some());
+
+        repl.make_pending(1, 1);
+        repl.try_combine(&slog).unwrap();
+        repl.make_pending(2, 1);
+        repl.try_combine(&slog).unwrap();
+        assert_eq!(repl.data.read(0).responses, 2);
+
+        // The remote combiner applies the two foreign entries before its own.
+        remote.make_pending(4, 1);
+        remote.try_combine(&slog).unwrap();
+        assert_eq!(remote.contexts()[0].res(), Some(Some(7)));
+        assert_eq!(remote.data.read(0).responses, 1);
+
+        repl.sync(&slog);
+        assert_eq!(repl.data.read(0).value, 7);
+        assert_eq!(repl.data.read(0).responses, 2);
     }
 
     // Tests whether try_combine() also applies pending operations on other threads to the log.
@@ -1033,7 +2404,7 @@ pub(crate) mod test {
         assert!(repl.try_combine(&slog).is_ok());
 
         assert_eq!(repl.data.read(0).junk, 1);
-        assert_eq!(repl.contexts[7].res(), Some(Ok(107)));
+        assert_eq!(repl.contexts()[7].res(), Some(Some(Ok(107))));
     }
 
     // Tests whether try_combine() fails if someone else is currently flat combining.
@@ -1049,7 +2420,7 @@ pub(crate) mod test {
         assert!(repl.try_combine(&slog).is_ok());
 
         assert_eq!(repl.data.read(0).junk, 0);
-        assert_eq!(repl.contexts[0].res(), None);
+        assert_eq!(repl.contexts()[0].res(), None);
     }
 
     // Tests whether we can execute an operation against the log using execute_mut().
@@ -1090,6 +2461,26 @@ pub(crate) mod test {
         assert_eq!(Ok(1), repl.execute(&slog, 11, idx).unwrap());
     }
 
+    // Tests that readers of a replica aren't blocked while it is forked.
+    #[test]
+    fn test_replica_fork_reads() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+        let idx = repl.register().expect("Failed to register with replica.");
+        assert_eq!(Ok(107), repl.execute_mut(&slog, 121, idx).unwrap());
+
+        let forked = repl.fork(
+            &slog,
+            |_lt| true,
+            |lt, d| {
+                assert_eq!(Ok(1), repl.execute(&slog, 11, idx).unwrap());
+                (lt, d.junk)
+            },
+        );
+        assert_eq!(forked.map(|(lt, junk)| (lt.0, junk)), Some((2, 1)));
+    }
+
     // Tests that execute() syncs up the replica with the log before
     // executing the read against the data structure.
     #[test]
@@ -1107,4 +2498,203 @@ pub(crate) mod test {
         let t1 = repl.register().expect("Failed to register with replica.");
         assert_eq!(Ok(2), repl.execute(&slog, 11, t1).unwrap());
     }
+
+    // Tests that execute_stale() only syncs up the replica with the log if
+    // it lags behind more than `max_lag` entries.
+    #[test]
+    fn test_replica_execute_stale() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+
+        let lt = slog.register().unwrap();
+        // Add in operations to the log off the side, not through the replica.
+        let o = [121, 212, 3];
+        slog.append(&o, &lt, |_o, _mine| {}).unwrap();
+        slog.exec(&lt, &mut |_o, _mine| {});
+
+        let t1 = repl.register().expect("Failed to register with replica.");
+        assert_eq!(Ok(0), repl.execute_stale(&slog, 11, t1, 3).unwrap());
+        assert_eq!(Ok(3), repl.execute_stale(&slog, 11, t1, 2).unwrap());
+    }
+
+    // Tests that a replica is only evicted once it lags more than `max_lag`
+    // entries behind and none of its threads operate on it, and that GC no
+    // longer waits for it afterwards.
+    #[test]
+    fn test_replica_evict() {
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let lt = slog.register().unwrap();
+        let repl = Replica::<Data>::new(lt);
+
+        let lt = slog.register().unwrap();
+        // Add in operations to the log off the side, not through the replica.
+        let o = [121, 212, 3];
+        slog.append(&o, &lt, |_o, _mine| {}).unwrap();
+        slog.exec(&lt, &mut |_o, _mine| {});
+        assert!(!repl.evict(&slog, 3));
+
+        let active = repl.enter().expect("Replica isn't dormant");
+        assert!(!repl.evict(&slog, 2));
+        drop(active);
+
+        assert!(repl.evict(&slog, 2));
+        assert!(repl.is_dormant());
+        assert!(repl.enter().is_none());
+        assert!(!repl.evict(&slog, 2));
+        assert_eq!(slog.find_min_tail(), (1, 3));
+    }
+
+    // Tests that a mutable operation is pending while another thread holds the
+    // combiner lock and that the task is woken once the lock is released.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_replica_poll_execute_mut() {
+        use std::sync::Arc;
+        use std::task::Wake;
+
+        struct CountWaker(AtomicUsize);
+        impl Wake for CountWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let repl = Replica::<Data>::new(slog.register().unwrap());
+        let idx = repl.register().expect("Failed to register with replica.");
+
+        let wakes = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut cx = task::Context::from_waker(&waker);
+        let mut pos = None;
+
+        let cl = repl.acquire_combiner_lock().unwrap();
+        assert!(repl
+            .poll_execute_mut(&slog, &121, &mut pos, idx, &mut cx)
+            .is_pending());
+        assert_eq!(pos, Some(0));
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+
+        drop(cl);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        match repl.poll_execute_mut(&slog, &121, &mut pos, idx, &mut cx) {
+            Poll::Ready(Ok(resp)) => assert_eq!(resp, Ok(107)),
+            _ => panic!("Operation should be completed"),
+        }
+        assert_eq!(repl.data.read(0).junk, 1);
+    }
+
+    // Tests that two tasks waiting on the context of the same thread are both
+    // woken once the combiner lock is released.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_replica_poll_execute_mut_shared() {
+        use std::sync::Arc;
+        use std::task::Wake;
+
+        struct CountWaker(AtomicUsize);
+        impl Wake for CountWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let repl = Replica::<Data>::new(slog.register().unwrap());
+        let idx = repl.register().expect("Failed to register with replica.");
+
+        let wakes = [
+            Arc::new(CountWaker(AtomicUsize::new(0))),
+            Arc::new(CountWaker(AtomicUsize::new(0))),
+        ];
+        let wakers = wakes.clone().map(Waker::from);
+        let mut pos = [None, None];
+
+        let cl = repl.acquire_combiner_lock().unwrap();
+        for (waker, pos) in wakers.iter().zip(pos.iter_mut()) {
+            let mut cx = task::Context::from_waker(waker);
+            assert!(repl
+                .poll_execute_mut(&slog, &121, pos, idx, &mut cx)
+                .is_pending());
+        }
+        assert_eq!(pos, [Some(0), Some(1)]);
+
+        drop(cl);
+        assert!(wakes.iter().all(|w| w.0.load(Ordering::SeqCst) == 1));
+        for (waker, pos) in wakers.iter().zip(pos.iter_mut()) {
+            let mut cx = task::Context::from_waker(waker);
+            match repl.poll_execute_mut(&slog, &121, pos, idx, &mut cx) {
+                Poll::Ready(Ok(resp)) => assert_eq!(resp, Ok(107)),
+                _ => panic!("Operation should be completed"),
+            }
+        }
+        assert_eq!(repl.data.read(0).junk, 2);
+    }
+
+    // Tests that a task whose context is full isn't woken right away (to poll
+    // again), but once another task of the thread took its response.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_replica_poll_execute_mut_full() {
+        use std::sync::Arc;
+        use std::task::Wake;
+
+        struct CountWaker(AtomicUsize);
+        impl Wake for CountWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let slog = Log::<<Data as Dispatch>::WriteOperation>::default();
+        let config = ReplicaConfig {
+            max_threads: 1,
+            pending_ops: 2,
+        };
+        let repl = Replica::<Data>::with_config(slog.register().unwrap(), config, Data::default())
+            .expect("Valid configuration");
+        let idx = repl.register().expect("Failed to register with replica.");
+
+        let wakes = [
+            Arc::new(CountWaker(AtomicUsize::new(0))),
+            Arc::new(CountWaker(AtomicUsize::new(0))),
+        ];
+        let wakers = wakes.clone().map(Waker::from);
+        let mut cx = [
+            task::Context::from_waker(&wakers[0]),
+            task::Context::from_waker(&wakers[1]),
+        ];
+        let mut pos = [None, None, None];
+
+        let cl = repl.acquire_combiner_lock().unwrap();
+        for pos in pos[..2].iter_mut() {
+            assert!(repl
+                .poll_execute_mut(&slog, &121, pos, idx, &mut cx[0])
+                .is_pending());
+        }
+        assert!(repl
+            .poll_execute_mut(&slog, &121, &mut pos[2], idx, &mut cx[1])
+            .is_pending());
+        assert_eq!(wakes[1].0.load(Ordering::SeqCst), 0);
+        drop(cl);
+
+        // Combines the first two operations, their responses fill the context.
+        let woken = wakes[1].0.load(Ordering::SeqCst);
+        assert!(repl
+            .poll_execute_mut(&slog, &121, &mut pos[2], idx, &mut cx[1])
+            .is_pending());
+        assert_eq!(pos[2], None);
+        assert_eq!(wakes[1].0.load(Ordering::SeqCst), woken + 1);
+
+        assert!(repl
+            .poll_execute_mut(&slog, &121, &mut pos[0], idx, &mut cx[0])
+            .is_ready());
+        assert_eq!(wakes[1].0.load(Ordering::SeqCst), woken + 2);
+        match repl.poll_execute_mut(&slog, &121, &mut pos[2], idx, &mut cx[1]) {
+            Poll::Ready(Ok(resp)) => assert_eq!(resp, Ok(107)),
+            _ => panic!("Operation should be completed"),
+        }
+        assert_eq!(repl.data.read(0).junk, 3);
+    }
 }
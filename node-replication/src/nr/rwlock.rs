@@ -14,9 +14,9 @@
 //! implementation which (with some modifications, see `loom_rwlock.rs`) we can
 //! use in the replica code.
 
+use alloc::boxed::Box;
 use core::cell::UnsafeCell;
 use core::default::Default;
-use core::hint::spin_loop;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
@@ -24,14 +24,12 @@ use crossbeam_utils::CachePadded;
 use static_assertions::const_assert;
 
 use crate::replica::MAX_THREADS_PER_REPLICA;
+use crate::wait::Waiter;
 
-/// Maximum number of reader threads that this lock supports.
+/// Default maximum number of reader threads that this lock supports.
 const MAX_READER_THREADS: usize = MAX_THREADS_PER_REPLICA;
 const_assert!(MAX_READER_THREADS > 0);
 
-#[allow(clippy::declare_interior_mutable_const)]
-const RLOCK_DEFAULT: CachePadded<AtomicUsize> = CachePadded::new(AtomicUsize::new(0));
-
 /// A scalable reader-writer lock.
 ///
 /// This lock favours reader performance over writers. Each reader thread gets
@@ -48,10 +46,13 @@ where
     wlock: CachePadded<AtomicBool>,
 
     /// Each reader use an individual lock to access the underlying data-structure.
-    rlock: [CachePadded<AtomicUsize>; MAX_READER_THREADS],
+    rlock: Box<[CachePadded<AtomicUsize>]>,
 
     /// The underlying data-structure.
     data: UnsafeCell<T>,
+
+    /// How threads wait for the lock (see [`crate::wait`]).
+    pub(crate) waiter: Waiter,
 }
 
 /// A read-guard that can be used to read the underlying data structure. Writes on
@@ -79,11 +80,7 @@ where
     /// Returns a new instance of a RwLock. Default constructs the
     /// underlying data structure.
     fn default() -> RwLock<T> {
-        RwLock {
-            wlock: CachePadded::new(AtomicBool::new(false)),
-            rlock: [RLOCK_DEFAULT; MAX_READER_THREADS],
-            data: UnsafeCell::new(T::default()),
-        }
+        RwLock::new(T::default())
     }
 }
 
@@ -94,10 +91,19 @@ where
     /// Returns a new instance of a RwLock. Default constructs the
     /// underlying data structure.
     pub fn new(t: T) -> Self {
+        RwLock::with_readers(t, MAX_READER_THREADS)
+    }
+
+    /// Returns a new instance of a RwLock that supports `readers` reader
+    /// threads (with ids `0..readers`).
+    pub fn with_readers(t: T, readers: usize) -> Self {
         Self {
             wlock: CachePadded::new(AtomicBool::new(false)),
-            rlock: [RLOCK_DEFAULT; MAX_READER_THREADS],
+            rlock: (0..readers)
+                .map(|_| CachePadded::new(AtomicUsize::new(0)))
+                .collect(),
             data: UnsafeCell::new(t),
+            waiter: Default::default(),
         }
     }
 
@@ -123,6 +129,7 @@ where
     /// ```
     pub fn write(&self, n: usize) -> WriteGuard<T> {
         // First, wait until we can acquire the writer lock.
+        let mut iteration = 0;
         loop {
             match self.wlock.compare_exchange_weak(
                 false,
@@ -131,19 +138,24 @@ where
                 Ordering::Acquire,
             ) {
                 Ok(_) => break,
-                Err(_) => continue,
+                Err(_) => {
+                    self.waiter.wait(iteration);
+                    iteration += 1;
+                }
             }
         }
 
         // Next, wait until all readers have released their locks. This condition
         // evaluates to true if each reader lock is free (i.e equal to zero).
+        let mut iteration = 0;
         while !self
             .rlock
             .iter()
             .take(n)
             .all(|item| item.load(Ordering::Relaxed) == 0)
         {
-            spin_loop();
+            self.waiter.wait(iteration);
+            iteration += 1;
         }
 
         unsafe { WriteGuard::new(self) }
@@ -175,12 +187,14 @@ where
                 as *const bool)
         };
 
+        let mut iteration = 0;
         loop {
             // First, wait until the write lock is free. This is the small
             // optimization spoken of earlier.
             unsafe {
                 while core::ptr::read_volatile(ptr) {
-                    spin_loop();
+                    self.waiter.wait(iteration);
+                    iteration += 1;
                 }
             }
 
@@ -208,6 +222,7 @@ where
             Ok(_) => (),
             Err(_) => panic!("write_unlock() called without acquiring the write lock"),
         }
+        self.waiter.notify();
     }
 
     /// Unlocks the read lock; called by the drop() method.
@@ -215,6 +230,14 @@ where
         if self.rlock[tid].fetch_sub(1, Ordering::Release) == 0 {
             panic!("read_unlock() called without acquiring the read lock");
         }
+        // Only a writer waits for readers, don't bother the wait strategy
+        // (which might take a lock) otherwise. A writer that sets `wlock`
+        // concurrently checks the readers after it did, and a strategy that
+        // parks threads has to cope with missed notifications anyway (see
+        // `wait::Park`).
+        if self.wlock.load(Ordering::Acquire) {
+            self.waiter.notify();
+        }
     }
 }
 
@@ -0,0 +1,817 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Node replication across processes, with the log in shared memory.
+//!
+//! [`crate::nr::NodeReplicated`] keeps the [`crate::nr::Log`] and all replicas
+//! in the heap of one process. With this module every replica lives in a
+//! process of its own, e.g., to isolate them from each other or to restart
+//! them independently: [`ShmLog`] places the log, including the local tail of
+//! every replica, in a shared memory mapping (created with `memfd_create(2)`
+//! or `shm_open(3)`) and a process attaches to it as replica N with
+//! [`ShmReplica::attach`].
+//!
+//! The mapping holds no pointers, so processes can map it at different
+//! addresses. Operations are stored in the log as bytes, encoded with
+//! [`LogSerialize`]; every entry has room for the number of bytes chosen when
+//! the log is created. All processes have to use the same build of the data
+//! structure and this crate.
+//!
+//! A replica is only used by the process that attached it. There is no flat
+//! combining: every operation is appended on its own and the threads of a
+//! process share its [`ShmReplica`] (e.g., behind a mutex).
+//!
+//! [`crate::nr::Log`] isn't reused for the mapping: it keeps its entries and
+//! the state of the replicas in separately allocated slices (with pointers to
+//! the operations in its arena), tracks log wrap-arounds with per-replica
+//! masks that aren't shared memory safe, and stores operations by value.
+//! [`ShmLog`] is a flat layout of plain integers instead, the wrap-around of
+//! an entry follows from its position.
+//!
+//! # Restarts
+//! The local tail of a replica stays in the log when its process detaches or
+//! dies. A process that attaches as the same replica later continues from
+//! there, so it has to bring a data structure that reflects all operations
+//! before [`ShmLog::local_tail`] (e.g., restored from a checkpoint). A replica
+//! that never attached starts at the beginning of the log, which is why
+//! entries are only reused once all replicas applied them. A replica that
+//! stops syncing, or a process that dies after reserving an entry but before
+//! filling it, eventually blocks the other replicas: their operations fail
+//! with [`ShmError::Stalled`].
+//!
+//! # Example
+//!
+//! ```
+//! # #![feature(generic_associated_types)]
+//! use node_replication::nr::shm::{ShmLog, ShmReplica};
+//! # use node_replication::nr::Dispatch;
+//!
+//! #[derive(Default)]
+//! struct Counter(u64);
+//! #
+//! # impl Dispatch for Counter {
+//! #     type ReadOperation<'rop> = ();
+//! #     type WriteOperation = u64;
+//! #     type Response = u64;
+//! #
+//! #     fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+//! #         self.0
+//! #     }
+//! #
+//! #     fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+//! #         self.0 += op;
+//! #         self.0
+//! #     }
+//! # }
+//!
+//! // A log with 1024 entries of up to 8 bytes for two replicas. The file
+//! // descriptor (see `as_raw_fd`) can be inherited by or sent to the process
+//! // of the second replica, which maps it with `ShmLog::from_raw_fd`.
+//! let log = ShmLog::<u64>::memfd(1024, 8, 2).expect("can't create log");
+//! let other = log.try_clone().expect("can't map log");
+//!
+//! let mut r0 = ShmReplica::attach(log, 0, Counter::default()).unwrap();
+//! let mut r1 = ShmReplica::attach(other, 1, Counter::default()).unwrap();
+//! assert_eq!(r0.execute_mut(5).unwrap(), 5);
+//! assert_eq!(r1.execute_mut(1).unwrap(), 6);
+//! assert_eq!(r0.execute(()).unwrap(), 6);
+//! ```
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::ffi::CStr;
+use std::io;
+use std::os::unix::io::RawFd;
+
+use crossbeam_utils::CachePadded;
+
+use super::wal::LogSerialize;
+use super::{Dispatch, ReplicaId};
+use crate::wait;
+
+/// Identifies a mapping that holds a [`ShmLog`] ("NRSHMLOG").
+const MAGIC: u64 = u64::from_le_bytes(*b"NRSHMLOG");
+
+/// Version of the layout of the mapping, bumped whenever it changes.
+const VERSION: u64 = 2;
+
+/// Number of unsuccessful iterations after which a replica gives up waiting
+/// for free entries or for an entry to be filled (see [`ShmError::Stalled`]).
+///
+/// Other replicas are processes that might not be scheduled for a while, so
+/// this is a lot more patient than [`crate::nr::log::WARN_THRESHOLD`].
+const STALL_THRESHOLD: usize = 1 << 24;
+
+/// Errors of the shared memory log and its replicas.
+#[derive(Debug)]
+pub enum ShmError {
+    /// Creating, opening or mapping the shared memory failed.
+    Io(io::Error),
+    /// The parameters for a new log are unusable, the string says why.
+    InvalidConfig(&'static str),
+    /// The mapping doesn't hold a (fully initialized) log of this version.
+    InvalidLayout,
+    /// The log has no replica with this id.
+    InvalidReplica,
+    /// A live process (with the given pid) is attached as this replica.
+    ReplicaInUse(u32),
+    /// The encoded operation doesn't fit into an entry of the log.
+    OpTooLarge,
+    /// The log is full and the replica with the given id doesn't apply its
+    /// entries, the operation was not appended. Or (with `None`) an entry
+    /// was reserved but never filled, e.g., because the process appending it
+    /// died. An operation of ours that was appended before is applied (and
+    /// its response dropped), the entries after the unfilled one are applied
+    /// by the next operation that gets past it.
+    Stalled(Option<ReplicaId>),
+    /// An entry of the log is not a valid operation (see
+    /// [`LogSerialize::deserialize`]).
+    Corrupt,
+}
+
+impl From<io::Error> for ShmError {
+    fn from(e: io::Error) -> Self {
+        ShmError::Io(e)
+    }
+}
+
+/// State of a replica, stored in the mapping.
+#[repr(C)]
+struct ReplicaSlot {
+    /// Position of the next entry the replica applies.
+    ltail: AtomicU64,
+    /// Pid of the process that is attached as the replica, 0 if none is.
+    owner: AtomicU32,
+}
+
+/// The start of the mapping, followed by a [`ReplicaSlot`] for every replica
+/// and the entries.
+#[repr(C)]
+struct Header {
+    /// [`MAGIC`], written last when the log is created.
+    magic: AtomicU64,
+    version: u64,
+    entries: u64,
+    op_bytes: u64,
+    replicas: u64,
+    /// Position of the next entry to reserve.
+    tail: CachePadded<AtomicU64>,
+    /// Position of the oldest entry that is still in use.
+    head: CachePadded<AtomicU64>,
+    /// Position up to which some replica applied all entries.
+    ctail: CachePadded<AtomicU64>,
+}
+
+/// An entry of the log, followed by `op_bytes` bytes for the encoded
+/// operation.
+#[repr(C)]
+struct EntryHeader {
+    /// Entry holds an operation if this is the alive mask of its position
+    /// (see [`ShmLog::alive_mask`]).
+    alivef: AtomicBool,
+    /// The replica that appended the entry (its id + 1).
+    replica: u32,
+    /// Length of the encoded operation.
+    len: u32,
+}
+
+/// A log shared by replicas in different processes.
+///
+/// The log is a circular buffer of `entries` entries in a shared memory
+/// mapping. Dropping a [`ShmLog`] unmaps it, the log lives on as long as
+/// other processes map it (or, for [`ShmLog::create`], until it is
+/// [unlinked](ShmLog::unlink)).
+pub struct ShmLog<T> {
+    base: NonNull<u8>,
+    bytes: usize,
+    fd: RawFd,
+    _op: PhantomData<fn(T) -> T>,
+}
+
+// Safety: The mapping is only accessed through atomics, entries are
+// written by the replica that reserved them and read by the others only
+// after they were published (`alivef`) and before they are reused.
+unsafe impl<T> Send for ShmLog<T> {}
+unsafe impl<T> Sync for ShmLog<T> {}
+
+/// Turns the return value `ret` of a libc call into an error if it's
+/// negative.
+fn check(ret: libc::c_int) -> Result<libc::c_int, ShmError> {
+    if ret < 0 {
+        Err(io::Error::last_os_error().into())
+    } else {
+        Ok(ret)
+    }
+}
+
+impl<T> ShmLog<T>
+where
+    T: LogSerialize,
+{
+    /// Creates a log in an anonymous `memfd_create(2)` file.
+    ///
+    /// The log has `entries` entries, each with room for an operation that
+    /// encodes to at most `op_bytes` bytes, for `replicas` replicas. Other
+    /// processes map it with [`ShmLog::from_raw_fd`] after inheriting (or
+    /// receiving) the file descriptor.
+    pub fn memfd(entries: usize, op_bytes: usize, replicas: usize) -> Result<Self, ShmError> {
+        let bytes = Self::layout(entries, op_bytes, replicas)?;
+        let name = b"node-replication\0";
+        let fd = check(unsafe { libc::memfd_create(name.as_ptr() as *const libc::c_char, 0) })?;
+        Self::init(fd, bytes, entries, op_bytes, replicas)
+    }
+
+    /// Creates a log in the POSIX shared memory object `name` (see
+    /// `shm_open(3)`), which must not exist yet.
+    ///
+    /// See [`ShmLog::memfd`] for the parameters. Other processes map it with
+    /// [`ShmLog::open`].
+    pub fn create(
+        name: &CStr,
+        entries: usize,
+        op_bytes: usize,
+        replicas: usize,
+    ) -> Result<Self, ShmError> {
+        let bytes = Self::layout(entries, op_bytes, replicas)?;
+        let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL;
+        let fd = check(unsafe { libc::shm_open(name.as_ptr(), flags, 0o600) })?;
+        Self::init(fd, bytes, entries, op_bytes, replicas)
+    }
+
+    /// Maps the log in the POSIX shared memory object `name`.
+    pub fn open(name: &CStr) -> Result<Self, ShmError> {
+        let fd = check(unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) })?;
+        // Safety: We just opened `fd`.
+        unsafe { Self::from_raw_fd(fd) }
+    }
+
+    /// Removes the POSIX shared memory object `name`. Processes that mapped
+    /// the log keep using it.
+    pub fn unlink(name: &CStr) -> Result<(), ShmError> {
+        check(unsafe { libc::shm_unlink(name.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Maps the log in the file `fd`.
+    ///
+    /// # Safety
+    /// `fd` must be an open file descriptor, which is owned (and closed) by
+    /// the returned log.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self, ShmError> {
+        let mut stat: libc::stat = core::mem::zeroed();
+        if let Err(e) = check(libc::fstat(fd, &mut stat)) {
+            libc::close(fd);
+            return Err(e);
+        }
+        let bytes = stat.st_size as usize;
+        if bytes < size_of::<Header>() {
+            libc::close(fd);
+            return Err(ShmError::InvalidLayout);
+        }
+
+        let log = Self::map(fd, bytes)?;
+        let h = log.header();
+        if h.magic.load(Ordering::Acquire) != MAGIC || h.version != VERSION {
+            return Err(ShmError::InvalidLayout);
+        }
+        let expected = Self::layout(h.entries as usize, h.op_bytes as usize, h.replicas as usize);
+        if expected.ok() != Some(bytes) {
+            return Err(ShmError::InvalidLayout);
+        }
+        Ok(log)
+    }
+
+    /// Maps the log another time (at a different address), e.g., to attach
+    /// one process as several replicas.
+    pub fn try_clone(&self) -> Result<Self, ShmError> {
+        let fd = check(unsafe { libc::dup(self.fd) })?;
+        // Safety: We just duplicated `fd`.
+        unsafe { Self::from_raw_fd(fd) }
+    }
+
+    /// The file descriptor of the mapped file.
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Number of replicas of the log.
+    pub fn replicas(&self) -> usize {
+        self.header().replicas as usize
+    }
+
+    /// Maximum length of an encoded operation.
+    pub fn op_bytes(&self) -> usize {
+        self.header().op_bytes as usize
+    }
+
+    /// Number of entries appended to the log since it was created.
+    pub fn tail(&self) -> usize {
+        self.header().tail.load(Ordering::Acquire) as usize
+    }
+
+    /// Number of entries replica `rid` applied (or None if the log has no
+    /// such replica).
+    pub fn local_tail(&self, rid: ReplicaId) -> Option<usize> {
+        self.slot(rid)
+            .map(|s| s.ltail.load(Ordering::Acquire) as usize)
+    }
+
+    /// Returns the size of the mapping for a log with the given parameters.
+    fn layout(entries: usize, op_bytes: usize, replicas: usize) -> Result<usize, ShmError> {
+        if entries == 0 {
+            return Err(ShmError::InvalidConfig("The log needs at least one entry"));
+        }
+        // Entries store the id of their replica + 1 as an `u32`.
+        if replicas == 0 || replicas >= u32::MAX as usize {
+            return Err(ShmError::InvalidConfig(
+                "The log needs at least one replica",
+            ));
+        }
+        if op_bytes > u32::MAX as usize {
+            return Err(ShmError::InvalidConfig("Operations are too large"));
+        }
+        entries
+            .checked_mul(Self::entry_bytes(op_bytes))
+            .and_then(|e| e.checked_add(Self::entries_offset(replicas)?))
+            .ok_or(ShmError::InvalidConfig("The log is too large"))
+    }
+
+    /// Offset of the first entry in the mapping of a log with `replicas`
+    /// replicas.
+    fn entries_offset(replicas: usize) -> Option<usize> {
+        replicas
+            .checked_mul(size_of::<CachePadded<ReplicaSlot>>())?
+            .checked_add(size_of::<Header>())
+    }
+
+    /// Size of an entry with room for `op_bytes` bytes.
+    fn entry_bytes(op_bytes: usize) -> usize {
+        // Alignments are powers of two.
+        let align = core::mem::align_of::<EntryHeader>();
+        (size_of::<EntryHeader>() + op_bytes + align - 1) & !(align - 1)
+    }
+
+    /// Sizes the (new) file `fd`, maps it and initializes the log.
+    fn init(
+        fd: RawFd,
+        bytes: usize,
+        entries: usize,
+        op_bytes: usize,
+        replicas: usize,
+    ) -> Result<Self, ShmError> {
+        if let Err(e) = check(unsafe { libc::ftruncate(fd, bytes as libc::off_t) }) {
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        let log = Self::map(fd, bytes)?;
+
+        // The file is zero-filled: all positions, tails and owners are 0 and
+        // no entry is alive.
+        let h = log.base.as_ptr() as *mut Header;
+        unsafe {
+            ptr::addr_of_mut!((*h).version).write(VERSION);
+            ptr::addr_of_mut!((*h).entries).write(entries as u64);
+            ptr::addr_of_mut!((*h).op_bytes).write(op_bytes as u64);
+            ptr::addr_of_mut!((*h).replicas).write(replicas as u64);
+        }
+        log.header().magic.store(MAGIC, Ordering::Release);
+        Ok(log)
+    }
+
+    /// Maps `bytes` bytes of the file `fd`, closes `fd` if that fails.
+    fn map(fd: RawFd, bytes: usize) -> Result<Self, ShmError> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                bytes,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e.into());
+        }
+
+        Ok(ShmLog {
+            base: NonNull::new(base as *mut u8).unwrap(),
+            bytes,
+            fd,
+            _op: PhantomData,
+        })
+    }
+
+    fn header(&self) -> &Header {
+        // Safety: The mapping starts with the header, see `layout`.
+        unsafe { &*(self.base.as_ptr() as *const Header) }
+    }
+
+    fn slot(&self, rid: ReplicaId) -> Option<&ReplicaSlot> {
+        if rid >= self.replicas() {
+            return None;
+        }
+        // Safety: The slots of the replicas follow the header, see `layout`.
+        unsafe {
+            let slots = self.base.as_ptr().add(size_of::<Header>());
+            Some(&*(slots as *const CachePadded<ReplicaSlot>).add(rid))
+        }
+    }
+
+    /// The entry at (logical) position `pos`.
+    fn entry(&self, pos: u64) -> *mut EntryHeader {
+        let h = self.header();
+        let idx = (pos % h.entries) as usize;
+        let offset = Self::entries_offset(h.replicas as usize).unwrap()
+            + idx * Self::entry_bytes(h.op_bytes as usize);
+        // Safety: `idx` is within the entries that follow the header.
+        unsafe { self.base.as_ptr().add(offset) as *mut EntryHeader }
+    }
+
+    /// The value of `alivef` of a filled entry at position `pos`. It flips
+    /// every time the log wraps around, so replicas don't need to keep a mask
+    /// of their own (see [`crate::nr::Log`]).
+    fn alive_mask(&self, pos: u64) -> bool {
+        (pos / self.header().entries) & 1 == 0
+    }
+
+    /// Sets the owner of replica `rid` to the current process.
+    fn claim(&self, rid: ReplicaId) -> Result<(), ShmError> {
+        let slot = self.slot(rid).ok_or(ShmError::InvalidReplica)?;
+        let pid = std::process::id();
+        loop {
+            let owner = slot.owner.load(Ordering::Acquire);
+            // A process that is gone (`kill` fails with ESRCH) no longer owns
+            // the replica.
+            let alive = owner != 0
+                && (unsafe { libc::kill(owner as libc::pid_t, 0) } == 0
+                    || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH));
+            if alive {
+                return Err(ShmError::ReplicaInUse(owner));
+            }
+            if slot
+                .owner
+                .compare_exchange(owner, pid, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Releases replica `rid` if it is owned by the current process.
+    fn release(&self, rid: ReplicaId) {
+        if let Some(slot) = self.slot(rid) {
+            let _r = slot.owner.compare_exchange(
+                std::process::id(),
+                0,
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    /// Appends the encoded operation `op` for replica `rid` and returns its
+    /// position.
+    ///
+    /// If the log is full, this waits for the other replicas and applies
+    /// entries with `s` in the meantime so it doesn't hold up garbage
+    /// collection itself.
+    fn append<F: FnMut(T, bool)>(
+        &self,
+        op: &[u8],
+        rid: ReplicaId,
+        s: &mut F,
+    ) -> Result<u64, ShmError> {
+        if op.len() > self.op_bytes() {
+            return Err(ShmError::OpTooLarge);
+        }
+
+        let h = self.header();
+        let mut iteration = 1;
+        loop {
+            let tail = h.tail.load(Ordering::Acquire);
+            let head = h.head.load(Ordering::Acquire);
+
+            // An entry can only be reused once all replicas applied it.
+            if tail >= head + h.entries {
+                if let Err(min_rid) = self.advance_head() {
+                    if iteration % STALL_THRESHOLD == 0 {
+                        return Err(ShmError::Stalled(Some(min_rid)));
+                    }
+                    iteration += 1;
+                    self.exec(rid, None, s)?;
+                    wait::wait(iteration);
+                }
+                continue;
+            }
+
+            if h.tail
+                .compare_exchange_weak(tail, tail + 1, Ordering::AcqRel, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+
+            let e = self.entry(tail);
+            unsafe {
+                let payload = (e as *mut u8).add(size_of::<EntryHeader>());
+                ptr::copy_nonoverlapping(op.as_ptr(), payload, op.len());
+                ptr::addr_of_mut!((*e).replica).write(rid as u32 + 1);
+                ptr::addr_of_mut!((*e).len).write(op.len() as u32);
+                (*e).alivef.store(self.alive_mask(tail), Ordering::Release);
+            }
+            return Ok(tail);
+        }
+    }
+
+    /// Applies all entries from the local tail of replica `rid` to the tail of
+    /// the log with `d`. Its second argument is true for the entry at position
+    /// `own`.
+    ///
+    /// Gives up if an entry isn't filled in time (see [`ShmError::Stalled`]),
+    /// the entries before it are applied.
+    fn exec<F: FnMut(T, bool)>(
+        &self,
+        rid: ReplicaId,
+        own: Option<u64>,
+        d: &mut F,
+    ) -> Result<(), ShmError> {
+        let h = self.header();
+        let slot = self.slot(rid).ok_or(ShmError::InvalidReplica)?;
+        let ltail = slot.ltail.load(Ordering::Relaxed);
+        let gtail = h.tail.load(Ordering::Acquire);
+
+        for pos in ltail..gtail {
+            let e = self.entry(pos);
+            let mut iteration = 1;
+            // The entry might be reserved, but not filled yet.
+            while unsafe { (*e).alivef.load(Ordering::Acquire) } != self.alive_mask(pos) {
+                if iteration % STALL_THRESHOLD == 0 {
+                    h.ctail.fetch_max(pos, Ordering::AcqRel);
+                    return Err(ShmError::Stalled(None));
+                }
+                wait::wait(iteration);
+                iteration += 1;
+            }
+
+            let op = unsafe {
+                let payload = (e as *const u8).add(size_of::<EntryHeader>());
+                T::deserialize(slice::from_raw_parts(payload, (*e).len as usize))
+            };
+            d(op.ok_or(ShmError::Corrupt)?, own == Some(pos));
+            // Store the tail after every entry, so a replica that restarts
+            // after its process died doesn't apply an entry twice.
+            slot.ltail.store(pos + 1, Ordering::Release);
+        }
+
+        h.ctail.fetch_max(gtail, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Moves the head to the smallest local tail of all replicas. Returns the
+    /// replica with this tail if the head didn't move.
+    fn advance_head(&self) -> Result<(), ReplicaId> {
+        let h = self.header();
+        let (min_rid, min_tail) = (0..self.replicas())
+            .map(|rid| (rid, self.slot(rid).unwrap().ltail.load(Ordering::Acquire)))
+            .min_by_key(|&(_rid, ltail)| ltail)
+            .unwrap();
+
+        if h.head.fetch_max(min_tail, Ordering::AcqRel) < min_tail {
+            Ok(())
+        } else {
+            Err(min_rid)
+        }
+    }
+}
+
+impl<T> Drop for ShmLog<T> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base.as_ptr() as *mut libc::c_void, self.bytes);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// A replica of a data structure in the current process, kept in sync with
+/// the replicas in other processes through a [`ShmLog`].
+pub struct ShmReplica<D>
+where
+    D: Dispatch,
+    D::WriteOperation: LogSerialize,
+{
+    log: ShmLog<D::WriteOperation>,
+    rid: ReplicaId,
+    data: D,
+    /// Buffer for encoding operations.
+    buf: Vec<u8>,
+}
+
+impl<D> ShmReplica<D>
+where
+    D: Dispatch,
+    D::WriteOperation: LogSerialize,
+{
+    /// Attaches the current process to `log` as replica `rid` with the data
+    /// structure `data`.
+    ///
+    /// `data` must reflect the first [`ShmLog::local_tail`] entries of the
+    /// log (for a replica that never attached, that's none). Fails if another
+    /// live process is attached as `rid`.
+    pub fn attach(
+        log: ShmLog<D::WriteOperation>,
+        rid: ReplicaId,
+        data: D,
+    ) -> Result<Self, ShmError> {
+        log.claim(rid)?;
+        Ok(ShmReplica {
+            log,
+            rid,
+            data,
+            buf: Vec::new(),
+        })
+    }
+
+    /// The id of the replica.
+    pub fn id(&self) -> ReplicaId {
+        self.rid
+    }
+
+    /// The log of the replica.
+    pub fn log(&self) -> &ShmLog<D::WriteOperation> {
+        &self.log
+    }
+
+    /// Executes a mutable operation against the data structure and returns
+    /// its response once all replicas can see it.
+    pub fn execute_mut(&mut self, op: D::WriteOperation) -> Result<D::Response, ShmError> {
+        self.buf.clear();
+        op.serialize(&mut self.buf);
+
+        // Our previous operations are applied already, so all entries we
+        // apply before ours were appended by other replicas.
+        let data = &mut self.data;
+        let pos = self
+            .log
+            .append(&self.buf, self.rid, &mut |o, _mine| data.apply_mut(o))?;
+
+        let mut resp = None;
+        self.log.exec(self.rid, Some(pos), &mut |o, mine| {
+            if mine {
+                resp = Some(data.dispatch_mut(o));
+            } else {
+                data.apply_mut(o);
+            }
+        })?;
+        Ok(resp.expect("Operation not applied"))
+    }
+
+    /// Executes a read-only operation against the data structure, after it
+    /// applied all operations that completed before.
+    pub fn execute(&mut self, op: D::ReadOperation<'_>) -> Result<D::Response, ShmError> {
+        self.sync()?;
+        Ok(self.data.dispatch(op))
+    }
+
+    /// Applies all operations that other replicas appended to the log.
+    pub fn sync(&mut self) -> Result<(), ShmError> {
+        let data = &mut self.data;
+        self.log
+            .exec(self.rid, None, &mut |o, _mine| data.apply_mut(o))
+    }
+
+    /// Detaches from the log and returns the data structure, which reflects
+    /// the first [`ShmLog::local_tail`] entries of the log.
+    pub fn detach(self) -> D {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        this.log.release(this.rid);
+        // Safety: `this` is not used (or dropped) afterwards.
+        unsafe {
+            ptr::drop_in_place(&mut this.log);
+            ptr::drop_in_place(&mut this.buf);
+            ptr::read(&this.data)
+        }
+    }
+}
+
+impl<D> Drop for ShmReplica<D>
+where
+    D: Dispatch,
+    D::WriteOperation: LogSerialize,
+{
+    fn drop(&mut self) {
+        self.log.release(self.rid);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter(u64);
+
+    impl Dispatch for Counter {
+        type ReadOperation<'rop> = ();
+        type WriteOperation = u64;
+        type Response = u64;
+
+        fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+            self.0
+        }
+
+        fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+            self.0 += op;
+            self.0
+        }
+    }
+
+    // Tests that replicas converge when the log is mapped at different
+    // addresses and wraps around several times.
+    #[test]
+    fn test_shm_replicas_converge() {
+        let log = ShmLog::<u64>::memfd(8, 8, 2).unwrap();
+        let other = log.try_clone().unwrap();
+        assert_ne!(log.base, other.base);
+
+        let mut r0 = ShmReplica::attach(log, 0, Counter::default()).unwrap();
+        let mut r1 = ShmReplica::attach(other, 1, Counter::default()).unwrap();
+        for i in 1..=50 {
+            r0.execute_mut(i).unwrap();
+            r1.execute_mut(i).unwrap();
+        }
+
+        assert_eq!(r0.execute(()).unwrap(), 2550);
+        assert_eq!(r1.execute(()).unwrap(), 2550);
+        assert_eq!(r0.log().tail(), 100);
+    }
+
+    // Tests that a replica can't be attached twice and that a new process
+    // continues at the local tail after the replica detached.
+    #[test]
+    fn test_shm_attach() {
+        let log = ShmLog::<u64>::memfd(8, 8, 2).unwrap();
+        let mut r0 = ShmReplica::attach(log.try_clone().unwrap(), 0, Counter::default()).unwrap();
+        assert!(matches!(
+            ShmReplica::attach(log.try_clone().unwrap(), 0, Counter::default()),
+            Err(ShmError::ReplicaInUse(_))
+        ));
+        assert!(matches!(
+            ShmReplica::attach(log.try_clone().unwrap(), 2, Counter::default()),
+            Err(ShmError::InvalidReplica)
+        ));
+
+        r0.execute_mut(3).unwrap();
+        let data = r0.detach();
+        assert_eq!(log.local_tail(0), Some(1));
+
+        let mut r0 = ShmReplica::attach(log, 0, data).unwrap();
+        assert_eq!(r0.execute_mut(4).unwrap(), 7);
+    }
+
+    // Tests that a replica gives up on an entry that was reserved but never
+    // filled and applies the entries before it.
+    #[test]
+    fn test_shm_unfilled_entry() {
+        let log = ShmLog::<u64>::memfd(8, 8, 2).unwrap();
+        let mut r0 = ShmReplica::attach(log, 0, Counter::default()).unwrap();
+        let mut r1 =
+            ShmReplica::attach(r0.log().try_clone().unwrap(), 1, Counter::default()).unwrap();
+        r0.execute_mut(1).unwrap();
+
+        // A process that died right after reserving an entry.
+        r0.log().header().tail.fetch_add(1, Ordering::AcqRel);
+        assert!(matches!(r1.sync(), Err(ShmError::Stalled(None))));
+        assert_eq!(r1.log().local_tail(1), Some(1));
+        assert_eq!(r1.data.0, 1);
+    }
+
+    // Tests that appends fail if an operation doesn't fit an entry and that
+    // the log refuses mappings it didn't create.
+    #[test]
+    fn test_shm_invalid() {
+        let log = ShmLog::<u64>::memfd(8, 4, 1).unwrap();
+        let mut r0 = ShmReplica::attach(log, 0, Counter::default()).unwrap();
+        assert!(matches!(r0.execute_mut(1), Err(ShmError::OpTooLarge)));
+        assert_eq!(r0.log().tail(), 0);
+
+        assert!(matches!(
+            ShmLog::<u64>::memfd(8, 8, 0),
+            Err(ShmError::InvalidConfig(_))
+        ));
+
+        let fd = unsafe { libc::memfd_create(b"empty\0".as_ptr() as *const libc::c_char, 0) };
+        assert!(matches!(
+            unsafe { ShmLog::<u64>::from_raw_fd(fd) },
+            Err(ShmError::InvalidLayout)
+        ));
+    }
+}
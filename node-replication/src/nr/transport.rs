@@ -0,0 +1,213 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Streams the mutable operations of a [`crate::nr::NodeReplicated`] instance
+//! (the leader) to another instance (a follower), e.g., a hot standby in a
+//! different process.
+//!
+//! The combiners of the leader hand every batch of operations they append to
+//! the [`crate::nr::Log`] to a [`LogTransport`], in log order and in the
+//! record format of the [write-ahead log](crate::nr::wal). The follower
+//! receives them in [`crate::nr::NodeReplicated::follow`] and applies them to
+//! its own replicas. It is read-only while it follows.
+//!
+//! The follower has to start with the same state as the leader, so the leader
+//! streams its operations from the moment it is created (see
+//! [`crate::nr::NodeReplicatedBuilder::transport`]).
+//!
+//! With the `std` feature, `ChannelTransport` (between instances in the same
+//! process) and `UnixTransport` (over a Unix domain socket) are provided.
+
+use alloc::vec::Vec;
+
+use super::wal::WalError;
+
+/// A connection between a leader and a follower that carries the records of
+/// the operations the leader appends to its log.
+///
+/// The leader only calls [`LogTransport::send`], the follower only
+/// [`LogTransport::recv`].
+pub trait LogTransport: Send {
+    /// Sends `buf` (the records of one batch of operations) to the other end.
+    ///
+    /// If this fails, the leader stops using the transport.
+    fn send(&mut self, buf: &[u8]) -> Result<(), WalError>;
+
+    /// Waits for data from the other end and appends it to `buf`. The data
+    /// doesn't have to arrive in the chunks it was sent in.
+    ///
+    /// Returns false (without appending data) once the other end closed the
+    /// transport and all data it sent was received.
+    fn recv(&mut self, buf: &mut Vec<u8>) -> Result<bool, WalError>;
+}
+
+/// A [`LogTransport`] between two instances in the same process.
+///
+/// # Example
+///
+/// ```
+/// # #![feature(generic_associated_types)]
+/// use node_replication::nr::transport::ChannelTransport;
+/// use node_replication::nr::{Dispatch, NodeReplicated};
+/// use std::sync::Arc;
+///
+/// #[derive(Default)]
+/// struct Counter(u64);
+/// #
+/// # impl Dispatch for Counter {
+/// #     type ReadOperation<'rop> = ();
+/// #     type WriteOperation = u64;
+/// #     type Response = u64;
+/// #
+/// #     fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+/// #         self.0
+/// #     }
+/// #
+/// #     fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+/// #         self.0 += op;
+/// #         self.0
+/// #     }
+/// # }
+///
+/// let (to_follower, from_leader) = ChannelTransport::pair();
+/// let leader = NodeReplicated::<Counter>::builder()
+///     .transport(to_follower)
+///     .build()
+///     .unwrap();
+///
+/// let follower = Arc::new(NodeReplicated::<Counter>::builder().build().unwrap());
+/// let ftkn = follower.register(0).unwrap();
+/// let following = {
+///     let follower = follower.clone();
+///     std::thread::spawn(move || follower.follow(from_leader, ftkn))
+/// };
+///
+/// let ttkn = leader.register(0).unwrap();
+/// leader.execute_mut(40, ttkn);
+/// leader.execute_mut(2, ttkn);
+/// // Closes the transport, the follower returns once it applied everything.
+/// drop(leader);
+/// following.join().unwrap().unwrap();
+///
+/// let ttkn = follower.register(0).unwrap();
+/// assert_eq!(follower.execute((), ttkn), 42);
+/// ```
+#[cfg(feature = "std")]
+pub struct ChannelTransport {
+    tx: std::sync::mpsc::Sender<Vec<u8>>,
+    rx: std::sync::mpsc::Receiver<Vec<u8>>,
+}
+
+#[cfg(feature = "std")]
+impl ChannelTransport {
+    /// Creates two connected ends of a transport.
+    pub fn pair() -> (Self, Self) {
+        let (tx1, rx1) = std::sync::mpsc::channel();
+        let (tx2, rx2) = std::sync::mpsc::channel();
+        (
+            ChannelTransport { tx: tx1, rx: rx2 },
+            ChannelTransport { tx: tx2, rx: rx1 },
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl LogTransport for ChannelTransport {
+    fn send(&mut self, buf: &[u8]) -> Result<(), WalError> {
+        self.tx.send(buf.to_vec()).map_err(|_e| WalError::Storage)
+    }
+
+    fn recv(&mut self, buf: &mut Vec<u8>) -> Result<bool, WalError> {
+        match self.rx.recv() {
+            Ok(data) => {
+                buf.extend_from_slice(&data);
+                Ok(true)
+            }
+            // All senders are gone.
+            Err(_e) => Ok(false),
+        }
+    }
+}
+
+/// A [`LogTransport`] over a Unix domain socket, e.g., to a follower in
+/// another process.
+#[cfg(all(feature = "std", unix))]
+pub struct UnixTransport(std::os::unix::net::UnixStream);
+
+#[cfg(all(feature = "std", unix))]
+impl UnixTransport {
+    /// Connects to the socket at `path`. The other end wraps the stream it
+    /// accepts with [`UnixTransport::from`].
+    pub fn connect(path: impl AsRef<std::path::Path>) -> Result<Self, WalError> {
+        Ok(UnixTransport(std::os::unix::net::UnixStream::connect(
+            path,
+        )?))
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl From<std::os::unix::net::UnixStream> for UnixTransport {
+    fn from(stream: std::os::unix::net::UnixStream) -> Self {
+        UnixTransport(stream)
+    }
+}
+
+#[cfg(all(feature = "std", unix))]
+impl LogTransport for UnixTransport {
+    fn send(&mut self, buf: &[u8]) -> Result<(), WalError> {
+        use std::io::Write;
+        Ok(self.0.write_all(buf)?)
+    }
+
+    fn recv(&mut self, buf: &mut Vec<u8>) -> Result<bool, WalError> {
+        use std::io::{ErrorKind, Read};
+        let mut chunk = [0; 4096];
+        loop {
+            match self.0.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => {
+                    buf.extend_from_slice(&chunk[..n]);
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std", unix))]
+mod test {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    // Tests that data sent over a Unix domain socket arrives in order and
+    // that the receiver notices when the sender closes the socket.
+    #[test]
+    fn test_unix_transport() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (mut a, mut b) = (UnixTransport::from(a), UnixTransport::from(b));
+        a.send(&[1, 2, 3]).unwrap();
+        a.send(&[4]).unwrap();
+        drop(a);
+
+        let mut buf = Vec::new();
+        while b.recv(&mut buf).unwrap() {}
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    // Tests that a channel transport is closed once the other end is
+    // dropped.
+    #[test]
+    fn test_channel_transport() {
+        let (mut a, mut b) = ChannelTransport::pair();
+        a.send(&[1, 2]).unwrap();
+        drop(a);
+
+        let mut buf = Vec::new();
+        assert!(b.recv(&mut buf).unwrap());
+        assert!(!b.recv(&mut buf).unwrap());
+        assert_eq!(buf, [1, 2]);
+        assert!(matches!(b.send(&[3]), Err(WalError::Storage)));
+    }
+}
@@ -0,0 +1,849 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! An optional write-ahead log (WAL) that makes the mutable operations of a
+//! [`crate::nr::NodeReplicated`] instance durable.
+//!
+//! The in-memory [`crate::nr::Log`] is lost when the process dies. With a WAL,
+//! the combiner of a replica also writes the operations it appends to the log
+//! into a [`Segment`] (e.g., a [`FileSegment`]) before the responses are handed
+//! back to the threads. [`crate::nr::NodeReplicated::recover`] replays the
+//! segment to bring a new instance back to the state before the crash.
+//!
+//! Operations are written in the order they appear in the log. For this, a
+//! combiner writes its batch once it reserved entries for it in the log, but
+//! before the operations are added to the entries. Combiners of different
+//! replicas take turns in the order of their entries (the position of the
+//! batch in the log is its ticket), without holding up the appends to the log.
+//!
+//! # Record format
+//! Every operation is stored as one record: its length (4 bytes, little
+//! endian), a checksum of the payload (4 bytes, little endian) and the payload
+//! produced by [`LogSerialize::serialize`]. If the process dies while a record
+//! is written, the incomplete record at the end of the segment is detected and
+//! discarded during recovery.
+//!
+//! If an operation panics while it is applied (see
+//! [`crate::nr::NodeReplicatedError::Poisoned`]), its record is already
+//! written. A marker record is appended for it instead: its length has the
+//! highest bit set and its payload is the index (8 bytes, little endian) of
+//! the record of the operation. Recovery skips marked operations. An operation
+//! that is too large for a record (see [`WalError::TooLarge`]) is replaced by a
+//! marker without payload, so the records still match the positions in the
+//! log.
+//!
+//! The same records are streamed to followers through a
+//! [`crate::nr::transport::LogTransport`]. The records of a batch are queued
+//! while the batch is appended and sent once the combiner appended it, so a
+//! slow transport doesn't hold up the appends of other replicas. Combiners
+//! only wait for the transport (after their append) if [`TRANSPORT_QUEUE`]
+//! batches are queued.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::convert::{TryFrom, TryInto};
+use core::marker::PhantomData;
+use core::num::NonZeroUsize;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::transport::LogTransport;
+use crate::wait::Waiter;
+
+/// Size of the header of a record (length and checksum).
+const HEADER_BYTES: usize = 8;
+
+/// Set in the length of a marker record (see the module documentation).
+const POISONED: u32 = 1 << 31;
+
+/// Maximum number of batches that are queued for the transport.
+pub const TRANSPORT_QUEUE: usize = 1024;
+
+/// Conversion of operations (typically [`crate::nr::Dispatch::WriteOperation`])
+/// from and to bytes so they can be stored in a [`Segment`].
+pub trait LogSerialize: Sized {
+    /// Appends the serialized operation to `buf`.
+    fn serialize(&self, buf: &mut Vec<u8>);
+
+    /// Reconstructs an operation from the bytes written by
+    /// [`LogSerialize::serialize`]. Returns None if `buf` is not a valid
+    /// operation.
+    fn deserialize(buf: &[u8]) -> Option<Self>;
+}
+
+impl LogSerialize for () {
+    fn serialize(&self, _buf: &mut Vec<u8>) {}
+
+    fn deserialize(buf: &[u8]) -> Option<Self> {
+        buf.is_empty().then_some(())
+    }
+}
+
+macro_rules! impl_log_serialize {
+    ($($t:ty),*) => {
+        $(
+            impl LogSerialize for $t {
+                fn serialize(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn deserialize(buf: &[u8]) -> Option<Self> {
+                    Some(<$t>::from_le_bytes(buf.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_log_serialize!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Errors of the write-ahead log.
+#[derive(Debug)]
+pub enum WalError {
+    /// Reading or writing the file of a [`FileSegment`] failed.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    /// Reading or writing a [`Segment`] failed.
+    Storage,
+    /// A complete record in the segment is not a valid operation (see
+    /// [`LogSerialize::deserialize`]).
+    Corrupt,
+    /// An operation was serialized to more than `i32::MAX` bytes. It is
+    /// applied, but not written to the segment.
+    TooLarge,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for WalError {
+    fn from(e: std::io::Error) -> Self {
+        WalError::Io(e)
+    }
+}
+
+/// Append-only storage that holds the records of a write-ahead log.
+pub trait Segment: Send {
+    /// Appends `buf` at the end of the segment.
+    fn append(&mut self, buf: &[u8]) -> Result<(), WalError>;
+
+    /// Makes sure everything appended so far is durable.
+    fn sync(&mut self) -> Result<(), WalError>;
+
+    /// Appends the whole content of the segment to `buf`.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), WalError>;
+
+    /// Shrinks the segment to `len` bytes. Used to discard an incomplete
+    /// record at the end of the segment.
+    fn truncate(&mut self, len: usize) -> Result<(), WalError>;
+}
+
+/// A [`Segment`] that is stored in a file.
+#[cfg(feature = "std")]
+pub struct FileSegment(std::fs::File);
+
+#[cfg(feature = "std")]
+impl FileSegment {
+    /// Opens the segment stored at `path`, the file is created if it doesn't
+    /// exist.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, WalError> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(FileSegment(file))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Segment for FileSegment {
+    fn append(&mut self, buf: &[u8]) -> Result<(), WalError> {
+        use std::io::Write;
+        Ok(self.0.write_all(buf)?)
+    }
+
+    fn sync(&mut self) -> Result<(), WalError> {
+        Ok(self.0.sync_data()?)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), WalError> {
+        use std::io::{Read, Seek, SeekFrom};
+        self.0.seek(SeekFrom::Start(0))?;
+        self.0.read_to_end(buf)?;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), WalError> {
+        self.0.set_len(len as u64)?;
+        Ok(self.0.sync_all()?)
+    }
+}
+
+/// When the write-ahead log makes sure that the written records are durable
+/// (see [`Segment::sync`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync after every batch of operations a combiner writes. Responses are
+    /// only handed out once the operations are durable.
+    Always,
+    /// Sync after every `n`-th batch of operations. Up to `n - 1` batches are
+    /// lost if the system crashes.
+    EveryN(NonZeroUsize),
+    /// Never sync explicitly, the storage (e.g., the OS page cache) decides
+    /// when records become durable.
+    Never,
+}
+
+/// State of the write-ahead log that is protected by [`Wal::lock`].
+struct Inner {
+    segment: Option<Box<dyn Segment>>,
+    /// Records of the batches that are not sent to the transport yet (in log
+    /// order), None if there is no transport.
+    outbox: Option<VecDeque<Vec<u8>>>,
+    /// Number of batches written since the last sync.
+    unsynced: usize,
+}
+
+impl Inner {
+    /// Appends the records in `buf` to the segment and syncs according to
+    /// `policy`.
+    fn persist(&mut self, buf: &[u8], policy: SyncPolicy) -> Result<(), WalError> {
+        let segment = match self.segment.as_mut() {
+            Some(segment) => segment,
+            None => return Ok(()),
+        };
+        segment.append(buf)?;
+
+        self.unsynced += 1;
+        let sync = match policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryN(n) => self.unsynced >= n.get(),
+            SyncPolicy::Never => false,
+        };
+        if sync {
+            segment.sync()?;
+            self.unsynced = 0;
+        }
+
+        Ok(())
+    }
+}
+
+/// The write-ahead log that is shared by all replicas of a
+/// [`crate::nr::NodeReplicated`] instance.
+pub(crate) struct Wal<T> {
+    lock: AtomicBool,
+    inner: UnsafeCell<Inner>,
+    /// Set by the thread that sends the queued records (see [`Wal::flush`]).
+    sending: AtomicBool,
+    /// Receives the same records as the segment, only used by the thread
+    /// that set `sending`.
+    transport: UnsafeCell<Option<Box<dyn LogTransport>>>,
+    /// Position in the [`crate::nr::Log`] of the next batch of operations
+    /// that is written (see [`Wal::append`]).
+    next: AtomicUsize,
+    policy: SyncPolicy,
+    /// Number of operations in the segment when it was opened, the operation
+    /// at position `i` of the [`crate::nr::Log`] has record `base + i`.
+    base: usize,
+    /// Type-erased [`LogSerialize::serialize`] so replicas don't need the
+    /// bound on their operations.
+    serialize: fn(&T, &mut Vec<u8>),
+    /// How threads wait for their turn to write (see [`crate::wait`]).
+    pub(crate) waiter: Waiter,
+    _op: PhantomData<fn(&T)>,
+}
+
+/// The WAL is [`Sync`]. All access to `inner` happens while holding `lock`,
+/// all access to `transport` while `sending` is set.
+unsafe impl<T> Sync for Wal<T> {}
+
+/// Releases the lock of the WAL when dropped (also while unwinding).
+struct WalGuard<'a>(&'a AtomicBool, Waiter);
+
+impl Drop for WalGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+        self.1.notify();
+    }
+}
+
+/// Passes the turn to write to the batch at log position `next` when dropped
+/// (also while unwinding).
+struct Ticket<'a> {
+    turn: &'a AtomicUsize,
+    next: usize,
+    waiter: Waiter,
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.turn.store(self.next, Ordering::Release);
+        self.waiter.notify();
+    }
+}
+
+impl<T: LogSerialize> Wal<T> {
+    /// Opens the write-ahead log stored in `segment` and returns it along with
+    /// the operations it already contains (in log order). Operations that
+    /// panicked are None.
+    ///
+    /// An incomplete record at the end of the segment is removed.
+    pub(crate) fn open(
+        mut segment: Box<dyn Segment>,
+        policy: SyncPolicy,
+    ) -> Result<(Self, Vec<Option<T>>), WalError> {
+        let mut buf = Vec::new();
+        segment.read_to_end(&mut buf)?;
+
+        let mut ops = Vec::new();
+        let mut offset = 0;
+        while let Some((poisoned, payload)) = record_at(&buf, offset) {
+            if poisoned && payload.is_empty() {
+                // The operation was too large to be written.
+                ops.push(None);
+            } else if poisoned {
+                let idx = u64::deserialize(payload).ok_or(WalError::Corrupt)?;
+                let op = usize::try_from(idx)
+                    .ok()
+                    .and_then(|idx| ops.get_mut(idx))
+                    .ok_or(WalError::Corrupt)?;
+                *op = None;
+            } else {
+                ops.push(Some(T::deserialize(payload).ok_or(WalError::Corrupt)?));
+            }
+            offset += HEADER_BYTES + payload.len();
+        }
+        if offset < buf.len() {
+            warn!("Discarding incomplete record at offset {} of WAL", offset);
+            segment.truncate(offset)?;
+        }
+
+        let wal = Wal {
+            lock: AtomicBool::new(false),
+            inner: UnsafeCell::new(Inner {
+                segment: Some(segment),
+                outbox: None,
+                unsynced: 0,
+            }),
+            sending: AtomicBool::new(false),
+            transport: UnsafeCell::new(None),
+            next: AtomicUsize::new(0),
+            policy,
+            base: ops.len(),
+            serialize: T::serialize,
+            waiter: Default::default(),
+            _op: PhantomData,
+        };
+        Ok((wal, ops))
+    }
+
+    /// Creates a log that doesn't store the operations, but streams them to
+    /// a follower through `transport`.
+    pub(crate) fn streaming(transport: Box<dyn LogTransport>) -> Self {
+        Wal {
+            lock: AtomicBool::new(false),
+            inner: UnsafeCell::new(Inner {
+                segment: None,
+                outbox: Some(VecDeque::new()),
+                unsynced: 0,
+            }),
+            sending: AtomicBool::new(false),
+            transport: UnsafeCell::new(Some(transport)),
+            next: AtomicUsize::new(0),
+            policy: SyncPolicy::Never,
+            base: 0,
+            serialize: T::serialize,
+            waiter: Default::default(),
+            _op: PhantomData,
+        }
+    }
+}
+
+impl<T> Wal<T> {
+    /// Appends `ops` to the in-memory log with `append` and writes them to the
+    /// segment.
+    ///
+    /// The operations are serialized into `buf` first. `append` has to call
+    /// the closure it is given with the position of the operations in the
+    /// log before it adds them (see [`crate::nr::Log::append_then`]), their
+    /// records are written then. Records are written in the order of the log,
+    /// so this waits until the batches in front of ours are written.
+    ///
+    /// Once `append` returns, the queued records are sent to the transport
+    /// (see [`Wal::flush`]).
+    ///
+    /// Returns the result of `append` and whether the operations were
+    /// written. If the segment can't be written, the operations are in the
+    /// log but they may not be durable.
+    pub(crate) fn append<R, E>(
+        &self,
+        ops: &[T],
+        buf: &mut Vec<u8>,
+        append: impl FnOnce(&mut dyn FnMut(usize)) -> Result<R, E>,
+    ) -> Result<(R, Result<(), WalError>), E> {
+        let encoded = self.encode(ops, buf);
+        let mut written = Ok(());
+        let r = append(&mut |pos| written = self.write_at(pos, ops.len(), buf));
+        self.flush();
+        Ok((r?, encoded.and(written)))
+    }
+
+    /// Returns the index of the record of the operation at position 0 of the
+    /// [`crate::nr::Log`].
+    pub(crate) fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Appends a marker for the operation with record `idx` which panicked,
+    /// recovery skips it.
+    ///
+    /// The marker is not sent to the transport.
+    pub(crate) fn poisoned(&self, idx: usize) -> Result<(), WalError> {
+        let payload = (idx as u64).to_le_bytes();
+        let mut buf = Vec::with_capacity(HEADER_BYTES + payload.len());
+        buf.extend_from_slice(&(POISONED | payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&checksum(&payload).to_le_bytes());
+        buf.extend_from_slice(&payload);
+
+        let _guard = self.lock();
+        // Safety: We hold the lock.
+        let inner = unsafe { &mut *self.inner.get() };
+        inner.persist(&buf, self.policy)
+    }
+
+    fn lock(&self) -> WalGuard<'_> {
+        let mut iteration = 1;
+        while self
+            .lock
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.waiter.wait(iteration);
+            iteration += 1;
+        }
+        WalGuard(&self.lock, self.waiter)
+    }
+
+    /// Serializes one record for every operation in `ops` into `buf`.
+    ///
+    /// An operation that is too large for a record is replaced by a marker
+    /// without payload (see the module documentation) and
+    /// [`WalError::TooLarge`] is returned.
+    fn encode(&self, ops: &[T], buf: &mut Vec<u8>) -> Result<(), WalError> {
+        let mut encoded = Ok(());
+        buf.clear();
+        for op in ops {
+            let start = buf.len();
+            buf.extend_from_slice(&[0; HEADER_BYTES]);
+            (self.serialize)(op, buf);
+
+            let len = u32::try_from(buf.len() - start - HEADER_BYTES)
+                .ok()
+                .filter(|len| len & POISONED == 0)
+                .unwrap_or_else(|| {
+                    buf.truncate(start + HEADER_BYTES);
+                    encoded = Err(WalError::TooLarge);
+                    POISONED
+                });
+            let checksum = checksum(&buf[start + HEADER_BYTES..]);
+            buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+            buf[start + 4..start + HEADER_BYTES].copy_from_slice(&checksum.to_le_bytes());
+        }
+
+        encoded
+    }
+
+    /// Writes the records in `buf` of the `nops` operations at position `pos`
+    /// of the log and syncs according to the [`SyncPolicy`]. The records are
+    /// queued for the transport as well.
+    ///
+    /// Waits until the records of the operations before `pos` are written.
+    fn write_at(&self, pos: usize, nops: usize, buf: &[u8]) -> Result<(), WalError> {
+        if nops == 0 {
+            return Ok(());
+        }
+
+        let mut iteration = 0;
+        while self.next.load(Ordering::Acquire) != pos {
+            self.waiter.wait(iteration);
+            iteration += 1;
+        }
+        let _ticket = Ticket {
+            turn: &self.next,
+            next: pos + nops,
+            waiter: self.waiter,
+        };
+
+        let _guard = self.lock();
+        // Safety: We hold the lock.
+        let inner = unsafe { &mut *self.inner.get() };
+        if let Some(outbox) = inner.outbox.as_mut() {
+            outbox.push_back(buf.to_vec());
+        }
+
+        inner.persist(buf, self.policy)
+    }
+
+    /// Sends the queued records to the transport, unless another thread is
+    /// sending them already. In that case, this waits until fewer than
+    /// [`TRANSPORT_QUEUE`] batches are queued.
+    ///
+    /// Only one thread sends at a time, so the records are sent in the order
+    /// they were queued. A transport that fails is dropped, followers must
+    /// not hold up the instance they follow.
+    fn flush(&self) {
+        let mut iteration = 0;
+        loop {
+            if self
+                .sending
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                // The sender picks up our records as well.
+                if self.queued() < TRANSPORT_QUEUE {
+                    return;
+                }
+                self.waiter.wait(iteration);
+                iteration += 1;
+                continue;
+            }
+
+            // Safety: We set `sending`.
+            let transport = unsafe { &mut *self.transport.get() };
+            loop {
+                let batch = {
+                    let _guard = self.lock();
+                    // Safety: We hold the lock.
+                    let inner = unsafe { &mut *self.inner.get() };
+                    match inner.outbox.as_mut() {
+                        Some(outbox) => outbox.pop_front(),
+                        None => {
+                            transport.take();
+                            None
+                        }
+                    }
+                };
+                let batch = match batch {
+                    Some(batch) => batch,
+                    None => break,
+                };
+                if let Some(Err(e)) = transport.as_mut().map(|t| t.send(&batch)) {
+                    warn!("Dropping log transport after it failed: {:?}", e);
+                    transport.take();
+                    let _guard = self.lock();
+                    // Safety: We hold the lock.
+                    unsafe { (*self.inner.get()).outbox = None };
+                }
+            }
+            self.sending.store(false, Ordering::Release);
+            self.waiter.notify();
+
+            // Records that were queued after we found the queue empty, but
+            // before we cleared `sending`, would be left behind.
+            if self.queued() == 0 {
+                return;
+            }
+        }
+    }
+
+    /// Returns the number of batches queued for the transport.
+    fn queued(&self) -> usize {
+        let _guard = self.lock();
+        // Safety: We hold the lock.
+        let inner = unsafe { &*self.inner.get() };
+        inner.outbox.as_ref().map_or(0, VecDeque::len)
+    }
+}
+
+/// Decodes the complete records at the start of `buf` (e.g., received through
+/// a [`LogTransport`]) into `ops` and returns the number of bytes they take
+/// up. The rest of `buf` is the beginning of a record that is incomplete.
+pub(crate) fn decode_records<T: LogSerialize>(
+    buf: &[u8],
+    ops: &mut Vec<T>,
+) -> Result<usize, WalError> {
+    let mut offset = 0;
+    while let Some(header) = buf.get(offset..offset + HEADER_BYTES) {
+        let len = (u32::from_le_bytes(header[..4].try_into().unwrap()) & !POISONED) as usize;
+        if buf.len() - offset - HEADER_BYTES < len {
+            break;
+        }
+        // Unlike in a segment, a complete record can't be torn.
+        let (poisoned, payload) = record_at(buf, offset).ok_or(WalError::Corrupt)?;
+        if !poisoned {
+            ops.push(T::deserialize(payload).ok_or(WalError::Corrupt)?);
+        }
+        offset += HEADER_BYTES + len;
+    }
+    Ok(offset)
+}
+
+/// Returns whether the record that starts at `offset` in `buf` is a marker and
+/// its payload, or None if there is no complete and valid record.
+fn record_at(buf: &[u8], offset: usize) -> Option<(bool, &[u8])> {
+    let header = buf.get(offset..offset + HEADER_BYTES)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let checksum_stored = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let start = offset + HEADER_BYTES;
+    let payload = buf.get(start..start.checked_add((len & !POISONED) as usize)?)?;
+    (checksum(payload) == checksum_stored).then_some((len & POISONED != 0, payload))
+}
+
+/// FNV-1a hash of `bytes`.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    extern crate std;
+
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// A segment that lives in memory and survives dropping the WAL, to
+    /// simulate a crash.
+    #[derive(Clone, Default)]
+    pub(crate) struct MemSegment(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl Segment for MemSegment {
+        fn append(&mut self, buf: &[u8]) -> Result<(), WalError> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(())
+        }
+
+        fn sync(&mut self) -> Result<(), WalError> {
+            Ok(())
+        }
+
+        fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<(), WalError> {
+            buf.extend_from_slice(&self.0.lock().unwrap());
+            Ok(())
+        }
+
+        fn truncate(&mut self, len: usize) -> Result<(), WalError> {
+            self.0.lock().unwrap().truncate(len);
+            Ok(())
+        }
+    }
+
+    /// Writes `ops` to `wal` as if they were appended to the log at `pos`.
+    fn append_at(wal: &Wal<u64>, pos: usize, ops: &[u64]) -> Result<(), WalError> {
+        let append = |reserved: &mut dyn FnMut(usize)| {
+            reserved(pos);
+            Ok::<_, ()>(())
+        };
+        wal.append(ops, &mut Vec::new(), append).unwrap().1
+    }
+
+    // Tests that operations written to the WAL are returned when it is opened
+    // again.
+    #[test]
+    fn test_wal_reopen() {
+        let seg = MemSegment::default();
+        let (wal, ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Always).unwrap();
+        assert!(ops.is_empty());
+
+        append_at(&wal, 0, &[1, 2]).unwrap();
+        // Nothing is written if we fail to append to the log.
+        let failed = wal.append(&[3], &mut Vec::new(), |_reserved| Err::<(), _>(()));
+        assert!(matches!(failed, Err(())));
+        append_at(&wal, 2, &[4]).unwrap();
+        drop(wal);
+
+        let (_wal, ops) = Wal::<u64>::open(Box::new(seg), SyncPolicy::Always).unwrap();
+        assert_eq!(ops, [Some(1), Some(2), Some(4)]);
+    }
+
+    // Tests that batches are written in the order of their positions in the
+    // log, no matter in which order they are appended.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_wal_order() {
+        let seg = MemSegment::default();
+        let (wal, _ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Never).unwrap();
+        std::thread::scope(|s| {
+            let later = s.spawn(|| append_at(&wal, 2, &[3]));
+            append_at(&wal, 0, &[1, 2]).unwrap();
+            later.join().unwrap().unwrap();
+        });
+        drop(wal);
+
+        let (_wal, ops) = Wal::<u64>::open(Box::new(seg), SyncPolicy::Never).unwrap();
+        assert_eq!(ops, [Some(1), Some(2), Some(3)]);
+    }
+
+    // Tests that an incomplete record at the end of the segment is discarded.
+    #[test]
+    fn test_wal_torn_record() {
+        let seg = MemSegment::default();
+        let (wal, _ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Never).unwrap();
+        append_at(&wal, 0, &[1, 2]).unwrap();
+        drop(wal);
+
+        let len = seg.0.lock().unwrap().len();
+        seg.0.lock().unwrap().truncate(len - 3);
+
+        let (wal, ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Never).unwrap();
+        assert_eq!(ops, [Some(1)]);
+        assert_eq!(seg.0.lock().unwrap().len(), HEADER_BYTES + 8);
+        append_at(&wal, 0, &[3]).unwrap();
+        drop(wal);
+
+        let (_wal, ops) = Wal::<u64>::open(Box::new(seg), SyncPolicy::Never).unwrap();
+        assert_eq!(ops, [Some(1), Some(3)]);
+    }
+
+    // Tests that operations with a marker are skipped when the WAL is opened
+    // again and that markers are not decoded as operations.
+    #[test]
+    fn test_wal_poisoned() {
+        let seg = MemSegment::default();
+        let (wal, _ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Always).unwrap();
+        append_at(&wal, 0, &[1, 2, 3]).unwrap();
+        wal.poisoned(1).unwrap();
+        drop(wal);
+
+        let (wal, ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Always).unwrap();
+        assert_eq!(ops, [Some(1), None, Some(3)]);
+        assert_eq!(wal.base(), 3);
+        append_at(&wal, 0, &[4]).unwrap();
+        wal.poisoned(wal.base()).unwrap();
+        drop(wal);
+
+        let (_wal, ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Always).unwrap();
+        assert_eq!(ops, [Some(1), None, Some(3), None]);
+
+        let bytes = seg.0.lock().unwrap().clone();
+        let mut ops = Vec::new();
+        assert_eq!(
+            decode_records::<u64>(&bytes, &mut ops).unwrap(),
+            bytes.len()
+        );
+        assert_eq!(ops, [1, 2, 3, 4]);
+
+        // A marker for an operation that doesn't exist is corrupt.
+        let (wal, _ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Always).unwrap();
+        wal.poisoned(4).unwrap();
+        drop(wal);
+        assert!(matches!(
+            Wal::<u64>::open(Box::new(seg), SyncPolicy::Always),
+            Err(WalError::Corrupt)
+        ));
+    }
+
+    /// A transport that keeps what it is sent and notes when it is dropped.
+    struct MemTransport(Arc<Mutex<Vec<u8>>>, Arc<AtomicBool>);
+
+    impl LogTransport for MemTransport {
+        fn send(&mut self, buf: &[u8]) -> Result<(), WalError> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(())
+        }
+
+        fn recv(&mut self, _buf: &mut Vec<u8>) -> Result<bool, WalError> {
+            Ok(false)
+        }
+    }
+
+    impl Drop for MemTransport {
+        fn drop(&mut self) {
+            self.1.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// A transport whose connection is gone.
+    struct FailingTransport;
+
+    impl LogTransport for FailingTransport {
+        fn send(&mut self, _buf: &[u8]) -> Result<(), WalError> {
+            Err(WalError::Storage)
+        }
+
+        fn recv(&mut self, _buf: &mut Vec<u8>) -> Result<bool, WalError> {
+            Ok(false)
+        }
+    }
+
+    // Tests that batches queued while another thread sends are sent in log
+    // order and that a transport which fails is dropped.
+    #[test]
+    fn test_wal_transport_queue() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let dropped = Arc::new(AtomicBool::new(false));
+        let transport = MemTransport(sent.clone(), dropped.clone());
+        let wal = Wal::<u64>::streaming(Box::new(transport));
+        append_at(&wal, 0, &[1, 2]).unwrap();
+
+        // Another thread is sending.
+        wal.sending.store(true, Ordering::Relaxed);
+        append_at(&wal, 2, &[3]).unwrap();
+        append_at(&wal, 3, &[4]).unwrap();
+        assert_eq!(wal.queued(), 2);
+        wal.sending.store(false, Ordering::Relaxed);
+        append_at(&wal, 4, &[5]).unwrap();
+        assert_eq!(wal.queued(), 0);
+
+        let mut ops = Vec::new();
+        decode_records::<u64>(&sent.lock().unwrap(), &mut ops).unwrap();
+        assert_eq!(ops, [1, 2, 3, 4, 5]);
+        assert!(!dropped.load(Ordering::Relaxed));
+
+        // Safety: Nobody is sending.
+        unsafe { *wal.transport.get() = Some(Box::new(FailingTransport)) };
+        assert!(dropped.load(Ordering::Relaxed));
+        append_at(&wal, 5, &[6]).unwrap();
+        append_at(&wal, 6, &[7]).unwrap();
+        assert!(unsafe { (*wal.transport.get()).is_none() });
+        assert_eq!(wal.queued(), 0);
+    }
+
+    // Tests that records are decoded no matter how the bytes are split up.
+    #[test]
+    fn test_wal_decode_records() {
+        let seg = MemSegment::default();
+        let (wal, _ops) = Wal::<u64>::open(Box::new(seg.clone()), SyncPolicy::Never).unwrap();
+        append_at(&wal, 0, &[1, 2, 3]).unwrap();
+        let bytes = seg.0.lock().unwrap().clone();
+
+        for split in 0..bytes.len() {
+            let mut ops = Vec::new();
+            let used = decode_records::<u64>(&bytes[..split], &mut ops).unwrap();
+            assert_eq!(used, ops.len() * (HEADER_BYTES + 8));
+
+            let mut rest = bytes[used..].to_vec();
+            assert_eq!(decode_records(&rest, &mut ops).unwrap(), rest.len());
+            assert_eq!(ops, [1, 2, 3]);
+
+            // A complete record with a wrong checksum is corrupt.
+            rest[HEADER_BYTES] ^= 1;
+            assert!(matches!(
+                decode_records::<u64>(&rest, &mut ops),
+                Err(WalError::Corrupt)
+            ));
+        }
+    }
+
+    // Tests that the WAL can be stored in a file.
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_wal_file_segment() {
+        let path = std::env::temp_dir().join(std::format!("nr-wal-{}", std::process::id()));
+        let _ignore = std::fs::remove_file(&path);
+
+        let seg = FileSegment::open(&path).unwrap();
+        let (wal, ops) = Wal::<u64>::open(Box::new(seg), SyncPolicy::Always).unwrap();
+        assert!(ops.is_empty());
+        append_at(&wal, 0, &[1, 2]).unwrap();
+        drop(wal);
+
+        let seg = FileSegment::open(&path).unwrap();
+        let (_wal, ops) = Wal::<u64>::open(Box::new(seg), SyncPolicy::Always).unwrap();
+        assert_eq!(ops, [Some(1), Some(2)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
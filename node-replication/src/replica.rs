@@ -5,7 +5,10 @@
 
 use static_assertions::const_assert;
 
-/// The maximum number of threads that can be registered with a replica.
+use crate::context::MAX_PENDING_OPS;
+
+/// The default maximum number of threads that can be registered with a
+/// replica (see [`ReplicaConfig`]).
 ///
 /// If more than this number of threads try to register, the
 /// [`crate::nr::Replica::register()`] or [`crate::cnr::Replica::register()`]
@@ -17,6 +20,57 @@ pub const MAX_THREADS_PER_REPLICA: usize = 2;
 // MAX_THREADS_PER_REPLICA must be a power of two
 const_assert!(MAX_THREADS_PER_REPLICA.is_power_of_two());
 
+/// Sizes the per-thread state of a replica.
+///
+/// Every replica preallocates a context for `max_threads` threads with
+/// `pending_ops` slots each. The [`crate::log::Log`] the replica appends to
+/// has to be created with (at least) the same configuration, since it needs
+/// to keep enough space for the largest batch a replica can append (see
+/// [`crate::log::Log::new_with_config`]).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReplicaConfig {
+    /// The maximum number of threads that can be registered with a replica.
+    pub max_threads: usize,
+    /// The maximum number of operations a thread can have pending, i.e., the
+    /// number of slots in its context. Must be a power of two.
+    pub pending_ops: usize,
+}
+
+impl Default for ReplicaConfig {
+    /// Uses [`MAX_THREADS_PER_REPLICA`] and
+    /// [`crate::context::MAX_PENDING_OPS`].
+    fn default() -> Self {
+        ReplicaConfig {
+            max_threads: MAX_THREADS_PER_REPLICA,
+            pending_ops: MAX_PENDING_OPS,
+        }
+    }
+}
+
+impl ReplicaConfig {
+    /// The largest number of operations a replica appends to the log at once
+    /// (every registered thread has a full context).
+    pub const fn max_batch(&self) -> usize {
+        self.max_threads * self.pending_ops
+    }
+
+    /// Returns why the configuration is unusable (if it is).
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_threads == 0 {
+            return Err(ConfigError("Replicas need at least one thread"));
+        }
+        if !self.pending_ops.is_power_of_two() {
+            return Err(ConfigError("pending_ops must be a power of two"));
+        }
+        Ok(())
+    }
+}
+
+/// A [`ReplicaConfig`] is unusable, the string says why (see
+/// [`ReplicaConfig::validate`]).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ConfigError(pub &'static str);
+
 /// Unique identifier for the given replicas.
 ///
 /// It's unique within a NR/CNR instance. It makes sense to be e.g., the same as
@@ -38,8 +92,13 @@ pub type ThreadIdx = usize;
 /// A token handed out to threads that replicas with replicas.
 ///
 /// It is a bug to supply this token to another replica object than the one that
-/// issued it. This would ideally be a runtime check which leads to a panic in
-/// the future.
+/// issued it. [`crate::nr::NodeReplicated`] checks this at runtime (see
+/// [`crate::nr::ThreadToken`]), replicas that are used directly don't.
+///
+/// Besides the thread index, the token holds the generation of the thread
+/// slot it was issued for. [`crate::nr`] replicas bump the generation when a
+/// thread unregisters, so a token is no longer accepted once its slot is
+/// handed out to another thread.
 ///
 /// # Implementation detail on types
 /// Ideally this would be an affine type (not Clone/Copy) for max. type safety
@@ -47,7 +106,7 @@ pub type ThreadIdx = usize;
 /// [`crate::nr::Replica::execute_mut`]. However it feels like this would hurt
 /// API ergonomics a lot.
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub struct ReplicaToken(pub(crate) ThreadIdx);
+pub struct ReplicaToken(pub(crate) ThreadIdx, pub(crate) usize);
 
 /// Make it harder to accidentially use the same ReplicaToken on multiple
 /// threads.
@@ -66,7 +125,7 @@ impl ReplicaToken {
     /// If `pub(test)` is ever supported, we should do that instead.
     #[doc(hidden)]
     pub unsafe fn new(tid: ThreadIdx) -> Self {
-        ReplicaToken(tid)
+        ReplicaToken(tid, 0)
     }
 
     /// Get the (replica specific) thread identifier for this particular token.
@@ -75,4 +134,9 @@ impl ReplicaToken {
     pub fn tid(&self) -> ThreadIdx {
         self.0
     }
+
+    /// Get the generation of the thread slot this token was issued for.
+    pub(crate) fn generation(&self) -> usize {
+        self.1
+    }
 }
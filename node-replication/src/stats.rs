@@ -0,0 +1,322 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Runtime statistics of replicas and the shared log.
+//!
+//! The counters are only maintained if the crate is compiled with the `stats`
+//! feature. Otherwise they are zero-sized and updating them compiles to
+//! nothing.
+//!
+//! Snapshots of the counters can be obtained with
+//! [`crate::nr::NodeReplicated::stats()`] and [`crate::cnr::Replica::stats()`].
+//! The module is only public with the `stats` feature.
+
+#[cfg(feature = "stats")]
+use alloc::vec::Vec;
+#[cfg(feature = "stats")]
+use core::ptr;
+#[cfg(feature = "stats")]
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+#[cfg(feature = "stats")]
+use core::time::Duration;
+
+/// Number of buckets in [`CombinerStats::batch_sizes`].
+///
+/// Bucket `i` counts the combiner rounds that applied between `2^i` and
+/// `2^(i+1) - 1` operations, the last bucket also counts all larger rounds.
+pub const BATCH_BUCKETS: usize = 16;
+
+/// A clock that returns the time that passed since some fixed point in time.
+#[cfg(feature = "stats")]
+pub type ClockFn = fn() -> Duration;
+
+/// The clock installed with [`set_clock`] (null if none was installed).
+#[cfg(feature = "stats")]
+static CLOCK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs the clock used to measure how long threads wait (see
+/// [`LogStats::gc_wait`]).
+///
+/// With the `std` feature, waits are measured with `std::time::Instant`
+/// unless a clock is installed. Without `std` there is no clock and waits
+/// are not measured until one is installed.
+#[cfg(feature = "stats")]
+pub fn set_clock(clock: ClockFn) {
+    CLOCK.store(clock as *mut (), Ordering::Release);
+}
+
+/// Reads the clock installed with [`set_clock`].
+#[cfg(feature = "stats")]
+fn installed_clock() -> Option<Duration> {
+    let clock = CLOCK.load(Ordering::Acquire);
+    if clock.is_null() {
+        None
+    } else {
+        // Safety: `CLOCK` is only ever set to a `ClockFn` by `set_clock`.
+        let clock: ClockFn = unsafe { core::mem::transmute(clock) };
+        Some(clock())
+    }
+}
+
+/// An event counter that disappears without the `stats` feature.
+#[derive(Default)]
+pub(crate) struct Counter(#[cfg(feature = "stats")] AtomicU64);
+
+impl Counter {
+    /// Adds `n` to the counter.
+    #[inline(always)]
+    pub(crate) fn add(&self, n: u64) {
+        #[cfg(feature = "stats")]
+        self.0.fetch_add(n, Ordering::Relaxed);
+        #[cfg(not(feature = "stats"))]
+        let _ = n;
+    }
+
+    /// Increments the counter by one.
+    #[inline(always)]
+    pub(crate) fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Returns the current value of the counter.
+    #[cfg(feature = "stats")]
+    pub(crate) fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Accumulates the time threads spent waiting, disappears without the
+/// `stats` feature.
+#[derive(Default)]
+pub(crate) struct Timer(Counter);
+
+impl Timer {
+    /// Starts measuring a wait, which ends when the returned [`Stopwatch`] is
+    /// dropped.
+    #[inline(always)]
+    pub(crate) fn start(&self) -> Stopwatch<'_> {
+        Stopwatch {
+            #[cfg(feature = "stats")]
+            timer: self,
+            #[cfg(feature = "stats")]
+            start: Start::now(),
+            #[cfg(not(feature = "stats"))]
+            _timer: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns the accumulated time.
+    #[cfg(feature = "stats")]
+    pub(crate) fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.get())
+    }
+}
+
+/// The time a [`Stopwatch`] was started at.
+#[cfg(feature = "stats")]
+enum Start {
+    /// Time of the clock installed with [`set_clock`].
+    Clock(Duration),
+    #[cfg(feature = "std")]
+    Instant(std::time::Instant),
+    /// There is no clock, the wait is not measured.
+    #[cfg(not(feature = "std"))]
+    Unknown,
+}
+
+#[cfg(feature = "stats")]
+impl Start {
+    fn now() -> Start {
+        match installed_clock() {
+            Some(now) => Start::Clock(now),
+            #[cfg(feature = "std")]
+            None => Start::Instant(std::time::Instant::now()),
+            #[cfg(not(feature = "std"))]
+            None => Start::Unknown,
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self {
+            Start::Clock(start) => installed_clock()
+                .map(|now| now.saturating_sub(*start))
+                .unwrap_or_default(),
+            #[cfg(feature = "std")]
+            Start::Instant(start) => start.elapsed(),
+            #[cfg(not(feature = "std"))]
+            Start::Unknown => Duration::ZERO,
+        }
+    }
+}
+
+/// Measures a wait for a [`Timer`] (see [`Timer::start`]).
+pub(crate) struct Stopwatch<'a> {
+    #[cfg(feature = "stats")]
+    timer: &'a Timer,
+    #[cfg(feature = "stats")]
+    start: Start,
+    #[cfg(not(feature = "stats"))]
+    _timer: core::marker::PhantomData<&'a Timer>,
+}
+
+#[cfg(feature = "stats")]
+impl Drop for Stopwatch<'_> {
+    fn drop(&mut self) {
+        let nanos = self.start.elapsed().as_nanos();
+        self.timer
+            .0
+            .add(core::cmp::min(nanos, u64::MAX as u128) as u64);
+    }
+}
+
+/// Counters maintained by the combiner of a replica.
+#[derive(Default)]
+pub(crate) struct CombinerCounters {
+    /// Number of combiner rounds.
+    pub(crate) combines: Counter,
+    /// Number of operations appended by the combiner.
+    pub(crate) ops: Counter,
+    /// Histogram of operations appended per combiner round.
+    pub(crate) batch_sizes: [Counter; BATCH_BUCKETS],
+}
+
+impl CombinerCounters {
+    /// Records a combiner round that appended `nops` operations.
+    #[inline(always)]
+    pub(crate) fn combined(&self, nops: usize) {
+        self.combines.inc();
+        if nops > 0 {
+            self.ops.add(nops as u64);
+            let bucket = (usize::BITS - 1 - nops.leading_zeros()) as usize;
+            self.batch_sizes[core::cmp::min(bucket, BATCH_BUCKETS - 1)].inc();
+        }
+    }
+
+    /// Takes a snapshot of the counters.
+    #[cfg(feature = "stats")]
+    pub(crate) fn snapshot(&self) -> CombinerStats {
+        let mut batch_sizes = [0; BATCH_BUCKETS];
+        for (b, c) in batch_sizes.iter_mut().zip(self.batch_sizes.iter()) {
+            *b = c.get();
+        }
+
+        CombinerStats {
+            combines: self.combines.get(),
+            ops: self.ops.get(),
+            batch_sizes,
+        }
+    }
+}
+
+/// Counters maintained by a [`crate::nr`] replica.
+#[derive(Default)]
+pub(crate) struct ReplicaCounters {
+    /// Counters of the combiner.
+    pub(crate) combiner: CombinerCounters,
+    /// Number of times the combiner found no space on the log.
+    pub(crate) no_log_space: Counter,
+    /// Number of times the combiner could not garbage collect the log.
+    pub(crate) gc_failed: Counter,
+    /// Number of times a thread of another replica synced this replica.
+    pub(crate) remote_syncs: Counter,
+    /// Number of times the replica was rebuilt after it was evicted.
+    pub(crate) rebuilds: Counter,
+}
+
+impl ReplicaCounters {
+    /// Records a combiner round that appended `nops` operations.
+    #[inline(always)]
+    pub(crate) fn combined(&self, nops: usize) {
+        self.combiner.combined(nops);
+    }
+
+    /// Takes a snapshot of the counters.
+    #[cfg(feature = "stats")]
+    pub(crate) fn snapshot(&self) -> ReplicaStats {
+        ReplicaStats {
+            combiner: self.combiner.snapshot(),
+            no_log_space: self.no_log_space.get(),
+            gc_failed: self.gc_failed.get(),
+            remote_syncs: self.remote_syncs.get(),
+            rebuilds: self.rebuilds.get(),
+        }
+    }
+}
+
+/// Counters maintained by the shared log.
+#[derive(Default)]
+pub(crate) struct LogCounters {
+    /// Time appends spent waiting for garbage collection.
+    pub(crate) gc_wait: Timer,
+}
+
+/// A snapshot of the counters of the combiner of a replica.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CombinerStats {
+    /// Number of combiner rounds executed by the replica.
+    pub combines: u64,
+    /// Number of mutable operations the combiner appended to the log(s).
+    pub ops: u64,
+    /// Histogram of the operations appended per combiner round (see
+    /// [`BATCH_BUCKETS`]).
+    pub batch_sizes: [u64; BATCH_BUCKETS],
+}
+
+/// A snapshot of the counters of a [`crate::nr`] replica.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReplicaStats {
+    /// Statistics of the combiner of the replica.
+    pub combiner: CombinerStats,
+    /// How often the combiner failed to append because the log was full.
+    pub no_log_space: u64,
+    /// How often the combiner appended but couldn't garbage collect the log.
+    pub gc_failed: u64,
+    /// How often threads of other replicas synced this replica to make
+    /// progress on the log.
+    pub remote_syncs: u64,
+    /// How often the replica was rebuilt from another replica after it lagged
+    /// too far behind and was evicted (see
+    /// [`crate::nr::NodeReplicatedBuilder::evict_after`]).
+    pub rebuilds: u64,
+}
+
+/// A snapshot of the counters of a shared log.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LogStats {
+    /// Completed tail of the log.
+    pub ctail: usize,
+    /// Time that appends spent waiting for garbage collection (see
+    /// [`set_clock`] for how it is measured).
+    pub gc_wait: Duration,
+    /// The number of entries each registered replica lags behind `ctail`.
+    ///
+    /// Indexed by the slot the replica occupies on the log, `None` for slots
+    /// of retired replicas.
+    pub lag: Vec<Option<usize>>,
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod test {
+    use super::*;
+
+    // Tests that combiner rounds end up in the right batch size bucket.
+    #[test]
+    fn test_batch_sizes() {
+        let c = CombinerCounters::default();
+        for nops in [0, 1, 2, 3, 4, 7, 8, usize::MAX] {
+            c.combined(nops);
+        }
+
+        let s = c.snapshot();
+        assert_eq!(s.combines, 8);
+        assert_eq!(s.batch_sizes[0], 1);
+        assert_eq!(s.batch_sizes[1], 2);
+        assert_eq!(s.batch_sizes[2], 2);
+        assert_eq!(s.batch_sizes[3], 1);
+        assert_eq!(s.batch_sizes[BATCH_BUCKETS - 1], 1);
+        assert_eq!(s.batch_sizes.iter().sum::<u64>(), 7);
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Strategies for threads that wait on a replica or the log.
+//!
+//! NR assumes every thread has a core to itself: Threads busy-wait for
+//! responses in their context, for the combiner lock, the [`crate::nr::rwlock`]
+//! and for garbage collection of the log. If there are more threads than
+//! cores, the spinning threads take CPU time away from the threads they wait
+//! for. A different [`WaitStrategy`] can be installed with [`set_strategy`]
+//! for such deployments.
+//!
+//! The strategy is global to the process and used by all [`crate::nr`]
+//! instances that weren't given their own strategy with
+//! [`crate::nr::NodeReplicatedBuilder::wait_strategy`]. [`crate::cnr`] uses it
+//! while a thread unregisters (see [`crate::cnr::Replica::unregister`]). It
+//! can only be installed once.
+//!
+//! # Example
+//!
+//! ```
+//! use node_replication::wait::{set_strategy, WaitStrategy};
+//!
+//! // Gives up the CPU right away instead of spinning.
+//! struct Yield;
+//!
+//! impl WaitStrategy for Yield {
+//!     fn wait(&self, _iteration: usize) {
+//!         std::thread::yield_now();
+//!     }
+//! }
+//!
+//! assert!(set_strategy(&Yield).is_ok());
+//! ```
+//!
+//! With the `std` feature, [`SpinThenYield`] and [`Park`] are provided.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "std")]
+use core::sync::atomic::AtomicUsize;
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::sync::{Condvar, Mutex};
+
+/// Decides what a thread does while it waits for another thread.
+pub trait WaitStrategy: Sync {
+    /// Called by a waiting thread each time the condition it waits for does
+    /// not hold (yet). `iteration` counts the unsuccessful checks of the
+    /// current wait, starting at 0.
+    fn wait(&self, iteration: usize);
+
+    /// Called after a thread did something other threads might wait for
+    /// (released a lock, published responses or freed space in the log).
+    fn notify(&self) {}
+}
+
+/// Busy-waits (the default).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Spin;
+
+impl WaitStrategy for Spin {
+    #[inline(always)]
+    fn wait(&self, _iteration: usize) {
+        spin_loop();
+    }
+}
+
+/// Busy-waits for `spins` iterations, then yields the CPU in every further
+/// iteration.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct SpinThenYield {
+    /// Number of iterations to busy-wait before yielding.
+    pub spins: usize,
+}
+
+#[cfg(feature = "std")]
+impl WaitStrategy for SpinThenYield {
+    fn wait(&self, iteration: usize) {
+        if iteration < self.spins {
+            spin_loop();
+        } else {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Busy-waits for `spins` iterations, then parks the thread until another
+/// thread notifies (see [`WaitStrategy::notify`]).
+///
+/// Waiting threads can't register for a specific event, so a notification
+/// wakes all parked threads and they check their condition again. A thread
+/// that starts to park just after the notification misses it, which is why
+/// parked threads wake up after `timeout` at the latest.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Park {
+    spins: usize,
+    timeout: Duration,
+    parked: AtomicUsize,
+    lock: Mutex<()>,
+    cv: Condvar,
+}
+
+#[cfg(feature = "std")]
+impl Park {
+    /// Creates a strategy that parks threads after `spins` iterations for at
+    /// most `timeout`.
+    pub const fn new(spins: usize, timeout: Duration) -> Self {
+        Park {
+            spins,
+            timeout,
+            parked: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            cv: Condvar::new(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl WaitStrategy for Park {
+    fn wait(&self, iteration: usize) {
+        if iteration < self.spins {
+            spin_loop();
+            return;
+        }
+
+        let guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.parked.fetch_add(1, Ordering::SeqCst);
+        let _r = self.cv.wait_timeout(guard, self.timeout);
+        self.parked.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn notify(&self) {
+        if self.parked.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+            self.cv.notify_all();
+        }
+    }
+}
+
+/// No strategy was installed, threads use [`Spin`].
+const UNSET: u8 = 0;
+/// [`set_strategy`] is writing [`STRATEGY`].
+const INSTALLING: u8 = 1;
+/// [`STRATEGY`] holds the installed strategy.
+const INSTALLED: u8 = 2;
+
+/// Whether a strategy is installed in [`STRATEGY`].
+static STATE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// The installed strategy, only read once [`STATE`] is [`INSTALLED`].
+static STRATEGY: Installed = Installed(UnsafeCell::new(None));
+
+/// A cell that is written once by [`set_strategy`].
+struct Installed(UnsafeCell<Option<&'static dyn WaitStrategy>>);
+
+// Safety: The cell is written once (before `STATE` becomes `INSTALLED`) and
+// only read afterwards.
+unsafe impl Sync for Installed {}
+
+/// Installs `strategy` for all threads of the process.
+///
+/// Should be called before threads start to use NR. A strategy can only be
+/// installed once, if one is installed already `strategy` is returned as an
+/// error.
+pub fn set_strategy(strategy: &'static dyn WaitStrategy) -> Result<(), &'static dyn WaitStrategy> {
+    if STATE
+        .compare_exchange(UNSET, INSTALLING, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        return Err(strategy);
+    }
+
+    // Safety: We won the race to `INSTALLING`, nobody else writes the cell
+    // and it isn't read before we set `INSTALLED`.
+    unsafe { *STRATEGY.0.get() = Some(strategy) };
+    STATE.store(INSTALLED, Ordering::Release);
+    Ok(())
+}
+
+/// Returns the installed strategy, None if threads spin.
+#[inline(always)]
+fn strategy() -> Option<&'static dyn WaitStrategy> {
+    if STATE.load(Ordering::Acquire) == INSTALLED {
+        // Safety: The cell isn't written anymore once it is `INSTALLED`.
+        unsafe { *STRATEGY.0.get() }
+    } else {
+        None
+    }
+}
+
+/// Waits according to the installed strategy (see [`WaitStrategy::wait`]).
+#[inline(always)]
+pub(crate) fn wait(iteration: usize) {
+    match strategy() {
+        Some(strategy) => strategy.wait(iteration),
+        None => spin_loop(),
+    }
+}
+
+/// Notifies waiting threads according to the installed strategy (see
+/// [`WaitStrategy::notify`]).
+#[inline(always)]
+pub(crate) fn notify() {
+    if let Some(strategy) = strategy() {
+        strategy.notify();
+    }
+}
+
+/// The strategy of one NR instance, falls back to the installed strategy if it
+/// has none.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Waiter(Option<&'static dyn WaitStrategy>);
+
+impl Waiter {
+    /// A waiter that uses `strategy` instead of the installed one.
+    pub(crate) const fn new(strategy: &'static dyn WaitStrategy) -> Self {
+        Waiter(Some(strategy))
+    }
+
+    /// Waits according to the strategy (see [`WaitStrategy::wait`]).
+    #[inline(always)]
+    pub(crate) fn wait(&self, iteration: usize) {
+        match self.0 {
+            Some(strategy) => strategy.wait(iteration),
+            None => wait(iteration),
+        }
+    }
+
+    /// Notifies waiting threads according to the strategy (see
+    /// [`WaitStrategy::notify`]).
+    #[inline(always)]
+    pub(crate) fn notify(&self) {
+        match self.0 {
+            Some(strategy) => strategy.notify(),
+            None => notify(),
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Tests a follower that mirrors a node-replication instance over a Unix
+//! domain socket.
+#![cfg(all(feature = "std", unix))]
+#![feature(generic_associated_types)]
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::os::unix::net::UnixListener;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use node_replication::nr::transport::UnixTransport;
+use node_replication::nr::wal::LogSerialize;
+use node_replication::nr::{Dispatch, NodeReplicated};
+
+#[derive(Default)]
+struct Map(HashMap<u64, u64>);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Put(u64, u64);
+
+impl LogSerialize for Put {
+    fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0.to_le_bytes());
+        buf.extend_from_slice(&self.1.to_le_bytes());
+    }
+
+    fn deserialize(buf: &[u8]) -> Option<Self> {
+        if buf.len() != 16 {
+            return None;
+        }
+        Some(Put(
+            u64::from_le_bytes(buf[..8].try_into().ok()?),
+            u64::from_le_bytes(buf[8..].try_into().ok()?),
+        ))
+    }
+}
+
+impl Dispatch for Map {
+    type ReadOperation<'rop> = u64;
+    type WriteOperation = Put;
+    type Response = Option<u64>;
+
+    fn dispatch(&self, key: Self::ReadOperation<'_>) -> Self::Response {
+        self.0.get(&key).copied()
+    }
+
+    fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+        self.0.insert(op.0, op.1)
+    }
+}
+
+/// The follower ends up with the same state as a leader whose threads write
+/// concurrently on two replicas.
+#[test]
+fn follow_unix_socket() {
+    let path = std::env::temp_dir().join(format!("nr-follow-{}.sock", std::process::id()));
+    let _ignore = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let follower = Arc::new(NodeReplicated::<Map>::builder().build().unwrap());
+    let ftkn = follower.register(0).unwrap();
+    let following = {
+        let follower = follower.clone();
+        thread::spawn(move || {
+            let (stream, _addr) = listener.accept().unwrap();
+            follower.follow(UnixTransport::from(stream), ftkn)
+        })
+    };
+
+    let leader = Arc::new(
+        NodeReplicated::<Map>::builder()
+            .replicas(2)
+            .transport(UnixTransport::connect(&path).unwrap())
+            .build()
+            .unwrap(),
+    );
+    let threads = 4;
+    let ops = 1000;
+    let barrier = Arc::new(Barrier::new(threads));
+    let writers: Vec<_> = (0..threads)
+        .map(|t| {
+            let leader = leader.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let ttkn = leader.register(t % 2).unwrap();
+                barrier.wait();
+                for i in 0..ops {
+                    // Threads overwrite each other's keys, so the order matters.
+                    leader.execute_mut(Put((i % 100) as u64, (t * ops + i) as u64), ttkn);
+                }
+            })
+        })
+        .collect();
+    for w in writers {
+        w.join().unwrap();
+    }
+
+    let leader = Arc::try_unwrap(leader).ok().unwrap();
+    let ltkn = leader.register(0).unwrap();
+    let expected: Vec<_> = (0..100).map(|k| leader.execute(k, ltkn)).collect();
+    drop(leader);
+    following.join().unwrap().unwrap();
+
+    let ttkn = follower.register(0).unwrap();
+    let mirrored: Vec<_> = (0..100).map(|k| follower.execute(k, ttkn)).collect();
+    assert_eq!(mirrored, expected);
+    std::fs::remove_file(&path).unwrap();
+}
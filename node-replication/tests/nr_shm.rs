@@ -0,0 +1,130 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Tests node-replication with one replica per process and the log in shared
+//! memory.
+//!
+//! The test re-executes its own binary to run the [`replica_process`] test in
+//! child processes, which only does something if the environment tells it
+//! which replica to attach as.
+#![cfg(all(feature = "std", target_os = "linux"))]
+#![feature(generic_associated_types)]
+
+use std::env;
+use std::ffi::CString;
+use std::process::{Child, Command, Stdio};
+
+use node_replication::nr::shm::{ShmLog, ShmReplica};
+use node_replication::nr::Dispatch;
+use node_replication::wait::{set_strategy, SpinThenYield};
+
+/// Tells a child process the name of the log.
+const LOG_VAR: &str = "NR_SHM_LOG";
+/// Tells a child process which replica to attach as.
+const REPLICA_VAR: &str = "NR_SHM_REPLICA";
+
+const REPLICAS: usize = 3;
+const OPS: u64 = 2000;
+
+static YIELD: SpinThenYield = SpinThenYield { spins: 1 << 10 };
+
+#[derive(Default)]
+struct Counter(u64);
+
+impl Dispatch for Counter {
+    type ReadOperation<'rop> = ();
+    type WriteOperation = u64;
+    type Response = u64;
+
+    fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+        self.0
+    }
+
+    fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+        self.0 += op;
+        self.0
+    }
+}
+
+/// Removes the shared memory object of the log when the test ends (also if
+/// it fails).
+struct Unlink(CString);
+
+impl Drop for Unlink {
+    fn drop(&mut self) {
+        let _r = ShmLog::<u64>::unlink(&self.0);
+    }
+}
+
+/// The sum of all operations of all replicas.
+fn total() -> u64 {
+    REPLICAS as u64 * OPS * (OPS + 1) / 2
+}
+
+/// Attaches as replica `rid` and adds 1..=OPS.
+fn run_replica(name: &CString, rid: usize) -> ShmReplica<Counter> {
+    let log = ShmLog::<u64>::open(name).unwrap();
+    let mut replica = ShmReplica::attach(log, rid, Counter::default()).unwrap();
+
+    let mut last = 0;
+    for i in 1..=OPS {
+        let resp = replica.execute_mut(i).unwrap();
+        assert!(resp >= last + i, "Responses must grow with every operation");
+        last = resp;
+    }
+    replica
+}
+
+/// Runs a replica when started by [`multi_process_counter`].
+#[test]
+fn replica_process() {
+    if let (Ok(name), Ok(rid)) = (env::var(LOG_VAR), env::var(REPLICA_VAR)) {
+        assert!(set_strategy(&YIELD).is_ok());
+        let mut replica = run_replica(&CString::new(name).unwrap(), rid.parse().unwrap());
+        // Keep the replica attached until it saw all operations.
+        while replica.execute(()).unwrap() != total() {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// All replicas, each in its own process, converge to the same state. The
+/// log is small, so it wraps around many times.
+#[test]
+fn multi_process_counter() {
+    assert!(set_strategy(&YIELD).is_ok());
+    let name = format!("/nr-shm-test-{}", std::process::id());
+    let cname = CString::new(name.clone()).unwrap();
+    let log = ShmLog::<u64>::create(&cname, 64, 8, REPLICAS).unwrap();
+    let _unlink = Unlink(cname.clone());
+
+    let mut children: Vec<Child> = (1..REPLICAS)
+        .map(|rid| {
+            Command::new(env::current_exe().unwrap())
+                .args(["--exact", "replica_process", "--test-threads", "1"])
+                .env(LOG_VAR, &name)
+                .env(REPLICA_VAR, rid.to_string())
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        })
+        .collect();
+
+    let mut replica = run_replica(&cname, 0);
+    while replica.execute(()).unwrap() != total() {
+        for child in children.iter_mut() {
+            if let Some(status) = child.try_wait().unwrap() {
+                assert!(status.success(), "Replica process failed");
+            }
+        }
+        std::thread::yield_now();
+    }
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    assert_eq!(log.tail(), REPLICAS * OPS as usize);
+    for rid in 0..REPLICAS {
+        assert_eq!(log.local_tail(rid), Some(REPLICAS * OPS as usize));
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright © 2019-2022 VMware, Inc. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+//! Tests node-replication with more threads than cores and waiting threads
+//! that park instead of spinning.
+#![cfg(feature = "std")]
+#![feature(generic_associated_types)]
+
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use node_replication::nr::{Dispatch, NodeReplicated};
+use node_replication::wait::{set_strategy, Park};
+
+static PARK: Park = Park::new(1 << 8, Duration::from_millis(1));
+
+#[derive(Default)]
+struct Counter(u64);
+
+impl Dispatch for Counter {
+    type ReadOperation<'rop> = ();
+    type WriteOperation = u64;
+    type Response = u64;
+
+    fn dispatch(&self, _op: Self::ReadOperation<'_>) -> Self::Response {
+        self.0
+    }
+
+    fn dispatch_mut(&mut self, op: Self::WriteOperation) -> Self::Response {
+        self.0 += op;
+        self.0
+    }
+}
+
+/// All operations of oversubscribed threads complete while waiting threads
+/// park.
+#[test]
+fn parked_oversubscribed() {
+    assert!(set_strategy(&PARK).is_ok());
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = 4 * cores;
+    let ops = 1000;
+
+    let replicas = NonZeroUsize::new(2).unwrap();
+    let nr = Arc::new(NodeReplicated::<Counter>::new(replicas, |_ac| 0).unwrap());
+    let barrier = Arc::new(Barrier::new(threads));
+
+    let mut handles = Vec::with_capacity(threads);
+    for t in 0..threads {
+        let nr = nr.clone();
+        let barrier = barrier.clone();
+        handles.push(thread::spawn(move || {
+            let ttkn = nr.register(t % replicas.get()).unwrap();
+            barrier.wait();
+            for _i in 0..ops {
+                nr.execute_mut(1, ttkn);
+                nr.execute((), ttkn);
+            }
+        }));
+    }
+
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    let ttkn = nr.register(0).unwrap();
+    assert_eq!(nr.execute((), ttkn), (threads * ops) as u64);
+}